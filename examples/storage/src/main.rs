@@ -20,6 +20,20 @@ struct MyConfig {
     val_two: u64,
 }
 
+/// Example versioned object.
+///
+/// Its stored representation is tagged with its schema identifier and version.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct MyVersionedConfig {
+    interval: u32,
+}
+
+impl storage::Versioned for MyVersionedConfig {
+    const SCHEMA_ID: u32 = 0x4d43;
+    const SCHEMA_VERSION: u16 = 1;
+}
+
 #[ariel_os::task(autostart)]
 async fn main() {
     info!("Start storage example");
@@ -92,6 +106,29 @@ async fn main() {
         info!("got cfg object: {:?}", cfg);
     }
 
+    // Storing a versioned object
+    let versioned_cfg = MyVersionedConfig { interval: 5 };
+    info!("Storing versioned cfg object {:?}", versioned_cfg);
+    storage::insert_versioned("my_versioned_config", versioned_cfg)
+        .await
+        .unwrap();
+
+    let versioned_cfg: Option<MyVersionedConfig> =
+        storage::get_versioned("my_versioned_config").await.unwrap();
+    if let Some(cfg) = versioned_cfg {
+        info!("got versioned cfg object: {:?}", cfg);
+    }
+
+    // Getting a versioned object as another type is detected.
+    if let Err(storage::Error::SchemaMismatch { expected, found }) =
+        storage::get::<MyConfig>("my_versioned_config").await
+    {
+        info!(
+            "Attempting to retrieve versioned cfg as untagged struct: schema mismatch (expected id {}, found id {})",
+            expected.id, found.id
+        );
+    }
+
     // Getting a value as raw bytes probably does not return what you want due
     // to the way postcard works
     let cfg_array: Option<arrayvec::ArrayVec<u8, 256>> = storage::get("my_config").await.unwrap();
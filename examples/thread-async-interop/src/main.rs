@@ -1,17 +1,16 @@
 #![no_main]
 #![no_std]
 
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-
 use ariel_os::{
     asynch::spawner,
     debug::{ExitCode, exit},
     log::*,
-    thread::block_on,
+    thread::sync::Queue,
     time::{Instant, Timer},
 };
 
-static SIGNAL: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+// This queue can be used from both threads and tasks.
+static QUEUE: Queue<u32, 1> = Queue::new();
 
 // This is a regular task.
 // For this example, we don't autostart it, but let the thread spawn it.
@@ -21,8 +20,8 @@ async fn async_task() {
 
     let mut counter = 0u32;
     loop {
-        info!("async_task(): signaling, counter={}", counter);
-        SIGNAL.signal(counter);
+        info!("async_task(): sending, counter={}", counter);
+        QUEUE.send_async(counter).await;
         Timer::after_millis(100).await;
         counter += 1;
     }
@@ -36,9 +35,8 @@ fn main() {
     spawner().spawn(async_task()).unwrap();
 
     for _ in 0..10 {
-        // With `block_on()`, async functions can be called from a thread.
-        // This way, async primitives like `Signal` can be used.
-        let counter = block_on(SIGNAL.wait());
+        // The thread blocks until the task has sent a value.
+        let counter = QUEUE.recv();
 
         // Get time since boot
         let now = Instant::now().as_millis();
@@ -0,0 +1,305 @@
+//! Block-wise transfers ([RFC 7959]) on the client side.
+//!
+//! [`download()`] retrieves a representation that does not fit into a single message block by
+//! block (using the Block2 option), and [`upload()`] sends a payload that does not fit into a
+//! single message in blocks (using the Block1 option). Their requests are sent through
+//! [`coap_client()`](crate::coap_client). With the `coap-client-oscore` feature,
+//! [`OscoreClient`](crate::OscoreClient) offers the same operations, protecting every block with
+//! OSCORE.
+//!
+//! On the server side, block-wise transfers are served for all handlers passed to
+//! [`coap_run()`](crate::coap_run), up to the size configured in
+//! `CONFIG_COAP_BLOCKWISE_BUFFER_SIZE`.
+//!
+//! [RFC 7959]: https://www.rfc-editor.org/rfc/rfc7959
+
+use core::net::SocketAddr;
+
+use coap_message::{MessageOption as _, MinimalWritableMessage as _, ReadableMessage as _};
+use coap_message_implementations::{inmemory, inmemory_write};
+use coap_numbers::option::{BLOCK1, BLOCK2};
+use coapcore::BlockOption;
+
+use crate::exchange::{MESSAGE_BUFFER_SIZE, exchange};
+
+/// Block size exponent used for requests (for blocks of 512 bytes).
+///
+/// Blocks of that size and the options of a typical request fit into a single message, even when
+/// protected with OSCORE. Servers may ask for smaller blocks.
+const SZX: u8 = 5;
+
+/// Error type returned by [`download()`] and [`upload()`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BlockwiseError {
+    /// A request could not be sent, or no response was received.
+    Transport,
+    /// The closure building the request returned an error, or the request does not fit into a
+    /// message.
+    Build,
+    /// The server's response does not continue the transfer.
+    UnexpectedBlock,
+}
+
+/// Retrieves a representation from `server` block by block.
+///
+/// `build` writes the request's code, options and payload (the Block2 option is added for each
+/// block). `process` is called for each response, with the position of its payload in the
+/// representation. If the server does not respond with blocks (eg. because the representation
+/// fits into a single message, or on errors), `process` is called once with the complete
+/// response.
+///
+/// # Errors
+///
+/// This produces errors if the request could not be built, if any request could not be sent, or
+/// if the server's responses do not continue the transfer.
+///
+/// # Panics
+///
+/// This panics when called from a thread other than the one hosting the network stack, see
+/// [`coap_client()`](crate::coap_client).
+pub async fn download<E>(
+    server: SocketAddr,
+    build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+    process: impl FnMut(usize, &inmemory::Message<'_>),
+) -> Result<(), BlockwiseError> {
+    let mut base_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+    let (code, length) = build_base(build, &mut base_buffer)?;
+    let (base, _) = base_buffer.split_at(length);
+
+    run_download(
+        async |request, response_buffer| {
+            exchange(server, request, response_buffer)
+                .await
+                .map_err(|()| BlockwiseError::Transport)
+        },
+        &inmemory::Message::new(code, base),
+        process,
+    )
+    .await
+}
+
+/// Sends `payload` to `server` in blocks.
+///
+/// `build` writes the request's code and options (the Block1 option and the payload are added for
+/// each block). If the payload fits into a single block, it is sent without a Block1 option.
+/// `process` is called with the response to the final block, or with the first response that
+/// does not ask for further blocks (eg. an error).
+///
+/// # Errors
+///
+/// This produces errors if the request could not be built, if any request could not be sent, or
+/// if the server's responses do not continue the transfer.
+///
+/// # Panics
+///
+/// This panics when called from a thread other than the one hosting the network stack, see
+/// [`coap_client()`](crate::coap_client).
+pub async fn upload<E, R>(
+    server: SocketAddr,
+    build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+    payload: &[u8],
+    process: impl FnOnce(&inmemory::Message<'_>) -> R,
+) -> Result<R, BlockwiseError> {
+    let mut base_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+    let (code, length) = build_base(build, &mut base_buffer)?;
+    let (base, _) = base_buffer.split_at(length);
+
+    run_upload(
+        async |request, response_buffer| {
+            exchange(server, request, response_buffer)
+                .await
+                .map_err(|()| BlockwiseError::Transport)
+        },
+        &inmemory::Message::new(code, base),
+        payload,
+        process,
+    )
+    .await
+}
+
+/// Writes the request built by `build` into `buffer`.
+///
+/// Returns the request code and the length of its options and payload.
+///
+/// # Errors
+///
+/// This produces an error if `build` failed.
+pub(crate) fn build_base<E>(
+    build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+    buffer: &mut [u8],
+) -> Result<(u8, usize), BlockwiseError> {
+    let mut code = 0;
+    let mut message = inmemory_write::Message::new(&mut code, buffer);
+    build(&mut message).map_err(|_| BlockwiseError::Build)?;
+    let length = message.finish();
+    Ok((code, length))
+}
+
+/// Runs a Block2 transfer of the request `base`, sending each block through `send`.
+///
+/// See [`download()`] for details.
+///
+/// # Errors
+///
+/// This produces errors if `send` fails, if a request does not fit into a message, or if the
+/// server's responses do not continue the transfer.
+pub(crate) async fn run_download<X: From<BlockwiseError>>(
+    mut send: impl AsyncFnMut(&inmemory::Message<'_>, &mut [u8]) -> Result<(u8, usize), X>,
+    base: &inmemory::Message<'_>,
+    mut process: impl FnMut(usize, &inmemory::Message<'_>),
+) -> Result<(), X> {
+    let mut request_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+    let mut response_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+
+    let mut block = BlockOption {
+        num: 0,
+        more: false,
+        szx: SZX,
+    };
+    let mut offset = 0;
+    loop {
+        let (code, length) = copy_request(
+            base,
+            Some((BLOCK2, block)),
+            base.payload(),
+            &mut request_buffer,
+        )?;
+        let (request, _) = request_buffer.split_at(length);
+        let request = inmemory::Message::new(code, request);
+
+        let (code, length) = send(&request, &mut response_buffer).await?;
+        let (response, _) = response_buffer.split_at(length);
+        let response = inmemory::Message::new(code, response);
+
+        let Some(received) = response.options().find(|o| o.number() == BLOCK2) else {
+            // A complete response, or an error ending the transfer.
+            if offset != 0 && code >> 5 == 2 {
+                return Err(BlockwiseError::UnexpectedBlock.into());
+            }
+            process(offset, &response);
+            return Ok(());
+        };
+        let received = BlockOption::parse(received.value())
+            .filter(|received| received.offset() == offset && received.szx <= block.szx)
+            .ok_or(BlockwiseError::UnexpectedBlock)?;
+        if received.more && response.payload().len() != received.size() {
+            return Err(BlockwiseError::UnexpectedBlock.into());
+        }
+
+        process(offset, &response);
+        if !received.more {
+            return Ok(());
+        }
+        offset += received.size();
+        // The server may have picked a smaller block size, which is kept from here on.
+        block = BlockOption {
+            num: received.num + 1,
+            more: false,
+            szx: received.szx,
+        };
+    }
+}
+
+/// Runs a Block1 transfer of `payload` with the request `base`, sending each block through
+/// `send`.
+///
+/// See [`upload()`] for details.
+///
+/// # Errors
+///
+/// This produces errors if `send` fails, if a request does not fit into a message, or if the
+/// server's responses do not continue the transfer.
+pub(crate) async fn run_upload<X: From<BlockwiseError>, R>(
+    mut send: impl AsyncFnMut(&inmemory::Message<'_>, &mut [u8]) -> Result<(u8, usize), X>,
+    base: &inmemory::Message<'_>,
+    payload: &[u8],
+    process: impl FnOnce(&inmemory::Message<'_>) -> R,
+) -> Result<R, X> {
+    let mut request_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+    let mut response_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+
+    let mut szx = SZX;
+    let mut offset = 0;
+    loop {
+        let size = BlockOption {
+            num: 0,
+            more: false,
+            szx,
+        }
+        .size();
+        let (_, rest) = payload.split_at(offset);
+        let (data, rest) = rest.split_at(size.min(rest.len()));
+        let block = BlockOption {
+            num: u32::try_from(offset / size).map_err(|_| BlockwiseError::Build)?,
+            more: !rest.is_empty(),
+            szx,
+        };
+
+        // A payload that fits into a single block is sent as a regular request.
+        let option = (offset != 0 || block.more).then_some((BLOCK1, block));
+        let (code, length) = copy_request(base, option, data, &mut request_buffer)?;
+        let (request, _) = request_buffer.split_at(length);
+        let request = inmemory::Message::new(code, request);
+
+        let (code, length) = send(&request, &mut response_buffer).await?;
+        let (response, _) = response_buffer.split_at(length);
+        let response = inmemory::Message::new(code, response);
+
+        if !block.more || code != coap_numbers::code::CONTINUE {
+            return Ok(process(&response));
+        }
+
+        let received = response
+            .options()
+            .find(|o| o.number() == BLOCK1)
+            .and_then(|o| BlockOption::parse(o.value()))
+            .filter(|received| received.num == block.num && received.szx <= szx)
+            .ok_or(BlockwiseError::UnexpectedBlock)?;
+        offset += data.len();
+        // The server may ask for smaller blocks from here on.
+        szx = received.szx;
+    }
+}
+
+/// Copies the request `base` into `buffer`, adding the block option (given as option number and
+/// value) if any, and replacing the payload with `payload`.
+///
+/// Returns the request code and the length of the written options and payload.
+///
+/// # Errors
+///
+/// This produces an error if the request does not fit into the buffer.
+fn copy_request(
+    base: &inmemory::Message<'_>,
+    block: Option<(u16, BlockOption)>,
+    payload: &[u8],
+    buffer: &mut [u8],
+) -> Result<(u8, usize), BlockwiseError> {
+    let mut code = 0;
+    let mut request = inmemory_write::Message::new(&mut code, buffer);
+    request.set_code(base.code());
+
+    let mut block = block.map(|(number, block)| (number, block.encode()));
+    for option in base.options() {
+        if let Some((number, value)) = block.take_if(|(number, _)| *number < option.number()) {
+            request
+                .add_option(number, &value)
+                .map_err(|_| BlockwiseError::Build)?;
+        }
+        request
+            .add_option(option.number(), option.value())
+            .map_err(|_| BlockwiseError::Build)?;
+    }
+    if let Some((number, value)) = block {
+        request
+            .add_option(number, &value)
+            .map_err(|_| BlockwiseError::Build)?;
+    }
+    request
+        .set_payload(payload)
+        .map_err(|_| BlockwiseError::Build)?;
+
+    let length = request.finish();
+    Ok((code, length))
+}
@@ -0,0 +1,65 @@
+//! Sending prepared requests through the system's [`coap_client()`](crate::coap_client).
+
+use core::net::SocketAddr;
+
+use coap_message::MinimalWritableMessage as _;
+use coap_message_implementations::{inmemory, inmemory_write};
+use coap_request::Stack as _;
+
+/// Size of the buffers into which messages are built and responses are copied.
+///
+/// embedded-nal-coap uses this max size, so any message sent or received through it fits.
+pub(crate) const MESSAGE_BUFFER_SIZE: usize = 1152;
+
+/// Sends `request` to `server`, and copies the response into `response_buffer`.
+///
+/// Returns the response code and the length of the response's options and payload.
+///
+/// # Errors
+///
+/// This produces an error if the request could not be sent, or no response was received.
+pub(crate) async fn exchange(
+    server: SocketAddr,
+    request: &inmemory::Message<'_>,
+    response_buffer: &mut [u8],
+) -> Result<(u8, usize), ()> {
+    let mut response_code = 0;
+    let mut response = inmemory_write::Message::new(&mut response_code, response_buffer);
+    crate::coap_client()
+        .await
+        .to(server)
+        .request(Exchange {
+            request,
+            response: &mut response,
+        })
+        .await
+        .map_err(|_| ())??;
+    let length = response.finish();
+    Ok((response_code, length))
+}
+
+/// A [`coap_request::Request`] that sends a prepared message, and copies out the response.
+struct Exchange<'a, 'b> {
+    request: &'a inmemory::Message<'b>,
+    response: &'a mut inmemory_write::Message<'b>,
+}
+
+impl<S: coap_request::Stack + ?Sized> coap_request::Request<S> for Exchange<'_, '_> {
+    type Carry = ();
+    type Output = Result<(), ()>;
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<Self::Carry, S::RequestUnionError> {
+        request.set_from_message(self.request)
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        (): Self::Carry,
+    ) -> Self::Output {
+        self.response.set_from_message(response).map_err(|_| ())
+    }
+}
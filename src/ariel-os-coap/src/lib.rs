@@ -2,9 +2,9 @@
 //!
 //! This crate mainly provides easy-to-use wrappers around the [`coapcore`] crate, with presets
 //! tailored towards Ariel OS: It utilizes [`embassy_net`] to open a network accessible CoAP socket
-//! and selects [`embedded_nal_coap`] for CoAP over UDP, it selects [`ariel_os_random`] as a source
-//! of randomness, and [`lakers_crypto_rustcrypto`] for the cryptographic algorithm
-//! implementations.
+//! and selects [`embedded_nal_coap`] for CoAP over UDP (CoAP over TCP is served by this crate),
+//! it selects [`ariel_os_random`] as a source of randomness, and [`lakers_crypto_rustcrypto`] for
+//! the cryptographic algorithm implementations.
 #![no_std]
 #![deny(missing_docs)]
 
@@ -18,13 +18,62 @@ mod stored;
 #[cfg(feature = "coap-transport-udp")]
 mod transport_udp;
 
+#[cfg(feature = "coap-transport-tcp")]
+mod transport_tcp;
+
+#[cfg(feature = "coap-server-blockwise")]
+mod requester;
+
+pub mod observe;
+
+#[cfg(any(feature = "coap-client-oscore", feature = "coap-client-blockwise"))]
+mod exchange;
+
+#[cfg(feature = "coap-client-blockwise")]
+pub mod blockwise;
+
+#[cfg(any(
+    feature = "coap-system-devinfo",
+    feature = "coap-system-sensors",
+    feature = "coap-system-threads"
+))]
+mod system;
+
+#[cfg(feature = "coap-client-oscore")]
+mod oscore_client;
+#[cfg(feature = "coap-client-oscore")]
+pub use oscore_client::{OscoreClient, OscoreClientError};
+
+#[cfg(feature = "coap-transport-udp")]
 use ariel_os_embassy::cell::SameExecutorCell;
 #[cfg(feature = "coap-server")]
 use coap_handler_implementations::ReportingHandlerBuilder as _;
+#[cfg(feature = "coap-transport-udp")]
 use embassy_sync::watch::Watch;
 
+// The client is provided by embedded-nal-coap on the UDP socket; CoAP-over-TCP has no client side
+// (and would need one per connection).
+#[cfg(all(
+    any(feature = "coap-client-oscore", feature = "coap-client-blockwise"),
+    not(feature = "coap-transport-udp")
+))]
+compile_error!(
+    "The CoAP client features (coap-client-oscore, coap-client-blockwise) require the coap-transport-udp transport."
+);
+
+#[cfg(feature = "coap-transport-udp")]
 const CONCURRENT_REQUESTS: usize = 3;
 
+/// Size of the largest request and response (options and payload) that block-wise transfers to
+/// and from the server are assembled into.
+#[cfg(feature = "coap-server-blockwise")]
+const BLOCKWISE_BUFFER_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_BLOCKWISE_BUFFER_SIZE",
+    2048,
+    "size of the buffers for CoAP block-wise transfers served by the CoAP server"
+);
+
+#[cfg(feature = "coap-transport-udp")]
 static CLIENT_READY: Watch<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
     SameExecutorCell<&'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS>>,
@@ -97,7 +146,10 @@ mod demo_setup {
 /// loop to run) get stalled.
 ///
 /// As the CoAP stack gets ready (which may take some time if the network is not ready yet), it also
-/// unblocks [`coap_client()`].
+/// unblocks [`coap_client()`] (which is only available on CoAP-over-UDP).
+///
+/// The system resources enabled through the `coap-system-*` features are served next to the
+/// handler's resources; requests to their paths do not reach the handler.
 ///
 /// # Panics
 ///
@@ -112,8 +164,8 @@ pub async fn coap_run(handler: impl coap_handler::Handler + coap_handler::Report
 /// This is a separate function because if that function is not exposed publicly (i.e. when the
 /// laze feature `coap-server` is not active), it is called automatically in a separate task.
 ///
-/// It sets up the security configuration, and ultimately runs the CoAP transport (currently only
-/// CoAP-over-UDP) forever.
+/// It sets up the security configuration, and ultimately runs the CoAP transport (CoAP-over-UDP or
+/// CoAP-over-TCP) forever.
 ///
 /// # Panics
 ///
@@ -144,6 +196,20 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
     // be limiting in special applications.
     #[cfg(feature = "coap-server")]
     let handler = handler.with_wkc();
+    // Wrapped like the application's resources, so that access to the system resources is
+    // authorized through the same scopes.
+    #[cfg(any(
+        feature = "coap-system-devinfo",
+        feature = "coap-system-sensors",
+        feature = "coap-system-threads"
+    ))]
+    let handler = system::SystemHandler::new(handler);
+    // Wrapped by the OscoreEdhocHandler, so that every block is protected on its own.
+    #[cfg(feature = "coap-server-blockwise")]
+    let handler = coapcore::BlockwiseHandler::<_, BLOCKWISE_BUFFER_SIZE, _>::with_requester(
+        handler,
+        requester::current,
+    );
     #[cfg(any(
         feature = "coap-server-config-storage",
         feature = "coap-server-config-demokeys"
@@ -155,11 +221,20 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
         ariel_os_random::crypto_rng(),
         coapcore::time::TimeUnknown,
     );
+    // Without OSCORE, notifications are built by evaluating the registering request again.
+    #[cfg(not(any(
+        feature = "coap-server-config-storage",
+        feature = "coap-server-config-demokeys"
+    )))]
+    let handler = observe::Unprotected(handler);
 
     cfg_select! {
         feature = "coap-transport-udp" => {
             transport_udp::coap_run_udp(handler).await
         }
+        feature = "coap-transport-tcp" => {
+            transport_tcp::coap_run_tcp(handler).await
+        }
         feature = "doc" => {
             loop {}
         }
@@ -171,14 +246,21 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
 
 /// Returns a CoAP client requester.
 ///
+/// Requests sent through it are not protected; see [`OscoreClient`] (with the
+/// `coap-client-oscore` feature) for requests to OSCORE protected servers.
+///
 /// This asynchronously blocks until [`coap_run()`] has been called (which happens at startup
 /// when the corresponding feature `coap-server` is not active), and the CoAP stack is operational.
 ///
+/// The client is only provided by the CoAP-over-UDP transport, so this is not available with
+/// CoAP-over-TCP.
+///
 /// # Panics
 ///
 /// This is currently only available from the thread that hosts the network stack, and panics
 /// otherwise. This restriction will be lifted in the future (by generalization in
 /// [`embedded_nal_coap`] to allow different mutexes).
+#[cfg(feature = "coap-transport-udp")]
 pub async fn coap_client()
 -> &'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS> {
     let mut receiver = CLIENT_READY
@@ -196,13 +278,14 @@ pub async fn coap_client()
 ///
 /// * It provides the backend for the CoAP client operation (which leaves message sending to that
 ///   task).
-/// * It runs any CoAP server components provided by the OS (none yet).
+/// * It runs the CoAP server components provided by the OS (the system resources enabled through
+///   the `coap-system-*` features).
 #[cfg(not(feature = "coap-server"))]
 #[ariel_os_macros::task(autostart)]
 async fn coap_run() {
     use coap_handler_implementations::new_dispatcher;
 
-    // FIXME: Provide an "all system components" constructor in this crate.
+    // System resources are added by `coap_run_impl()`; no application resources are served.
     let handler = new_dispatcher();
     coap_run_impl(handler).await;
 }
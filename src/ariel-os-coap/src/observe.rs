@@ -0,0 +1,571 @@
+//! Server side support for observing resources ([RFC 7641]).
+//!
+//! A client registers as an observer of a resource by sending a GET (or FETCH) request with the
+//! Observe option set to 0. Whenever the application calls [`notify()`] with the resource's path
+//! (or [`notify_all()`]), the server evaluates the request again through the handler passed to
+//! [`coap_run()`](crate::coap_run), and sends the response to the observer as a notification. The
+//! observation ends when the client deregisters (by sending the request with the Observe option
+//! set to 1), or when a notification is an error response.
+//!
+//! On CoAP-over-TCP, observations also end when the connection is lost. On CoAP-over-UDP,
+//! notifications are sent as confirmable messages; the observation ends when the client rejects
+//! one with a Reset message, or does not acknowledge it after the retransmissions of [RFC 7252
+//! Section 4.2].
+//!
+//! Observations can be registered through OSCORE protected requests. For those, the decrypted
+//! request is evaluated again (after checking again that the security context's authorization
+//! allows it), and every notification is protected with a Partial IV of its own.
+//!
+//! [RFC 7252 Section 4.2]: https://www.rfc-editor.org/rfc/rfc7252#section-4.2
+//!
+//! [RFC 7641]: https://www.rfc-editor.org/rfc/rfc7641
+
+use core::cell::RefCell;
+
+use ariel_os_log::warn;
+use coap_message::{
+    MessageOption as _, MinimalWritableMessage, MutableWritableMessage, ReadableMessage,
+    error::RenderableOnMinimal as _,
+};
+use coap_message_implementations::{inmemory, inmemory_write};
+use embassy_sync::signal::Signal;
+use embassy_sync::{blocking_mutex::Mutex, blocking_mutex::raw::CriticalSectionRawMutex};
+
+/// Maximum number of observations per connection (on CoAP-over-TCP) or in total (on
+/// CoAP-over-UDP).
+const MAX_OBSERVATIONS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_MAX_OBSERVATIONS",
+    4,
+    "maximum number of CoAP observations per connection (TCP) or in total (UDP)"
+);
+
+/// Maximum size of the options and payload of a request that can be registered as an
+/// observation.
+pub(crate) const MAX_REQUEST_LEN: usize = 128;
+
+/// Largest token length.
+pub(crate) const MAX_TOKEN_LEN: usize = 8;
+
+/// Maximum number of distinct paths that can be pending notification; beyond that, all
+/// observations are notified.
+const MAX_PENDING_PATHS: usize = 4;
+
+/// Maximum length of a path passed to [`notify()`] that is tracked individually.
+const MAX_PATH_LEN: usize = 32;
+
+/// Observe option values are 24 bits long (RFC 7641 Section 4.4).
+const SEQUENCE_MASK: u32 = 0xff_ffff;
+
+/// Paths whose observers are pending notification.
+pub(crate) struct Pending {
+    all: bool,
+    paths: heapless::Vec<heapless::String<MAX_PATH_LEN>, MAX_PENDING_PATHS>,
+}
+
+impl Pending {
+    const fn new() -> Self {
+        Self {
+            all: false,
+            paths: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns true if an observation of `request` is pending notification.
+    fn matches(&self, request: &impl ReadableMessage) -> bool {
+        self.all
+            || self.paths.iter().any(|path| {
+                let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+                let mut uri_path = request
+                    .options()
+                    .filter(|o| o.number() == coap_numbers::option::URI_PATH);
+                loop {
+                    match (segments.next(), uri_path.next()) {
+                        (None, None) => return true,
+                        (Some(segment), Some(option)) if segment.as_bytes() == option.value() => {}
+                        _ => return false,
+                    }
+                }
+            })
+    }
+}
+
+static PENDING: Mutex<CriticalSectionRawMutex, RefCell<Pending>> =
+    Mutex::new(RefCell::new(Pending::new()));
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Notifies the observers of the resource at `path` (e.g., `"/s/0"`) that it has changed.
+///
+/// Notifications are sent asynchronously; when the resource changes several times before they
+/// are sent, its observers are only notified of the latest state.
+///
+/// This can be called from any thread or task.
+pub fn notify(path: &str) {
+    PENDING.lock(|pending| {
+        let mut pending = pending.borrow_mut();
+        if pending.all
+            || pending
+                .paths
+                .iter()
+                .any(|pending_path| pending_path == path)
+        {
+            return;
+        }
+        let added = heapless::String::try_from(path)
+            .ok()
+            .is_some_and(|path| pending.paths.push(path).is_ok());
+        if !added {
+            pending.all = true;
+        }
+    });
+    CHANGED.signal(());
+}
+
+/// Notifies the observers of all resources that they have changed.
+///
+/// See [`notify()`].
+pub fn notify_all() {
+    PENDING.lock(|pending| pending.borrow_mut().all = true);
+    CHANGED.signal(());
+}
+
+/// Waits until [`notify()`] or [`notify_all()`] has been called, and returns which observers
+/// need to be notified.
+pub(crate) async fn wait_pending() -> Pending {
+    CHANGED.wait().await;
+    PENDING.lock(|pending| core::mem::replace(&mut *pending.borrow_mut(), Pending::new()))
+}
+
+/// A handler through which observations can be registered, and which builds their notifications.
+///
+/// For unprotected requests, this is merely a [`coap_handler::Handler`]: their notifications are
+/// built by evaluating the request again. Observations registered through OSCORE protected
+/// requests are kept by the transport in the form the handler hands out for them.
+pub(crate) trait ObservableHandler: coap_handler::Handler {
+    /// State of an observation registered through an OSCORE protected request.
+    type Protected;
+
+    /// Takes the observation registered through an OSCORE protected request by the response built
+    /// last, if any.
+    fn take_protected(&mut self) -> Option<Self::Protected>;
+
+    /// Returns the decrypted request of an OSCORE protected observation.
+    fn protected_request(protected: &Self::Protected) -> impl ReadableMessage + '_;
+
+    /// Builds a notification of an OSCORE protected observation into `buffer`.
+    ///
+    /// Returns the code and length of the notification's options and payload, and whether the
+    /// decrypted response is successful. Returns `None` if the observation ended without a
+    /// notification to send.
+    fn build_protected_notification(
+        &mut self,
+        protected: &mut Self::Protected,
+        buffer: &mut [u8],
+    ) -> Option<(u8, usize, bool)>;
+}
+
+#[cfg(any(
+    feature = "coap-server-config-storage",
+    feature = "coap-server-config-demokeys"
+))]
+impl<H, Crypto, CryptoFactory, SSC, RNG, TP> ObservableHandler
+    for coapcore::OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP>
+where
+    H: coap_handler::Handler,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: coapcore::seccfg::ServerSecurityConfig,
+    RNG: rand_core::RngCore + rand_core::CryptoRng,
+    TP: coapcore::time::TimeProvider,
+{
+    type Protected = coapcore::OscoreObservation;
+
+    fn take_protected(&mut self) -> Option<Self::Protected> {
+        self.take_observation()
+    }
+
+    fn protected_request(protected: &Self::Protected) -> impl ReadableMessage + '_ {
+        protected.request()
+    }
+
+    fn build_protected_notification(
+        &mut self,
+        protected: &mut Self::Protected,
+        buffer: &mut [u8],
+    ) -> Option<(u8, usize, bool)> {
+        let mut code = 0;
+        let mut message = inmemory_write::Message::new(&mut code, buffer);
+        let succeeded = self.build_notification(protected, &mut message)?;
+        let length = message.finish();
+        Some((code, length, succeeded))
+    }
+}
+
+/// A handler of a server without OSCORE, through which only unprotected observations are
+/// registered.
+pub(crate) struct Unprotected<H>(pub(crate) H);
+
+impl<H: coap_handler::Handler> coap_handler::Handler for Unprotected<H> {
+    type RequestData = H::RequestData;
+    type ExtractRequestError = H::ExtractRequestError;
+    type BuildResponseError<M: MinimalWritableMessage> = H::BuildResponseError<M>;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        self.0.extract_request_data(request)
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        self.0.estimate_length(request)
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        self.0.build_response(response, request)
+    }
+}
+
+impl<H: coap_handler::Handler> ObservableHandler for Unprotected<H> {
+    type Protected = core::convert::Infallible;
+
+    fn take_protected(&mut self) -> Option<Self::Protected> {
+        None
+    }
+
+    fn protected_request(protected: &Self::Protected) -> impl ReadableMessage + '_ {
+        let request: inmemory::Message<'_> = match *protected {};
+        request
+    }
+
+    fn build_protected_notification(
+        &mut self,
+        protected: &mut Self::Protected,
+        _buffer: &mut [u8],
+    ) -> Option<(u8, usize, bool)> {
+        match *protected {}
+    }
+}
+
+/// The request through which an observation was registered.
+enum ObservedRequest<P> {
+    /// Code, and options and payload of an unprotected request.
+    Plain(u8, heapless::Vec<u8, MAX_REQUEST_LEN>),
+    /// State of an observation registered through an OSCORE protected request, which holds the
+    /// decrypted request.
+    Protected(P),
+}
+
+/// A registered observation.
+///
+/// `A` identifies the peer that registered it (on transports that serve several), and `P` is the
+/// handler's [`ObservableHandler::Protected`] type.
+pub(crate) struct Observation<A, P> {
+    pub(crate) peer: A,
+    pub(crate) token: heapless::Vec<u8, MAX_TOKEN_LEN>,
+    request: ObservedRequest<P>,
+    sequence: u32,
+    /// Confirmable notification that was not acknowledged yet.
+    #[cfg(feature = "coap-transport-udp")]
+    pub(crate) unacknowledged: Option<crate::transport_udp::Unacknowledged>,
+}
+
+impl<A, P> Observation<A, P> {
+    /// Advances and returns the sequence number to be sent in the next notification.
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence = (self.sequence + 1) & SEQUENCE_MASK;
+        self.sequence
+    }
+}
+
+/// The observations registered on a connection (on CoAP-over-TCP) or on the server (on
+/// CoAP-over-UDP).
+pub(crate) struct Observations<A, P> {
+    entries: heapless::Vec<Observation<A, P>, MAX_OBSERVATIONS>,
+}
+
+impl<A: PartialEq, P> Observations<A, P> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Processes the Observe option of a request from `peer` (given by its `code`, and its options
+    /// and payload in `body`), after the handler built a response with code `response_code`.
+    ///
+    /// `protected` is what the handler [handed out][ObservableHandler::take_protected] after
+    /// building the response; OSCORE protected requests are only registered with it.
+    ///
+    /// Returns the sequence number to send in the response's Observe option if the request was
+    /// registered as an observation.
+    pub(crate) fn process_request(
+        &mut self,
+        peer: A,
+        token: &[u8],
+        code: u8,
+        body: &[u8],
+        response_code: u8,
+        protected: Option<P>,
+    ) -> Option<u32> {
+        let request = inmemory::Message::new(code, body);
+        let mut observe = None;
+        let mut oscore = false;
+        for option in request.options() {
+            match option.number() {
+                coap_numbers::option::OBSERVE => observe = Some(option.value()),
+                coap_numbers::option::OSCORE => oscore = true,
+                _ => (),
+            }
+        }
+
+        let observable = code == coap_numbers::code::GET || code == coap_numbers::code::FETCH;
+
+        match observe? {
+            // Register
+            b"" if observable => {
+                self.cancel(&peer, token);
+                let request = if oscore {
+                    // The handler only hands this out after it checked the decrypted request, and
+                    // built a successful response (whose code is not visible from the outside).
+                    ObservedRequest::Protected(protected?)
+                } else {
+                    if !is_success(response_code) {
+                        return None;
+                    }
+                    ObservedRequest::Plain(code, heapless::Vec::from_slice(body).ok()?)
+                };
+                let observation = Observation {
+                    peer,
+                    token: heapless::Vec::from_slice(token).ok()?,
+                    request,
+                    sequence: 0,
+                    #[cfg(feature = "coap-transport-udp")]
+                    unacknowledged: None,
+                };
+                // When out of slots, the response is sent without Observe option, which tells
+                // the client that the resource is not observed.
+                self.entries.push(observation).ok()?;
+                Some(0)
+            }
+            // Deregister
+            [1] => {
+                self.cancel(&peer, token);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the observation registered by `peer` with `token`, if any.
+    pub(crate) fn cancel(&mut self, peer: &A, token: &[u8]) {
+        self.entries
+            .retain(|entry| entry.peer != *peer || entry.token != token);
+    }
+
+    /// Returns the index of the first observation at or after `start` that is pending
+    /// notification.
+    pub(crate) fn next_pending<H: ObservableHandler<Protected = P>>(
+        &self,
+        pending: &Pending,
+        start: usize,
+    ) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, entry)| match &entry.request {
+                ObservedRequest::Plain(code, request) => {
+                    pending.matches(&inmemory::Message::new(*code, request))
+                }
+                ObservedRequest::Protected(protected) => {
+                    pending.matches(&H::protected_request(protected))
+                }
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the index of the first observation at or after `start` for which `f` returns true.
+    #[cfg(feature = "coap-transport-udp")]
+    pub(crate) fn position(
+        &self,
+        start: usize,
+        mut f: impl FnMut(&Observation<A, P>) -> bool,
+    ) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, entry)| f(entry))
+            .map(|(index, _)| index)
+    }
+
+    /// Returns an iterator over all observations.
+    #[cfg(feature = "coap-transport-udp")]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Observation<A, P>> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut Observation<A, P>> {
+        self.entries.get_mut(index)
+    }
+
+    /// Removes the observation at `index`; the last observation takes its place.
+    pub(crate) fn remove(&mut self, index: usize) {
+        if index < self.entries.len() {
+            self.entries.swap_remove(index);
+        }
+    }
+}
+
+/// A notification built by [`build_notification()`].
+pub(crate) struct Notification {
+    pub(crate) code: u8,
+    /// Length of the notification's options and payload.
+    pub(crate) length: usize,
+    /// True if the notification ends the observation, because it is an error response (which
+    /// then carries no Observe option).
+    pub(crate) last: bool,
+}
+
+/// Builds the next notification of `observation` into `buffer`, using `response_buffer` for the
+/// response before the Observe option is added.
+///
+/// Returns `None` if the observation ended without a notification to send, which happens when the
+/// security context of an OSCORE protected observation is gone.
+pub(crate) fn build_notification<A, H: ObservableHandler>(
+    handler: &mut H,
+    observation: &mut Observation<A, H::Protected>,
+    response_buffer: &mut [u8],
+    buffer: &mut [u8],
+) -> Option<Notification> {
+    // The request is not a block of a transfer of whichever peer sent the last one.
+    #[cfg(feature = "coap-server-blockwise")]
+    crate::requester::clear();
+
+    let (code, length, succeeded) = match &mut observation.request {
+        ObservedRequest::Plain(code, request) => {
+            let (code, length) = respond(
+                handler,
+                &inmemory::Message::new(*code, request),
+                response_buffer,
+            );
+            (code, length, is_success(code))
+        }
+        ObservedRequest::Protected(protected) => {
+            handler.build_protected_notification(protected, response_buffer)?
+        }
+    };
+    let (response, _) = response_buffer.split_at(length);
+
+    if !succeeded {
+        buffer.get_mut(..length)?.copy_from_slice(response);
+        return Some(Notification {
+            code,
+            length,
+            last: true,
+        });
+    }
+
+    let sequence = observation.next_sequence();
+    let (code, length) = with_observe(code, response, sequence, buffer);
+    Some(Notification {
+        code,
+        length,
+        last: !is_success(code),
+    })
+}
+
+/// Writes the response with the given code and options and payload into `buffer`, adding an
+/// Observe option with the value `sequence`.
+///
+/// Returns the code and the length of the written options and payload; if the response does not
+/// fit, a 5.00 (Internal Server Error) response without options is written instead.
+pub(crate) fn with_observe(
+    code: u8,
+    response: &[u8],
+    sequence: u32,
+    buffer: &mut [u8],
+) -> (u8, usize) {
+    let response = inmemory::Message::new(code, response);
+
+    let mut written_code = 0;
+    let mut message = inmemory_write::Message::new(&mut written_code, &mut *buffer);
+    if copy_with_observe(&response, sequence, &mut message).is_ok() {
+        let length = message.finish();
+        return (written_code, length);
+    }
+
+    warn!("Notification does not fit into the buffer.");
+    (coap_numbers::code::INTERNAL_SERVER_ERROR, 0)
+}
+
+/// Returns true if `code` is a success response code (2.xx).
+pub(crate) fn is_success(code: u8) -> bool {
+    code >> 5 == 2
+}
+
+/// Copies `response` into `out`, adding an Observe option with the value `sequence`.
+///
+/// # Errors
+///
+/// This produces an error if the response does not fit into `out`.
+fn copy_with_observe(
+    response: &inmemory::Message<'_>,
+    sequence: u32,
+    out: &mut inmemory_write::Message<'_>,
+) -> Result<(), ()> {
+    let sequence = sequence.to_be_bytes();
+    // Observe values are encoded as minimal unsigned integers.
+    let leading_zeros = sequence.iter().take_while(|byte| **byte == 0).count();
+    let (_, sequence) = sequence.split_at(leading_zeros);
+
+    out.set_code(response.code());
+    let mut pending = true;
+    for option in response.options() {
+        if pending && option.number() > coap_numbers::option::OBSERVE {
+            out.add_option(coap_numbers::option::OBSERVE, sequence)
+                .map_err(|_| ())?;
+            pending = false;
+        }
+        out.add_option(option.number(), option.value())
+            .map_err(|_| ())?;
+    }
+    if pending {
+        out.add_option(coap_numbers::option::OBSERVE, sequence)
+            .map_err(|_| ())?;
+    }
+    out.set_payload(response.payload()).map_err(|_| ())
+}
+
+/// Runs a request through the handler, and writes the response into `buffer`.
+///
+/// Returns the response code and the length of the response's options and payload.
+pub(crate) fn respond<H: coap_handler::Handler>(
+    handler: &mut H,
+    request: &inmemory::Message<'_>,
+    buffer: &mut [u8],
+) -> (u8, usize) {
+    let mut code = 0;
+    let mut message = inmemory_write::Message::new(&mut code, buffer);
+
+    // FIXME rewind message: when building the response fails, the error is rendered into what was
+    // already written, as there is no way yet to discard that.
+    let rendered = match handler.extract_request_data(request) {
+        Ok(extracted) => match handler.build_response(&mut message, extracted) {
+            Ok(()) => Ok(()),
+            Err(e) => e.render(&mut message).map_err(|_| ()),
+        },
+        Err(e) => e.render(&mut message).map_err(|_| ()),
+    };
+    if rendered.is_err() {
+        warn!("Error could not be rendered into the response.");
+        message.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+    }
+
+    let length = message.finish();
+    (code, length)
+}
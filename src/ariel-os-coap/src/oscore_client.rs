@@ -0,0 +1,275 @@
+//! A CoAP client that protects its requests with OSCORE, see [`OscoreClient`].
+
+use core::net::SocketAddr;
+
+use coap_message_implementations::{inmemory, inmemory_write};
+use coapcore::{ClientError, OscoreEdhocClient};
+
+#[cfg(feature = "coap-client-blockwise")]
+use crate::blockwise::{self, BlockwiseError};
+use crate::exchange::{MESSAGE_BUFFER_SIZE, exchange};
+
+type Crypto = lakers_crypto_rustcrypto::Crypto<ariel_os_random::CryptoRng>;
+
+/// Error type returned by [`OscoreClient::request()`] and its block-wise variants.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum OscoreClientError {
+    /// The request could not be sent, or no response was received.
+    Transport,
+    /// The closure building the request returned an error.
+    Build,
+    /// Setting up the security context, protecting the request or verifying the response failed.
+    Security(ClientError),
+    /// The server's response does not continue a block-wise transfer.
+    #[cfg(feature = "coap-client-blockwise")]
+    UnexpectedBlock,
+}
+
+impl From<ClientError> for OscoreClientError {
+    fn from(error: ClientError) -> Self {
+        Self::Security(error)
+    }
+}
+
+#[cfg(feature = "coap-client-blockwise")]
+impl From<BlockwiseError> for OscoreClientError {
+    fn from(error: BlockwiseError) -> Self {
+        match error {
+            BlockwiseError::Transport => Self::Transport,
+            BlockwiseError::Build => Self::Build,
+            BlockwiseError::UnexpectedBlock => Self::UnexpectedBlock,
+        }
+    }
+}
+
+/// A CoAP client that sends requests to a single server, protected by OSCORE.
+///
+/// The OSCORE security context is established through EDHOC when the first request is sent, and
+/// after the server lost it. The client authenticates itself with its own credential, and only
+/// accepts responses from a server that authenticates with the configured server credential.
+///
+/// Requests are sent through the system's [`coap_client()`](crate::coap_client).
+///
+/// Example:
+///
+/// ```ignore
+/// let mut client = OscoreClient::new(server, server_credential, own_credential, own_key);
+///
+/// let code = client
+///     .request(
+///         |request| {
+///             request.set_code(coap_numbers::code::GET);
+///             request.add_option(coap_numbers::option::URI_PATH, b"temperature")
+///         },
+///         |response| response.code(),
+///     )
+///     .await;
+/// ```
+pub struct OscoreClient {
+    server: SocketAddr,
+    security: OscoreEdhocClient<Crypto, fn() -> Crypto>,
+}
+
+impl OscoreClient {
+    /// Creates a client for the server at `server`, which needs to authenticate with
+    /// `server_credential`.
+    ///
+    /// `own_credential` and `own_key` are the device's EDHOC credential and the corresponding
+    /// private key. The credential is sent by reference, so the server needs to know it in advance.
+    #[must_use]
+    pub fn new(
+        server: SocketAddr,
+        server_credential: lakers::Credential,
+        own_credential: lakers::Credential,
+        own_key: lakers::BytesP256ElemLen,
+    ) -> Self {
+        Self {
+            server,
+            security: OscoreEdhocClient::new(own_credential, own_key, server_credential, || {
+                lakers_crypto_rustcrypto::Crypto::new(ariel_os_random::crypto_rng())
+            }),
+        }
+    }
+
+    /// Sends a request protected by OSCORE, and processes the verified response.
+    ///
+    /// `build` writes the request's code, options and payload, all of which are protected. Only
+    /// once the server's response has been verified is it passed to `process`.
+    ///
+    /// If no security context is available, an EDHOC exchange with the server is run first.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if the request could not be built or sent, if the EDHOC exchange
+    /// failed (e.g., because the server did not authenticate with the configured credential), or
+    /// if the response could not be verified. If the response is not protected with OSCORE (which
+    /// typically means that the server lost the security context), the security context is
+    /// discarded, so that the next request starts over with EDHOC.
+    ///
+    /// # Panics
+    ///
+    /// This panics when called from a thread other than the one hosting the network stack, see
+    /// [`coap_client()`](crate::coap_client).
+    pub async fn request<E, R>(
+        &mut self,
+        build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+        process: impl FnOnce(&inmemory::Message<'_>) -> R,
+    ) -> Result<R, OscoreClientError> {
+        let mut plaintext_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut plaintext_code = 0;
+        let mut plaintext =
+            inmemory_write::Message::new(&mut plaintext_code, &mut plaintext_buffer[..]);
+        build(&mut plaintext).map_err(|_| OscoreClientError::Build)?;
+        let length = plaintext.finish();
+        let (written, _) = plaintext_buffer.split_at(length);
+        let plaintext = inmemory::Message::new(plaintext_code, written);
+
+        let mut response_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let (response_code, length) = self
+            .exchange_protected(&plaintext, &mut response_buffer)
+            .await?;
+        let (written, _) = response_buffer.split_at(length);
+
+        Ok(process(&inmemory::Message::new(response_code, written)))
+    }
+
+    /// Retrieves a representation from the server block by block, protecting every block with
+    /// OSCORE.
+    ///
+    /// This works like [`blockwise::download()`](crate::blockwise::download), with the requests
+    /// sent as in [`Self::request()`].
+    ///
+    /// # Errors
+    ///
+    /// This produces the errors of [`Self::request()`], and errors if the server's responses do
+    /// not continue the transfer.
+    ///
+    /// # Panics
+    ///
+    /// This panics when called from a thread other than the one hosting the network stack, see
+    /// [`coap_client()`](crate::coap_client).
+    #[cfg(feature = "coap-client-blockwise")]
+    pub async fn download<E>(
+        &mut self,
+        build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+        process: impl FnMut(usize, &inmemory::Message<'_>),
+    ) -> Result<(), OscoreClientError> {
+        let mut base_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let (code, length) = blockwise::build_base(build, &mut base_buffer)?;
+        let (base, _) = base_buffer.split_at(length);
+
+        blockwise::run_download(
+            async |request, response_buffer| {
+                self.exchange_protected(request, response_buffer).await
+            },
+            &inmemory::Message::new(code, base),
+            process,
+        )
+        .await
+    }
+
+    /// Sends a payload to the server in blocks, protecting every block with OSCORE.
+    ///
+    /// This works like [`blockwise::upload()`](crate::blockwise::upload), with the requests sent
+    /// as in [`Self::request()`].
+    ///
+    /// # Errors
+    ///
+    /// This produces the errors of [`Self::request()`], and errors if the server's responses do
+    /// not continue the transfer.
+    ///
+    /// # Panics
+    ///
+    /// This panics when called from a thread other than the one hosting the network stack, see
+    /// [`coap_client()`](crate::coap_client).
+    #[cfg(feature = "coap-client-blockwise")]
+    pub async fn upload<E, R>(
+        &mut self,
+        build: impl FnOnce(&mut inmemory_write::Message<'_>) -> Result<(), E>,
+        payload: &[u8],
+        process: impl FnOnce(&inmemory::Message<'_>) -> R,
+    ) -> Result<R, OscoreClientError> {
+        let mut base_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let (code, length) = blockwise::build_base(build, &mut base_buffer)?;
+        let (base, _) = base_buffer.split_at(length);
+
+        blockwise::run_upload(
+            async |request, response_buffer| {
+                self.exchange_protected(request, response_buffer).await
+            },
+            &inmemory::Message::new(code, base),
+            payload,
+            process,
+        )
+        .await
+    }
+
+    /// Protects `plaintext`, sends it to the server, and writes the verified response into
+    /// `response_buffer`.
+    ///
+    /// Returns the response code and the length of the response's options and payload.
+    ///
+    /// If no security context is available, an EDHOC exchange with the server is run first.
+    async fn exchange_protected(
+        &mut self,
+        plaintext: &inmemory::Message<'_>,
+        response_buffer: &mut [u8],
+    ) -> Result<(u8, usize), OscoreClientError> {
+        if !self.security.has_context() {
+            self.establish().await?;
+        }
+
+        let mut request_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut request_code = 0;
+        let mut request = inmemory_write::Message::new(&mut request_code, &mut request_buffer[..]);
+        let correlation = self.security.protect_request(plaintext, &mut request)?;
+        let length = request.finish();
+        let (written, _) = request_buffer.split_at(length);
+        let request = inmemory::Message::new(request_code, written);
+
+        let mut protected_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let (protected_code, length) = exchange(self.server, &request, &mut protected_buffer)
+            .await
+            .map_err(|()| OscoreClientError::Transport)?;
+        let (written, _) = protected_buffer.split_at(length);
+        let protected = inmemory::Message::new(protected_code, written);
+
+        let mut response_code = 0;
+        let mut response = inmemory_write::Message::new(&mut response_code, response_buffer);
+        match self
+            .security
+            .unprotect_response(correlation, &protected, &mut response)
+        {
+            Ok(()) => (),
+            Err(ClientError::UnexpectedResponse) => {
+                self.security.reset();
+                return Err(ClientError::UnexpectedResponse.into());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let length = response.finish();
+        Ok((response_code, length))
+    }
+
+    /// Runs an EDHOC exchange with the server to establish a security context.
+    async fn establish(&mut self) -> Result<(), OscoreClientError> {
+        let mut request_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut request_code = 0;
+        let mut request = inmemory_write::Message::new(&mut request_code, &mut request_buffer[..]);
+        self.security.build_edhoc_request(&mut request)?;
+        let length = request.finish();
+        let (written, _) = request_buffer.split_at(length);
+        let request = inmemory::Message::new(request_code, written);
+
+        let mut response_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let (response_code, length) = exchange(self.server, &request, &mut response_buffer)
+            .await
+            .map_err(|()| OscoreClientError::Transport)?;
+        let (written, _) = response_buffer.split_at(length);
+
+        self.security
+            .process_edhoc_response(&inmemory::Message::new(response_code, written))?;
+        Ok(())
+    }
+}
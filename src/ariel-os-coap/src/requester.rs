@@ -0,0 +1,88 @@
+//! Tracks who sent the request the CoAP server is processing.
+//!
+//! The block-wise layer of the server ([`coapcore::BlockwiseHandler`]) only continues a transfer
+//! with requests from the requester that started it, but handlers do not learn where a request
+//! came from. The transports record the requester here before a request is passed on, and the
+//! block-wise layer reads it through [`current()`].
+
+use core::cell::RefCell;
+
+use coap_message::{MessageOption as _, ReadableMessage};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+/// Longest OSCORE key ID that is told apart.
+///
+/// The recipient IDs coapcore assigns are a single byte long; requests with longer key IDs are
+/// rejected before they reach the block-wise layer.
+const MAX_KID_LEN: usize = 4;
+
+/// The peer a request was received from.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Peer {
+    /// Remote address of a CoAP-over-UDP request.
+    #[cfg(feature = "coap-transport-udp")]
+    Udp(core::net::SocketAddr),
+    /// Remote endpoint of the CoAP-over-TCP connection a request was received on.
+    #[cfg(feature = "coap-transport-tcp")]
+    Tcp(embassy_net::IpEndpoint),
+}
+
+/// Who sent a request, as far as the block-wise layer is concerned.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Requester {
+    /// The peer, or `None` for requests evaluated by the server itself (eg. for notifications).
+    peer: Option<Peer>,
+    /// The key ID of the OSCORE option, which identifies the security context the request is
+    /// unprotected with.
+    kid: Option<heapless::Vec<u8, MAX_KID_LEN>>,
+}
+
+impl Requester {
+    const NONE: Self = Self {
+        peer: None,
+        kid: None,
+    };
+}
+
+static CURRENT: Mutex<CriticalSectionRawMutex, RefCell<Requester>> =
+    Mutex::new(RefCell::new(Requester::NONE));
+
+/// Records `request` as received from `peer`, until the next request is recorded.
+pub(crate) fn set(peer: Option<Peer>, request: &impl ReadableMessage) {
+    let requester = Requester {
+        peer,
+        kid: oscore_kid(request),
+    };
+    CURRENT.lock(|current| *current.borrow_mut() = requester);
+}
+
+/// Records that the request processed next is evaluated by the server itself, eg. to build a
+/// notification.
+pub(crate) fn clear() {
+    CURRENT.lock(|current| *current.borrow_mut() = Requester::NONE);
+}
+
+/// Returns the requester of the request being processed.
+pub(crate) fn current() -> Requester {
+    CURRENT.lock(|current| current.borrow().clone())
+}
+
+/// Returns the key ID of the request's OSCORE option (RFC 8613 Section 6.1), if it has one.
+fn oscore_kid(request: &impl ReadableMessage) -> Option<heapless::Vec<u8, MAX_KID_LEN>> {
+    let option = request
+        .options()
+        .find(|option| option.number() == coap_numbers::option::OSCORE)?;
+    let (flags, rest) = option.value().split_first()?;
+    if flags & 0x08 == 0 {
+        return None;
+    }
+    // Skips the Partial IV, and the kid context if present.
+    let rest = rest.get(usize::from(flags & 0x07)..)?;
+    let kid = if flags & 0x10 == 0 {
+        rest
+    } else {
+        let (length, rest) = rest.split_first()?;
+        rest.get(usize::from(*length)..)?
+    };
+    heapless::Vec::from_slice(kid).ok()
+}
@@ -8,6 +8,18 @@ mod flash_peers {
     include!(concat!(env!("OUT_DIR"), "/peers.rs"));
 }
 
+/// Storage namespace holding the persisted CoAP server configuration.
+///
+/// It is encrypted with the `storage-encryption` feature, as it holds the private key of the
+/// server.
+#[cfg(feature = "storage-encryption")]
+const STORAGE_NAMESPACE: ariel_os_storage::EncryptedNamespace =
+    ariel_os_storage::EncryptedNamespace::new("ariel-os-coap");
+/// Storage namespace holding the persisted CoAP server configuration.
+#[cfg(not(feature = "storage-encryption"))]
+const STORAGE_NAMESPACE: ariel_os_storage::Namespace =
+    ariel_os_storage::Namespace::new("ariel-os-coap");
+
 pub async fn server_security_config() -> impl ServerSecurityConfig {
     StoredPolicy::load().await
 }
@@ -97,18 +109,42 @@ impl StoredPolicy {
         // Storage format: ([u8], [u8; 32]), where the former is a CCS, and the latter the
         // corresponding key. We may need to extend the latter to be a COSE_Key when crypto agility
         // becomes a thing.
-        const OWN_CREDENTIAL_KEY: &str = "ariel-os-coap.own-edhoc-credential";
-
-        let (credential, key) = match ariel_os_storage::get(OWN_CREDENTIAL_KEY)
-            .await
-            .expect("flash error prevents startup")
-        {
-            Some(credpair) => credpair,
-            None => {
-                let credpair = generate_credpair();
-                ariel_os_storage::insert(OWN_CREDENTIAL_KEY, credpair.clone())
+        const OWN_CREDENTIAL_KEY: &str = "own-edhoc-credential";
+        // Key the credential was stored under before storage namespaces existed.
+        const LEGACY_OWN_CREDENTIAL_KEY: &str = "ariel-os-coap.own-edhoc-credential";
+
+        let (credential, key) = match STORAGE_NAMESPACE.get(OWN_CREDENTIAL_KEY).await {
+            Ok(Some(credpair)) => credpair,
+            result => {
+                // STM32 flash drivers do not implement `MultiwriteNorFlash`, so a plaintext copy
+                // under the legacy key cannot be removed there.
+                #[cfg(not(context = "stm32"))]
+                let from_legacy_key = result.is_ok();
+                let stored = match result {
+                    Ok(_) => ariel_os_storage::get(LEGACY_OWN_CREDENTIAL_KEY).await,
+                    // Credentials stored before the namespace was encrypted are in plaintext.
+                    #[cfg(feature = "storage-encryption")]
+                    Err(ariel_os_storage::Error::SchemaMismatch { .. }) => {
+                        STORAGE_NAMESPACE.namespace().get(OWN_CREDENTIAL_KEY).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let stored = stored.expect("flash error prevents startup");
+                let migrated = stored.is_some();
+                let credpair = stored.unwrap_or_else(generate_credpair);
+                if migrated {
+                    info!("Migrating stored CoAP server credential.");
+                }
+                STORAGE_NAMESPACE
+                    .insert(OWN_CREDENTIAL_KEY, credpair.clone())
                     .await
                     .expect("flash error prevents startup");
+                #[cfg(not(context = "stm32"))]
+                if migrated && from_legacy_key {
+                    ariel_os_storage::remove(LEGACY_OWN_CREDENTIAL_KEY)
+                        .await
+                        .expect("flash error prevents startup");
+                }
                 credpair
             }
         };
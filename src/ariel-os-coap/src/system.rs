@@ -0,0 +1,666 @@
+//! Resources provided by the system on the CoAP server, see [`SystemHandler`].
+//!
+//! Each group of resources is enabled through a Cargo feature:
+//!
+//! * `coap-system-devinfo`: `/dev/info` describes the device as a CBOR map (with the keys `"os"`,
+//!   `"board"` and, if the device has an identity, `"id"`).
+//! * `coap-system-sensors`: `/sensors` holds the latest readings of all sensors of the
+//!   [sensor registry](ariel_os_sensors_registry::REGISTRY) as a SenML CBOR pack ([RFC 8428]),
+//!   and `/sensors/<n>` the readings of the `n`-th sensor alone. Sensors are sampled every
+//!   `CONFIG_COAP_SENSORS_INTERVAL_MS` milliseconds; each new reading notifies the observers of
+//!   these resources.
+//! * `coap-system-threads`: `/threads` lists the threads with their state, priority and stack
+//!   usage as a CBOR array of maps.
+//!
+//! The resources are not listed in `/.well-known/core`. As they describe the device and its
+//! internals, they can not be enabled together with `coap-server-config-unprotected`.
+//!
+//! The device's storage is not served (yet): its interface is asynchronous, while resources are
+//! rendered synchronously within the server's handler, and neither transport offers a way to
+//! respond to a request later.
+//!
+//! [RFC 8428]: https://www.rfc-editor.org/rfc/rfc8428
+
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage, error::RenderableOnMinimal,
+};
+use coap_numbers::option::{ACCEPT, URI_HOST, URI_PATH};
+use minicbor::encode::write::{Cursor, EndOfSlice};
+
+// Without an access policy, the system resources would be readable by anyone who can reach the
+// device.
+#[cfg(feature = "coap-server-config-unprotected")]
+compile_error!(
+    "The CoAP system resources (coap-system-*) can not be served with coap-server-config-unprotected; select a configuration with an access policy."
+);
+
+/// Size of the buffer into which the representation of a system resource is written.
+///
+/// Representations that do not fit into a single message need the `coap-server-blockwise` feature
+/// to be sent in blocks.
+const PAYLOAD_BUFFER_SIZE: usize = 1024;
+
+/// Content-Format of `application/cbor`.
+#[cfg(any(feature = "coap-system-devinfo", feature = "coap-system-threads"))]
+const CONTENT_FORMAT_CBOR: u8 = 60;
+
+/// Content-Format of `application/senml+cbor`.
+#[cfg(feature = "coap-system-sensors")]
+const CONTENT_FORMAT_SENML_CBOR: u8 = 112;
+
+/// Error produced when writing a representation into the payload buffer.
+type EncodeError = minicbor::encode::Error<EndOfSlice>;
+
+/// A system resource, as recognized from a request's path.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Resource {
+    /// `/dev/info`
+    #[cfg(feature = "coap-system-devinfo")]
+    DevInfo,
+    /// `/sensors`
+    #[cfg(feature = "coap-system-sensors")]
+    Sensors,
+    /// `/sensors/<n>`
+    #[cfg(feature = "coap-system-sensors")]
+    Sensor(usize),
+    /// `/threads`
+    #[cfg(feature = "coap-system-threads")]
+    Threads,
+}
+
+impl Resource {
+    /// Recognizes a resource from the Uri-Path options of a request.
+    ///
+    /// Returns `None` if the path is not that of a system resource.
+    fn from_request<M: ReadableMessage>(request: &M) -> Option<Self> {
+        let mut path = heapless::Vec::<Segment, 2>::new();
+        for option in request.options().filter(|o| o.number() == URI_PATH) {
+            path.push(Segment::new(option.value())).ok()?;
+        }
+        match path.as_slice() {
+            #[cfg(feature = "coap-system-devinfo")]
+            [Segment::Dev, Segment::Info] => Some(Self::DevInfo),
+            #[cfg(feature = "coap-system-sensors")]
+            [Segment::Sensors] => Some(Self::Sensors),
+            #[cfg(feature = "coap-system-sensors")]
+            [Segment::Sensors, Segment::Index(index)] if sensors::is_valid(*index) => {
+                Some(Self::Sensor(*index))
+            }
+            #[cfg(feature = "coap-system-threads")]
+            [Segment::Threads] => Some(Self::Threads),
+            _ => None,
+        }
+    }
+
+    /// Returns the Content-Format of the resource's representation.
+    #[allow(
+        clippy::match_same_arms,
+        reason = "arms are present depending on the enabled features"
+    )]
+    fn content_format(self) -> u8 {
+        match self {
+            #[cfg(feature = "coap-system-devinfo")]
+            Self::DevInfo => CONTENT_FORMAT_CBOR,
+            #[cfg(feature = "coap-system-sensors")]
+            Self::Sensors | Self::Sensor(_) => CONTENT_FORMAT_SENML_CBOR,
+            #[cfg(feature = "coap-system-threads")]
+            Self::Threads => CONTENT_FORMAT_CBOR,
+        }
+    }
+
+    /// Writes the resource's representation into `buffer`.
+    ///
+    /// Returns the length of the representation, or `None` if there is no representation yet.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the representation does not fit into `buffer`.
+    fn encode(self, buffer: &mut [u8]) -> Result<Option<usize>, EncodeError> {
+        let mut encoder = minicbor::Encoder::new(Cursor::new(buffer));
+        match self {
+            #[cfg(feature = "coap-system-devinfo")]
+            Self::DevInfo => encode_dev_info(&mut encoder)?,
+            #[cfg(feature = "coap-system-sensors")]
+            Self::Sensors => sensors::encode_all(&mut encoder)?,
+            #[cfg(feature = "coap-system-sensors")]
+            Self::Sensor(index) => {
+                if !sensors::encode_one(&mut encoder, index)? {
+                    return Ok(None);
+                }
+            }
+            #[cfg(feature = "coap-system-threads")]
+            Self::Threads => encode_threads(&mut encoder)?,
+        }
+        Ok(Some(encoder.into_writer().position()))
+    }
+}
+
+/// A segment of a request's path, as far as it is relevant to system resources.
+#[derive(Copy, Clone)]
+enum Segment {
+    Dev,
+    Info,
+    Sensors,
+    Threads,
+    Index(usize),
+    Other,
+}
+
+impl Segment {
+    fn new(value: &[u8]) -> Self {
+        match value {
+            b"dev" => Self::Dev,
+            b"info" => Self::Info,
+            b"sensors" => Self::Sensors,
+            b"threads" => Self::Threads,
+            value => core::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map_or(Self::Other, Self::Index),
+        }
+    }
+}
+
+/// Error type returned when a request to a system resource can not be processed.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum SystemError {
+    /// The request has a critical option that is not understood.
+    BadOption,
+    /// The request method is not GET.
+    MethodNotAllowed,
+    /// The request's Accept option asks for a different format.
+    NotAcceptable,
+}
+
+impl RenderableOnMinimal for SystemError {
+    type Error<IE>
+        = IE
+    where
+        IE: RenderableOnMinimal,
+        IE: core::fmt::Debug;
+
+    fn render<M: MinimalWritableMessage>(
+        self,
+        msg: &mut M,
+    ) -> Result<(), Self::Error<M::UnionError>> {
+        let code = match self {
+            Self::BadOption => coap_numbers::code::BAD_OPTION,
+            Self::MethodNotAllowed => coap_numbers::code::METHOD_NOT_ALLOWED,
+            Self::NotAcceptable => coap_numbers::code::NOT_ACCEPTABLE,
+        };
+        msg.set_code(M::Code::new(code)?);
+        Ok(())
+    }
+}
+
+/// Either a system resource's own data, or the inner handler's.
+///
+/// This mirrors the type of the same name that coapcore uses internally.
+#[derive(Debug)]
+pub(crate) enum OrInner<O, I> {
+    Own(O),
+    Inner(I),
+}
+
+impl<O: RenderableOnMinimal, I: RenderableOnMinimal> RenderableOnMinimal for OrInner<O, I> {
+    type Error<IE>
+        = OrInner<O::Error<IE>, I::Error<IE>>
+    where
+        IE: RenderableOnMinimal,
+        IE: core::fmt::Debug;
+
+    fn render<M: MinimalWritableMessage>(
+        self,
+        msg: &mut M,
+    ) -> Result<(), Self::Error<M::UnionError>> {
+        match self {
+            OrInner::Own(own) => own.render(msg).map_err(OrInner::Own),
+            OrInner::Inner(inner) => inner.render(msg).map_err(OrInner::Inner),
+        }
+    }
+}
+
+/// A [`coap_handler::Handler`] wrapper that serves the system resources enabled through Cargo
+/// features, and passes all other requests on to the inner handler.
+///
+/// Like the application's handler, it is wrapped by the
+/// [`OscoreEdhocHandler`](coapcore::OscoreEdhocHandler) (unless the server is unprotected), so
+/// requests to system resources are subject to the same authorization: A peer needs to be granted
+/// GET on each resource's path (e.g., `/sensors/0`) in its scope.
+pub(crate) struct SystemHandler<H> {
+    inner: H,
+}
+
+impl<H: coap_handler::Handler> SystemHandler<H> {
+    /// Wraps a handler to serve the system resources next to it.
+    pub(crate) fn new(inner: H) -> Self {
+        Self { inner }
+    }
+}
+
+impl<H: coap_handler::Handler> coap_handler::Handler for SystemHandler<H> {
+    type RequestData = OrInner<Resource, H::RequestData>;
+    type ExtractRequestError = OrInner<SystemError, H::ExtractRequestError>;
+    type BuildResponseError<M: MinimalWritableMessage> =
+        OrInner<M::UnionError, H::BuildResponseError<M>>;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        let Some(resource) = Resource::from_request(request) else {
+            return self
+                .inner
+                .extract_request_data(request)
+                .map(OrInner::Inner)
+                .map_err(OrInner::Inner);
+        };
+
+        for option in request.options() {
+            match option.number() {
+                URI_PATH | URI_HOST => (),
+                ACCEPT => {
+                    let accept = option
+                        .value()
+                        .iter()
+                        .fold(0u32, |acc, byte| (acc << 8) | u32::from(*byte));
+                    if accept != u32::from(resource.content_format()) {
+                        return Err(OrInner::Own(SystemError::NotAcceptable));
+                    }
+                }
+                // Critical options
+                number if number & 1 == 1 => {
+                    return Err(OrInner::Own(SystemError::BadOption));
+                }
+                _ => (),
+            }
+        }
+        let code: u8 = request.code().into();
+        if code != coap_numbers::code::GET {
+            return Err(OrInner::Own(SystemError::MethodNotAllowed));
+        }
+
+        Ok(OrInner::Own(resource))
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        match request {
+            // Content-Format option and payload marker
+            OrInner::Own(_) => PAYLOAD_BUFFER_SIZE + 4,
+            OrInner::Inner(request) => self.inner.estimate_length(request),
+        }
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        let resource = match request {
+            OrInner::Own(resource) => resource,
+            OrInner::Inner(request) => {
+                return self
+                    .inner
+                    .build_response(response, request)
+                    .map_err(OrInner::Inner);
+            }
+        };
+
+        build_system_response(response, resource).map_err(OrInner::Own)
+    }
+}
+
+/// Builds the response to a GET request to `resource`.
+///
+/// # Errors
+///
+/// This produces an error if the response could not be written into the message.
+fn build_system_response<M: MutableWritableMessage>(
+    response: &mut M,
+    resource: Resource,
+) -> Result<(), M::UnionError> {
+    let mut buffer = [0u8; PAYLOAD_BUFFER_SIZE];
+    let code = match resource.encode(&mut buffer) {
+        Ok(Some(length)) => {
+            let (payload, _) = buffer.split_at(length);
+            response.set_code(M::Code::new(coap_numbers::code::CONTENT)?);
+            response.add_option(
+                M::OptionNumber::new(coap_numbers::option::CONTENT_FORMAT)?,
+                &[resource.content_format()],
+            )?;
+            response.set_payload(payload)?;
+            return Ok(());
+        }
+        // No reading is available yet.
+        Ok(None) => coap_numbers::code::SERVICE_UNAVAILABLE,
+        Err(_) => {
+            ariel_os_log::warn!("System resource does not fit into the payload buffer");
+            coap_numbers::code::INTERNAL_SERVER_ERROR
+        }
+    };
+    response.set_code(M::Code::new(code)?);
+    Ok(())
+}
+
+/// Writes the representation of `/dev/info`.
+#[cfg(feature = "coap-system-devinfo")]
+fn encode_dev_info(encoder: &mut minicbor::Encoder<Cursor<&mut [u8]>>) -> Result<(), EncodeError> {
+    let id = ariel_os_identity::device_id_bytes().ok();
+    encoder.map(if id.is_some() { 3 } else { 2 })?;
+    encoder.str("os")?.str(ariel_os_buildinfo::OS_NAME)?;
+    encoder.str("board")?.str(ariel_os_buildinfo::BOARD)?;
+    if let Some(id) = id {
+        encoder.str("id")?.bytes(id.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Writes the representation of `/threads`.
+#[cfg(feature = "coap-system-threads")]
+fn encode_threads(encoder: &mut minicbor::Encoder<Cursor<&mut [u8]>>) -> Result<(), EncodeError> {
+    use ariel_os_threads::ThreadState;
+
+    encoder.begin_array()?;
+    for thread in ariel_os_threads::info() {
+        let state = match thread.state {
+            ThreadState::Invalid => "invalid",
+            ThreadState::Running => "running",
+            ThreadState::Parked => "parked",
+            ThreadState::LockBlocked => "lock-blocked",
+            ThreadState::SemaphoreBlocked(_) => "semaphore-blocked",
+            ThreadState::FlagBlocked(_) => "flag-blocked",
+            ThreadState::EventGroupBlocked(_) => "event-group-blocked",
+            ThreadState::ChannelRxBlocked(_) => "channel-rx-blocked",
+            ThreadState::ChannelTxBlocked(_) => "channel-tx-blocked",
+            ThreadState::WaitQueueBlocked => "wait-queue-blocked",
+            ThreadState::Finished => "finished",
+        };
+        encoder.map(5)?;
+        encoder.str("id")?.encode(usize::from(thread.thread_id))?;
+        encoder.str("state")?.str(state)?;
+        encoder.str("prio")?.encode(usize::from(thread.priority))?;
+        encoder.str("stack")?.encode(thread.stack_size)?;
+        encoder.str("stack-used")?.encode(thread.stack_peak_usage)?;
+    }
+    encoder.end()?;
+    Ok(())
+}
+
+#[cfg(feature = "coap-system-sensors")]
+mod sensors {
+    //! Sampling of the sensors, and their representation in SenML.
+
+    use core::{cell::RefCell, fmt::Write as _};
+
+    use ariel_os_sensors::{Label, MeasurementUnit, Reading as _, sensor::Samples};
+    use ariel_os_sensors_registry::REGISTRY;
+    use embassy_sync::{blocking_mutex::Mutex, blocking_mutex::raw::CriticalSectionRawMutex};
+    use embassy_time::{Duration, Instant, Timer};
+    use minicbor::encode::write::Cursor;
+
+    use super::EncodeError;
+
+    /// Maximum number of sensors whose readings are served.
+    const MAX_SENSORS: usize = ariel_os_utils::usize_from_env_or!(
+        "CONFIG_COAP_MAX_SENSORS",
+        8,
+        "maximum number of sensors served as CoAP system resources"
+    );
+
+    /// Interval between two readings of the sensors.
+    const INTERVAL_MS: usize = ariel_os_utils::usize_from_env_or!(
+        "CONFIG_COAP_SENSORS_INTERVAL_MS",
+        10_000,
+        "interval in milliseconds at which sensors served as CoAP system resources are read"
+    );
+
+    /// Time after which a sensor that has not produced a reading is skipped.
+    const READING_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// SenML labels ([RFC 8428 Section 6]).
+    ///
+    /// [RFC 8428 Section 6]: https://www.rfc-editor.org/rfc/rfc8428#section-6
+    mod label {
+        pub(super) const BASE_NAME: i8 = -2;
+        pub(super) const NAME: i8 = 0;
+        pub(super) const UNIT: i8 = 1;
+        pub(super) const VALUE: i8 = 2;
+        pub(super) const BOOLEAN_VALUE: i8 = 4;
+        pub(super) const TIME: i8 = 6;
+    }
+
+    /// Latest reading of each sensor, with the time it was taken.
+    static READINGS: Mutex<
+        CriticalSectionRawMutex,
+        RefCell<[Option<(Samples, Instant)>; MAX_SENSORS]>,
+    > = Mutex::new(RefCell::new([None; MAX_SENSORS]));
+
+    /// Reads all sensors periodically, and stores their readings to be served.
+    #[ariel_os_macros::task(autostart)]
+    async fn sample_sensors() {
+        loop {
+            for (index, sensor) in REGISTRY.sensors().enumerate().take(MAX_SENSORS) {
+                let reading = if sensor.trigger_measurement().is_ok() {
+                    embassy_time::with_timeout(READING_TIMEOUT, sensor.wait_for_reading())
+                        .await
+                        .ok()
+                        .and_then(Result::ok)
+                } else {
+                    None
+                };
+                READINGS.lock(|readings| {
+                    if let Some(slot) = readings.borrow_mut().get_mut(index) {
+                        *slot = reading.map(|reading| (reading, Instant::now()));
+                    }
+                });
+
+                let mut path = heapless::String::<16>::new();
+                if write!(path, "/sensors/{index}").is_ok() {
+                    crate::observe::notify(&path);
+                }
+            }
+            crate::observe::notify("/sensors");
+
+            Timer::after_millis(INTERVAL_MS as u64).await;
+        }
+    }
+
+    /// Returns true if a sensor with this index is served.
+    pub(super) fn is_valid(index: usize) -> bool {
+        index < MAX_SENSORS && index < REGISTRY.sensors().len()
+    }
+
+    /// Writes the latest readings of all sensors as a SenML pack.
+    pub(super) fn encode_all(
+        encoder: &mut minicbor::Encoder<Cursor<&mut [u8]>>,
+    ) -> Result<(), EncodeError> {
+        let readings = READINGS.lock(|readings| *readings.borrow());
+        let count = readings
+            .iter()
+            .flatten()
+            .map(|(reading, _)| records(reading))
+            .sum::<u64>();
+        encoder.array(count)?;
+        for (index, (reading, taken)) in readings
+            .iter()
+            .enumerate()
+            .filter_map(|(index, reading)| Some((index, reading.as_ref()?)))
+        {
+            encode_records(encoder, index, reading, *taken)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the latest reading of the sensor at `index` as a SenML pack.
+    ///
+    /// Returns false if the sensor has not produced a reading yet.
+    pub(super) fn encode_one(
+        encoder: &mut minicbor::Encoder<Cursor<&mut [u8]>>,
+        index: usize,
+    ) -> Result<bool, EncodeError> {
+        let Some((reading, taken)) =
+            READINGS.lock(|readings| readings.borrow().get(index).copied().flatten())
+        else {
+            return Ok(false);
+        };
+        encoder.array(records(&reading))?;
+        encode_records(encoder, index, &reading, taken)?;
+        Ok(true)
+    }
+
+    /// Number of SenML records a reading is represented by (one per available sample).
+    fn records(reading: &Samples) -> u64 {
+        reading
+            .samples()
+            .filter(|(_, sample)| sample.value().is_ok())
+            .count() as u64
+    }
+
+    /// Writes the SenML records of the reading of the sensor at `index`, taken at `taken`.
+    ///
+    /// The records are named `<index>/<channel>` (e.g., `0/temperature`).
+    fn encode_records(
+        encoder: &mut minicbor::Encoder<Cursor<&mut [u8]>>,
+        index: usize,
+        reading: &Samples,
+        taken: Instant,
+    ) -> Result<(), EncodeError> {
+        use core::fmt::Write as _;
+
+        let mut base_name = heapless::String::<8>::new();
+        // Sensor indices are far below 10^7.
+        let _ = write!(base_name, "{index}/");
+        // Relative times are negative for the past (RFC 8428 Section 4.5.3).
+        let age = i64::try_from(taken.elapsed().as_secs()).unwrap_or(i64::MAX);
+
+        let mut first = true;
+        for (position, (channel, sample)) in reading.samples().enumerate() {
+            let Ok(value) = sample.value() else {
+                continue;
+            };
+            let name = channel_name(channel.label());
+            let unit = senml_unit(channel.unit(), channel.label());
+
+            let entries = 2 + u64::from(first) + u64::from(unit.is_some()) + u64::from(age != 0);
+            encoder.map(entries)?;
+            if first {
+                encoder.i8(label::BASE_NAME)?.str(&base_name)?;
+                first = false;
+            }
+            encoder.i8(label::NAME)?;
+            if let Some(name) = name {
+                encoder.str(name)?;
+            } else {
+                // Readings have at most 12 samples.
+                let mut name = heapless::String::<4>::new();
+                let _ = write!(name, "{position}");
+                encoder.str(&name)?;
+            }
+            if let Some(unit) = unit {
+                encoder.i8(label::UNIT)?.str(unit)?;
+            }
+            if channel.unit() == MeasurementUnit::Bool {
+                encoder.i8(label::BOOLEAN_VALUE)?.bool(value != 0)?;
+            } else {
+                encoder.i8(label::VALUE)?;
+                match channel.scaling() {
+                    0 => encoder.i32(value)?,
+                    scaling => encoder.f64(scaled(value, scaling))?,
+                };
+            }
+            if age != 0 {
+                encoder.i8(label::TIME)?.i64(-age)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `value * 10^scaling`.
+    fn scaled(value: i32, scaling: i8) -> f64 {
+        // Powers of ten up to 10^22 are exact in an f64, so the result is rounded only once.
+        let factor = (0..scaling.unsigned_abs()).fold(1f64, |factor, _| factor * 10.0);
+        if scaling < 0 {
+            f64::from(value) / factor
+        } else {
+            f64::from(value) * factor
+        }
+    }
+
+    /// Returns the SenML record name of a channel.
+    fn channel_name(label: Label) -> Option<&'static str> {
+        Some(match label {
+            Label::AccelerationX => "acceleration-x",
+            Label::AccelerationY => "acceleration-y",
+            Label::AccelerationZ => "acceleration-z",
+            Label::Altitude => "altitude",
+            Label::AngularVelocityX => "angular-velocity-x",
+            Label::AngularVelocityY => "angular-velocity-y",
+            Label::AngularVelocityZ => "angular-velocity-z",
+            Label::Co2 => "co2",
+            Label::GroundSpeed => "ground-speed",
+            Label::Illuminance => "illuminance",
+            Label::Latitude => "latitude",
+            Label::Longitude => "longitude",
+            Label::Opaque => "opaque",
+            Label::OpaqueGnssTime => "gnss-time",
+            Label::Pressure => "pressure",
+            Label::RelativeHumidity => "relative-humidity",
+            Label::Heading => "heading",
+            Label::Temperature => "temperature",
+            Label::VerticalSpeed => "vertical-speed",
+            Label::X => "x",
+            Label::Y => "y",
+            Label::Z => "z",
+            _ => return None,
+        })
+    }
+
+    /// Returns the SenML unit of a channel, if the unit is registered for SenML.
+    ///
+    /// See the [SenML Units registry](https://www.iana.org/assignments/senml/senml.xhtml#senml-units).
+    fn senml_unit(unit: MeasurementUnit, label: Label) -> Option<&'static str> {
+        Some(match unit {
+            MeasurementUnit::Ampere => "A",
+            MeasurementUnit::Becquerel => "Bq",
+            MeasurementUnit::Candela => "cd",
+            MeasurementUnit::Celsius => "Cel",
+            MeasurementUnit::Coulomb => "C",
+            MeasurementUnit::Decibel => "dB",
+            MeasurementUnit::DecimalDegree => match label {
+                Label::Latitude => "lat",
+                Label::Longitude => "lon",
+                _ => return None,
+            },
+            MeasurementUnit::Farad => "F",
+            MeasurementUnit::Gram => "g",
+            MeasurementUnit::Gray => "Gy",
+            MeasurementUnit::Henry => "H",
+            MeasurementUnit::Hertz => "Hz",
+            MeasurementUnit::Joule => "J",
+            MeasurementUnit::Katal => "kat",
+            MeasurementUnit::Kelvin => "K",
+            MeasurementUnit::Lumen => "lm",
+            MeasurementUnit::Lux => "lx",
+            MeasurementUnit::Meter => "m",
+            MeasurementUnit::MeterPerSecond => "m/s",
+            MeasurementUnit::Mole => "mol",
+            MeasurementUnit::Newton => "N",
+            MeasurementUnit::Ohm => "Ohm",
+            MeasurementUnit::PartsPerMillion => "ppm",
+            MeasurementUnit::Pascal => "Pa",
+            MeasurementUnit::Percent => "%",
+            MeasurementUnit::PercentageRelativeHumidity => "%RH",
+            MeasurementUnit::Radian => "rad",
+            MeasurementUnit::Second => "s",
+            MeasurementUnit::Siemens => "S",
+            MeasurementUnit::Sievert => "Sv",
+            MeasurementUnit::Steradian => "sr",
+            MeasurementUnit::Tesla => "T",
+            MeasurementUnit::Volt => "V",
+            MeasurementUnit::Watt => "W",
+            MeasurementUnit::Weber => "Wb",
+            _ => return None,
+        })
+    }
+}
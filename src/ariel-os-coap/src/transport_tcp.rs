@@ -0,0 +1,381 @@
+//! Transport implementation for CoAP-over-TCP ([RFC 8323]).
+//!
+//! Without further configuration, connections are accepted on the default CoAP-over-TCP port. If a
+//! peer is configured through `CONFIG_COAP_TCP_PEER`, the device instead keeps a connection to that
+//! peer open (reconnecting whenever it is lost), and serves the peer's requests on it. This allows
+//! reaching devices behind NATs and firewalls that block incoming connections or UDP.
+//!
+//! [RFC 8323]: https://www.rfc-editor.org/rfc/rfc8323
+
+use core::net::SocketAddr;
+
+use ariel_os_log::{info, warn};
+use coap_message_implementations::inmemory;
+use embassy_futures::select::{Either, select};
+use embassy_net::tcp::TcpSocket;
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{Read as _, Write as _};
+
+use crate::observe::{self, ObservableHandler, Observations, Pending};
+
+/// Port on which connections are accepted if no peer is configured.
+const COAP_TCP_PORT: u16 = 5683;
+
+/// Largest size of the options and payload of a message that can be received or sent.
+///
+/// This matches the size embedded-nal-coap uses for CoAP-over-UDP, and is the size RFC 8323
+/// assumes before a CSM was received.
+const MAX_MESSAGE_SIZE: usize = 1152;
+
+/// Largest token length (RFC 8323 has the same limit as RFC 7252).
+const MAX_TOKEN_LEN: usize = observe::MAX_TOKEN_LEN;
+
+/// Largest size of a message header: the length and token length byte, up to four bytes of
+/// extended length, and the code.
+const MAX_HEADER_LEN: usize = 6;
+
+/// Idle time after which TCP keep-alive packets are sent, which keeps NAT bindings open.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Time after which an unresponsive connection is considered lost.
+const TIMEOUT: Duration = Duration::from_secs(180);
+/// Time to wait before connecting to the peer again after the connection was lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Peer to keep a connection open to; if empty, connections are accepted instead.
+const PEER: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_COAP_TCP_PEER",
+    "",
+    "address (IP address and port) of the peer to keep a CoAP-over-TCP connection open to",
+);
+
+// Signaling codes (RFC 8323 Section 11.1).
+const CSM: u8 = 0xe1;
+const PING: u8 = 0xe2;
+const PONG: u8 = 0xe3;
+const RELEASE: u8 = 0xe4;
+const ABORT: u8 = 0xe5;
+
+/// Options of the CSM sent at connection start: the Max-Message-Size option (2), set to
+/// [`MAX_MESSAGE_SIZE`].
+const CSM_OPTIONS: [u8; 3] = [0x22, 0x04, 0x80];
+const _CSM_OPTIONS_CHECK: () = assert!(MAX_MESSAGE_SIZE == 0x0480);
+
+/// Reasons for which a connection is terminated.
+enum ConnectionError {
+    /// The connection was closed or reset, or a read or write failed.
+    Io,
+    /// The peer sent a message that is not well-formed.
+    Protocol,
+    /// The peer sent a message exceeding the size announced in our CSM.
+    TooLarge,
+}
+
+impl From<embassy_net::tcp::Error> for ConnectionError {
+    fn from(_: embassy_net::tcp::Error) -> Self {
+        Self::Io
+    }
+}
+
+impl From<embedded_io_async::ReadExactError<embassy_net::tcp::Error>> for ConnectionError {
+    fn from(_: embedded_io_async::ReadExactError<embassy_net::tcp::Error>) -> Self {
+        Self::Io
+    }
+}
+
+/// Header of a received message.
+struct Header {
+    code: u8,
+    token: heapless::Vec<u8, MAX_TOKEN_LEN>,
+    /// Length of the options and payload.
+    length: usize,
+}
+
+/// Runs the CoAP handler on CoAP-over-TCP indefinitely.
+///
+/// Connections are served one at a time.
+///
+/// # Panics
+///
+/// This can only be run once, as it sets up a system wide CoAP handler.
+///
+/// Panics if `CONFIG_COAP_TCP_PEER` is not a valid socket address.
+pub(crate) async fn coap_run_tcp(mut handler: impl ObservableHandler) -> ! {
+    let peer: Option<SocketAddr> = (!PEER.is_empty()).then(|| {
+        PEER.parse()
+            .expect("CONFIG_COAP_TCP_PEER should be an IP address and port")
+    });
+
+    let stack = ariel_os_embassy::net::network_stack().await.unwrap();
+
+    stack.wait_config_up().await;
+
+    let mut rx_buffer = [0; 1500];
+    let mut tx_buffer = [0; 1500];
+
+    info!("Starting up CoAP server");
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_keep_alive(Some(KEEP_ALIVE));
+        socket.set_timeout(Some(TIMEOUT));
+
+        let connected = if let Some(peer) = peer {
+            socket.connect(peer).await.is_ok()
+        } else {
+            socket.accept(COAP_TCP_PORT).await.is_ok()
+        };
+
+        if connected {
+            info!("CoAP-over-TCP connection established.");
+            match serve_connection(&mut socket, &mut handler).await {
+                Ok(()) => info!("CoAP-over-TCP connection released."),
+                Err(ConnectionError::Io) => warn!("CoAP-over-TCP connection lost."),
+                Err(ConnectionError::Protocol) => {
+                    warn!("Aborting CoAP-over-TCP connection: malformed message.");
+                    let _ = send_message(&mut socket, ABORT, &[], &[]).await;
+                }
+                Err(ConnectionError::TooLarge) => {
+                    warn!("Aborting CoAP-over-TCP connection: message too large.");
+                    let _ = send_message(&mut socket, ABORT, &[], &[]).await;
+                }
+            }
+        } else {
+            warn!("CoAP-over-TCP connection could not be established.");
+        }
+
+        socket.close();
+        let _ = socket.flush().await;
+
+        if peer.is_some() {
+            Timer::after(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+/// Serves requests on an established connection until it is released or lost.
+///
+/// # Errors
+///
+/// This produces errors if the connection was lost, or the peer violated the protocol; in the
+/// latter case, the caller is expected to abort the connection.
+async fn serve_connection<H: ObservableHandler>(
+    socket: &mut TcpSocket<'_>,
+    handler: &mut H,
+) -> Result<(), ConnectionError> {
+    let mut request_buffer = [0u8; MAX_MESSAGE_SIZE];
+    let mut response_buffer = [0u8; MAX_MESSAGE_SIZE];
+    let mut observations = Observations::new();
+
+    // Each side's first message is a CSM (RFC 8323 Section 5.3).
+    send_message(socket, CSM, &[], &CSM_OPTIONS).await?;
+
+    loop {
+        // Only waiting for data to become available, so that no partially read message is lost
+        // when notifications are sent first.
+        if let Either::Second(pending) =
+            select(socket.wait_read_ready(), observe::wait_pending()).await
+        {
+            notify(
+                socket,
+                handler,
+                &mut observations,
+                &pending,
+                &mut request_buffer,
+                &mut response_buffer,
+            )
+            .await?;
+            continue;
+        }
+
+        let header = read_header(socket).await?;
+        let body = request_buffer
+            .get_mut(..header.length)
+            .ok_or(ConnectionError::TooLarge)?;
+        socket.read_exact(body).await?;
+
+        match header.code {
+            // Empty messages are ignored (RFC 8323 Section 3.4), and so is the peer's CSM: we do
+            // not send anything larger than the default Max-Message-Size.
+            0 | CSM | PONG => (),
+            PING => send_message(socket, PONG, &header.token, &[]).await?,
+            RELEASE | ABORT => return Ok(()),
+            // Requests
+            1..=0x1f => {
+                let request = inmemory::Message::new(header.code, &*body);
+                #[cfg(feature = "coap-server-blockwise")]
+                crate::requester::set(
+                    socket.remote_endpoint().map(crate::requester::Peer::Tcp),
+                    &request,
+                );
+                let (code, length) = observe::respond(handler, &request, &mut response_buffer);
+                let (response, _) = response_buffer.split_at(length);
+                // Connections are served one at a time, so the peer needs no identification.
+                if let Some(sequence) = observations.process_request(
+                    (),
+                    &header.token,
+                    header.code,
+                    body,
+                    code,
+                    handler.take_protected(),
+                ) {
+                    // The request is not needed any more, its buffer takes the response with the
+                    // Observe option.
+                    let (code, length) =
+                        observe::with_observe(code, response, sequence, &mut request_buffer);
+                    if !observe::is_success(code) {
+                        observations.cancel(&(), &header.token);
+                    }
+                    let (response, _) = request_buffer.split_at(length);
+                    send_message(socket, code, &header.token, response).await?;
+                } else {
+                    send_message(socket, code, &header.token, response).await?;
+                }
+            }
+            // We do not send requests, so responses are unexpected; unknown signals are ignored
+            // as well.
+            _ => (),
+        }
+    }
+}
+
+/// Sends notifications to all observations that are pending.
+///
+/// Observations whose request now results in an error response are removed after that response
+/// was sent (RFC 7641 Section 4.2).
+///
+/// # Errors
+///
+/// This produces errors if the connection was lost.
+async fn notify<H: ObservableHandler>(
+    socket: &mut TcpSocket<'_>,
+    handler: &mut H,
+    observations: &mut Observations<(), H::Protected>,
+    pending: &Pending,
+    notification_buffer: &mut [u8],
+    response_buffer: &mut [u8],
+) -> Result<(), ConnectionError> {
+    let mut next = 0;
+    while let Some(index) = observations.next_pending::<H>(pending, next) {
+        let Some(observation) = observations.get_mut(index) else {
+            break;
+        };
+        let notification =
+            observe::build_notification(handler, observation, response_buffer, notification_buffer);
+
+        let last = match notification {
+            Some(notification) => {
+                let (notification_body, _) = notification_buffer.split_at(notification.length);
+                send_message(
+                    socket,
+                    notification.code,
+                    &observation.token,
+                    notification_body,
+                )
+                .await?;
+                notification.last
+            }
+            None => true,
+        };
+
+        if last {
+            // The last observation moves into this index, so it is looked at next.
+            observations.remove(index);
+            next = index;
+        } else {
+            next = index + 1;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a message header from the connection.
+///
+/// # Errors
+///
+/// This produces errors if the connection was lost, or the token is too long.
+async fn read_header(socket: &mut TcpSocket<'_>) -> Result<Header, ConnectionError> {
+    let mut first = [0u8];
+    socket.read_exact(&mut first).await?;
+    let [first] = first;
+    let len_nibble = first >> 4;
+    let token_length = usize::from(first & 0x0f);
+
+    let mut extended = [0u8; 4];
+    let extended_length = match len_nibble {
+        13 => 1,
+        14 => 2,
+        15 => 4,
+        _ => 0,
+    };
+    let (extended, _) = extended.split_at_mut(extended_length);
+    socket.read_exact(extended).await?;
+    let extended = extended
+        .iter()
+        .fold(0usize, |acc, byte| (acc << 8) | usize::from(*byte));
+    let length = match len_nibble {
+        13 => extended + 13,
+        14 => extended + 269,
+        15 => extended.saturating_add(65805),
+        short => usize::from(short),
+    };
+
+    let mut code = [0u8];
+    socket.read_exact(&mut code).await?;
+    let [code] = code;
+
+    let mut token = heapless::Vec::new();
+    token
+        .resize_default(token_length)
+        .map_err(|()| ConnectionError::Protocol)?;
+    socket.read_exact(&mut token).await?;
+
+    Ok(Header {
+        code,
+        token,
+        length,
+    })
+}
+
+/// Sends a message with the given code, token, and options and payload.
+///
+/// # Errors
+///
+/// This produces errors if the connection was lost.
+async fn send_message(
+    socket: &mut TcpSocket<'_>,
+    code: u8,
+    token: &[u8],
+    body: &[u8],
+) -> Result<(), ConnectionError> {
+    let (len_nibble, extended, extended_length) = match body.len() {
+        short @ 0..13 => (short, 0, 0),
+        length @ 13..269 => (13, length - 13, 1),
+        length @ 269..65805 => (14, length - 269, 2),
+        length => (15, length - 65805, 4),
+    };
+    let extended = u32::try_from(extended)
+        .map_err(|_| ConnectionError::TooLarge)?
+        .to_be_bytes();
+    let (_, extended) = extended.split_at(extended.len() - extended_length);
+    let token_length = u8::try_from(token.len()).map_err(|_| ConnectionError::Protocol)?;
+
+    let mut header = heapless::Vec::<u8, { MAX_HEADER_LEN + MAX_TOKEN_LEN }>::new();
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "the length nibble is at most 15 by construction"
+    )]
+    header
+        .push(((len_nibble as u8) << 4) | token_length)
+        .map_err(|_| ConnectionError::Protocol)?;
+    header
+        .extend_from_slice(extended)
+        .map_err(|_| ConnectionError::Protocol)?;
+    header.push(code).map_err(|_| ConnectionError::Protocol)?;
+    header
+        .extend_from_slice(token)
+        .map_err(|_| ConnectionError::Protocol)?;
+
+    socket.write_all(&header).await?;
+    socket.write_all(body).await?;
+    Ok(())
+}
@@ -1,22 +1,59 @@
 //! Transport implementation for CoAP-over-UDP.
+//!
+//! Requests are served by [`embedded_nal_coap`], which also provides the CoAP client. As it does
+//! not send messages on its own, observations ([RFC 7641]) are implemented around it: the socket
+//! it runs on is wrapped in an [`ObservingSocket`], which sees the requests and responses that
+//! register observations, and sends the notifications between receiving requests.
+//!
+//! [RFC 7641]: https://www.rfc-editor.org/rfc/rfc7641
 
+use core::cell::RefCell;
 use core::net::{Ipv6Addr, SocketAddr};
 
 use ariel_os_embassy::cell::SameExecutorCell;
-use ariel_os_log::info;
-
+use ariel_os_log::{info, warn};
+use coap_message::{
+    MessageOption as _, MinimalWritableMessage, MutableWritableMessage, ReadableMessage as _,
+};
+use coap_message_implementations::inmemory;
+use embassy_futures::select::{Either3, select3};
 use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_nal_async::UnconnectedUdp as _;
+use rand_core::RngCore as _;
 use static_cell::StaticCell;
 
+use super::observe::{self, ObservableHandler, Observations, Pending};
 use super::udp_nal;
 use super::{CLIENT_READY, CONCURRENT_REQUESTS};
 
+/// Largest size of the options and payload of a message that can be sent as a notification.
+///
+/// This matches the size embedded-nal-coap uses.
+const MAX_MESSAGE_SIZE: usize = 1152;
+
+/// Largest size of a message header: the fixed header and the token.
+const MAX_HEADER_LEN: usize = 4 + observe::MAX_TOKEN_LEN;
+
+// Message types (RFC 7252 Section 3).
+const CON: u8 = 0;
+const NON: u8 = 1;
+const ACK: u8 = 2;
+const RST: u8 = 3;
+
+/// Initial time to wait for the acknowledgement of a notification, before the random factor
+/// (`ACK_RANDOM_FACTOR` of 1.5) is applied (RFC 7252 Section 4.8).
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Number of times a notification is sent again before the observation ends (RFC 7252 Section
+/// 4.8).
+const MAX_RETRANSMIT: u8 = 4;
+
 /// Runs the CoAP handler on CoAP-over-UDP indefinitely.
 ///
 /// # Panics
 ///
 /// This can only be run once, as it sets up a system wide CoAP handler.
-pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
+pub(crate) async fn coap_run_udp(handler: impl ObservableHandler) -> ! {
     static COAP: StaticCell<embedded_nal_coap::CoAPShared<CONCURRENT_REQUESTS>> = StaticCell::new();
 
     let stack = ariel_os_embassy::net::network_stack().await.unwrap();
@@ -46,10 +83,15 @@ pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
     info!("Starting up CoAP server");
 
     let local_any = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 5683);
-    let mut unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local_any)
+    let unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local_any)
         .await
         .unwrap();
 
+    // The handler is used both by embedded-nal-coap for requests, and by the socket for
+    // notifications; neither holds on to it across an await point.
+    let handler = RefCell::new(handler);
+    let mut socket = ObservingSocket::new(unconnected, &handler);
+
     info!("Server is ready.");
 
     let coap = COAP.init_with(embedded_nal_coap::CoAPShared::new);
@@ -67,11 +109,416 @@ pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
 
     server
         .run(
-            &mut unconnected,
-            &mut handler,
+            &mut socket,
+            &mut SharedHandler(&handler),
             &mut ariel_os_random::fast_rng(),
         )
         .await
         .expect("UDP error");
     unreachable!("embassy-net's sockets do not get closed (but embedded-nal-coap can't know that)");
 }
+
+/// A handler shared between embedded-nal-coap and the [`ObservingSocket`].
+struct SharedHandler<'a, H>(&'a RefCell<H>);
+
+impl<H: coap_handler::Handler> coap_handler::Handler for SharedHandler<'_, H> {
+    type RequestData = H::RequestData;
+    type ExtractRequestError = H::ExtractRequestError;
+    type BuildResponseError<M: MinimalWritableMessage> = H::BuildResponseError<M>;
+
+    fn extract_request_data<M: coap_message::ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        self.0.borrow_mut().extract_request_data(request)
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        self.0.borrow_mut().estimate_length(request)
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        self.0.borrow_mut().build_response(response, request)
+    }
+}
+
+/// Local and remote address of the peer that registered an observation.
+#[derive(Clone, Copy, PartialEq)]
+struct Endpoints {
+    local: SocketAddr,
+    remote: SocketAddr,
+}
+
+/// A confirmable notification that was not acknowledged yet.
+pub(crate) struct Unacknowledged {
+    message_id: u16,
+    /// Time at which the notification is sent again, or the observation ends.
+    deadline: Instant,
+    timeout: Duration,
+    retransmissions: u8,
+}
+
+/// A received request with an Observe option, kept until the response to it is sent.
+struct ObserveRequest {
+    endpoints: Endpoints,
+    token: heapless::Vec<u8, { observe::MAX_TOKEN_LEN }>,
+    code: u8,
+    body: heapless::Vec<u8, { observe::MAX_REQUEST_LEN }>,
+}
+
+/// A parsed CoAP-over-UDP message.
+struct Message<'a> {
+    message_type: u8,
+    code: u8,
+    message_id: u16,
+    token: &'a [u8],
+    /// The fixed header and the token.
+    header: &'a [u8],
+    /// The options and payload.
+    body: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Parses a message, returning `None` if it is not a well-formed CoAP message.
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let (fixed, rest) = message.split_first_chunk::<4>()?;
+        let [first, code, id_high, id_low] = *fixed;
+        if first >> 6 != 1 {
+            return None;
+        }
+        let token_length = usize::from(first & 0x0f);
+        let token = rest.get(..token_length)?;
+        let body = rest.get(token_length..)?;
+        let (header, _) = message.split_at(fixed.len() + token_length);
+        Some(Self {
+            message_type: (first >> 4) & 0x03,
+            code,
+            message_id: u16::from_be_bytes([id_high, id_low]),
+            token,
+            header,
+            body,
+        })
+    }
+
+    /// Returns true if the message is a response.
+    fn is_response(&self) -> bool {
+        self.code >> 5 >= 2
+    }
+}
+
+/// A socket for embedded-nal-coap that tracks observations and sends their notifications.
+///
+/// Requests with an Observe option are kept when they are received, and processed as soon as the
+/// response to them is sent: if that registers an observation, the Observe option is added to it.
+/// Acknowledgements and resets of notifications are processed, and not passed on.
+struct ObservingSocket<'a, H: ObservableHandler> {
+    socket: udp_nal::UnconnectedUdp<'a>,
+    handler: &'a RefCell<H>,
+    observations: Observations<Endpoints, H::Protected>,
+    request: Option<ObserveRequest>,
+    message_id: u16,
+    datagram: [u8; MAX_HEADER_LEN + MAX_MESSAGE_SIZE],
+    response_buffer: [u8; MAX_MESSAGE_SIZE],
+}
+
+impl<'a, H: ObservableHandler> ObservingSocket<'a, H> {
+    fn new(socket: udp_nal::UnconnectedUdp<'a>, handler: &'a RefCell<H>) -> Self {
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "any 16 bits of randomness are good as a start value"
+        )]
+        let message_id = ariel_os_random::fast_rng().next_u32() as u16;
+        Self {
+            socket,
+            handler,
+            observations: Observations::new(),
+            request: None,
+            message_id,
+            datagram: [0; MAX_HEADER_LEN + MAX_MESSAGE_SIZE],
+            response_buffer: [0; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    /// Processes a received message.
+    ///
+    /// Returns true if the message was an acknowledgement or reset of a notification, which is not
+    /// passed on to embedded-nal-coap.
+    fn process_received(&mut self, endpoints: Endpoints, message: &Message<'_>) -> bool {
+        match (message.message_type, message.code) {
+            (ACK | RST, 0) => {
+                let Some(index) = self.observations.position(0, |observation| {
+                    observation.peer == endpoints
+                        && observation
+                            .unacknowledged
+                            .as_ref()
+                            .is_some_and(|unacknowledged| {
+                                unacknowledged.message_id == message.message_id
+                            })
+                }) else {
+                    return false;
+                };
+                if message.message_type == ACK {
+                    if let Some(observation) = self.observations.get_mut(index) {
+                        observation.unacknowledged = None;
+                    }
+                } else {
+                    info!("Observer rejected a notification, ending observation.");
+                    self.observations.remove(index);
+                }
+                true
+            }
+            (_, 1..=0x1f) => {
+                let request = inmemory::Message::new(message.code, message.body);
+                #[cfg(feature = "coap-server-blockwise")]
+                crate::requester::set(
+                    Some(crate::requester::Peer::Udp(endpoints.remote)),
+                    &request,
+                );
+                let observe = request
+                    .options()
+                    .any(|option| option.number() == coap_numbers::option::OBSERVE);
+                // Requests that do not fit are served without being registered.
+                self.request = heapless::Vec::from_slice(message.token)
+                    .ok()
+                    .zip(heapless::Vec::from_slice(message.body).ok())
+                    .filter(|_| observe)
+                    .map(|(token, body)| ObserveRequest {
+                        endpoints,
+                        token,
+                        code: message.code,
+                        body,
+                    });
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Sends notifications to all observations that are pending.
+    async fn notify(&mut self, pending: &Pending) {
+        let mut next = 0;
+        while let Some(index) = self.observations.next_pending::<H>(pending, next) {
+            next = if self.send_notification(index, false).await {
+                index + 1
+            } else {
+                // The last observation moves into this index, so it is looked at next.
+                index
+            };
+        }
+    }
+
+    /// Sends notifications again whose acknowledgement timed out, and ends the observations
+    /// whose notifications were sent too often.
+    async fn retransmit(&mut self) {
+        let now = Instant::now();
+        let mut next = 0;
+        while let Some(index) = self.observations.position(next, |observation| {
+            observation
+                .unacknowledged
+                .as_ref()
+                .is_some_and(|unacknowledged| unacknowledged.deadline <= now)
+        }) {
+            next = if self.send_notification(index, true).await {
+                index + 1
+            } else {
+                index
+            };
+        }
+    }
+
+    /// Returns the time at which the next notification needs to be sent again.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.observations
+            .iter()
+            .filter_map(|observation| observation.unacknowledged.as_ref())
+            .map(|unacknowledged| unacknowledged.deadline)
+            .min()
+    }
+
+    /// Sends a notification for the observation at `index`.
+    ///
+    /// Instead of sending the same message again on `retransmission`, a new notification with the
+    /// latest state is sent with the retransmission counter and timeout of the unacknowledged one
+    /// (RFC 7641 Section 4.5.2). For the same reason, a new notification that becomes due while an
+    /// earlier one is unacknowledged takes over its retransmission state.
+    ///
+    /// Returns true if the observation is still registered afterwards.
+    async fn send_notification(&mut self, index: usize, retransmission: bool) -> bool {
+        let Some(observation) = self.observations.get_mut(index) else {
+            return false;
+        };
+        if retransmission
+            && observation
+                .unacknowledged
+                .as_ref()
+                .is_some_and(|unacknowledged| unacknowledged.retransmissions >= MAX_RETRANSMIT)
+        {
+            info!("Observer did not acknowledge notifications, ending observation.");
+            self.observations.remove(index);
+            return false;
+        }
+
+        let token_length = observation.token.len();
+        let (header, body) = self.datagram.split_at_mut(4 + token_length);
+        let Some(notification) = observe::build_notification(
+            &mut *self.handler.borrow_mut(),
+            observation,
+            &mut self.response_buffer,
+            body,
+        ) else {
+            self.observations.remove(index);
+            return false;
+        };
+
+        self.message_id = self.message_id.wrapping_add(1);
+        // Error responses end the observation, so there is no point in having them acknowledged.
+        let message_type = if notification.last { NON } else { CON };
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "tokens are at most 8 bytes long"
+        )]
+        let first = 0x40 | (message_type << 4) | token_length as u8;
+        let [id_high, id_low] = self.message_id.to_be_bytes();
+        let (fixed, token) = header.split_at_mut(4);
+        fixed.copy_from_slice(&[first, notification.code, id_high, id_low]);
+        token.copy_from_slice(&observation.token);
+
+        observation.unacknowledged = match observation.unacknowledged.take() {
+            _ if notification.last => None,
+            Some(unacknowledged) if retransmission => {
+                let timeout = unacknowledged.timeout * 2;
+                Some(Unacknowledged {
+                    message_id: self.message_id,
+                    deadline: Instant::now() + timeout,
+                    timeout,
+                    retransmissions: unacknowledged.retransmissions + 1,
+                })
+            }
+            Some(unacknowledged) => Some(Unacknowledged {
+                message_id: self.message_id,
+                ..unacknowledged
+            }),
+            None => {
+                // ACK_TIMEOUT to ACK_TIMEOUT * ACK_RANDOM_FACTOR
+                let timeout = ACK_TIMEOUT
+                    + Duration::from_millis(
+                        u64::from(ariel_os_random::fast_rng().next_u32())
+                            % (ACK_TIMEOUT.as_millis() / 2),
+                    );
+                Some(Unacknowledged {
+                    message_id: self.message_id,
+                    deadline: Instant::now() + timeout,
+                    timeout,
+                    retransmissions: 0,
+                })
+            }
+        };
+
+        let endpoints = observation.peer;
+        let length = 4 + token_length + notification.length;
+        let sent = match self.datagram.get(..length) {
+            Some(datagram) => self
+                .socket
+                .send(endpoints.local, endpoints.remote, datagram)
+                .await
+                .is_ok(),
+            None => false,
+        };
+
+        if !sent {
+            warn!("Notification could not be sent, ending observation.");
+        }
+        if !sent || notification.last {
+            self.observations.remove(index);
+            return false;
+        }
+        true
+    }
+}
+
+impl<H: ObservableHandler> embedded_nal_async::UnconnectedUdp for ObservingSocket<'_, H> {
+    type Error = udp_nal::Error;
+
+    async fn send(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        let endpoints = Endpoints { local, remote };
+        if let Some(message) = Message::parse(data)
+            && message.is_response()
+            && let Some(request) = self
+                .request
+                .take_if(|request| request.endpoints == endpoints && request.token == message.token)
+        {
+            let protected = self.handler.borrow_mut().take_protected();
+            if let Some(sequence) = self.observations.process_request(
+                endpoints,
+                &request.token,
+                request.code,
+                &request.body,
+                message.code,
+                protected,
+            ) {
+                let (header, body) = self.datagram.split_at_mut(message.header.len());
+                header.copy_from_slice(message.header);
+                let (code, length) =
+                    observe::with_observe(message.code, message.body, sequence, body);
+                if !observe::is_success(code) {
+                    self.observations.cancel(&endpoints, &request.token);
+                }
+                if let Some(header_code) = header.get_mut(1) {
+                    *header_code = code;
+                }
+                let length = message.header.len() + length;
+                if let Some(datagram) = self.datagram.get(..length) {
+                    return self.socket.send(local, remote, datagram).await;
+                }
+            }
+        }
+
+        self.socket.send(local, remote, data).await
+    }
+
+    async fn receive_into(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        loop {
+            let deadline = self.next_deadline();
+            let retransmission = async {
+                match deadline {
+                    Some(deadline) => Timer::at(deadline).await,
+                    None => core::future::pending().await,
+                }
+            };
+
+            match select3(
+                self.socket.receive_into(buffer),
+                observe::wait_pending(),
+                retransmission,
+            )
+            .await
+            {
+                Either3::First(received) => {
+                    let (length, local, remote) = received?;
+                    let endpoints = Endpoints { local, remote };
+                    let consumed = buffer
+                        .get(..length)
+                        .and_then(Message::parse)
+                        .is_some_and(|message| self.process_received(endpoints, &message));
+                    if !consumed {
+                        return Ok((length, local, remote));
+                    }
+                }
+                Either3::Second(pending) => self.notify(&pending).await,
+                Either3::Third(()) => self.retransmit().await,
+            }
+        }
+    }
+}
@@ -8,6 +8,12 @@ pub mod hwrng;
 pub mod identity;
 pub mod peripherals {}
 
+#[cfg(feature = "storage")]
+#[doc(hidden)]
+pub mod storage;
+
+mod time_driver;
+
 pub struct OptionalPeripherals {}
 
 #[must_use]
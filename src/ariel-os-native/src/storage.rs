@@ -0,0 +1,236 @@
+//! Provides a NOR flash emulation backed by a file, for use by the storage.
+//!
+//! The flash geometry is configured at build time:
+//!
+//! - `CONFIG_NATIVE_STORAGE_SIZE`: size of the emulated flash, in bytes.
+//! - `CONFIG_NATIVE_STORAGE_PAGE_SIZE`: size of an erasable page, in bytes.
+//!
+//! The following environment variables are read at runtime:
+//!
+//! - `ARIEL_NATIVE_STORAGE`: path of the backing file, created if it does not exist
+//!   (default: `ariel-os-storage.bin` in the current directory).
+//! - `ARIEL_NATIVE_STORAGE_POWER_LOSS_AFTER`: simulates a power loss during the write or erase
+//!   operation following the given number of operations: only part of it is performed, and the
+//!   process is aborted.
+//! - `ARIEL_NATIVE_STORAGE_BIT_FLIPS`: flips a pseudo-random bit in one out of the given number
+//!   of reads on average.
+//! - `ARIEL_NATIVE_STORAGE_SEED`: seed of the pseudo-random bit flips (default: `1`).
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read as _, Seek as _, SeekFrom, Write as _},
+};
+
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+    check_erase, check_read, check_write,
+};
+
+pub type Flash = BlockingAsync<FileFlash>;
+
+/// Size of the emulated flash.
+const SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_NATIVE_STORAGE_SIZE",
+    8 * 1024,
+    "size of the emulated flash"
+);
+
+/// Size of an erasable page of the emulated flash.
+const PAGE_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_NATIVE_STORAGE_PAGE_SIZE",
+    4 * 1024,
+    "page size of the emulated flash"
+);
+
+const ERASED: u8 = 0xff;
+
+/// Error returned by the emulated flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    /// The arguments are not properly aligned.
+    NotAligned,
+    /// The arguments are out of bounds.
+    OutOfBounds,
+    /// Accessing the backing file failed.
+    Io,
+}
+
+impl From<NorFlashErrorKind> for FlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Self::NotAligned,
+            NorFlashErrorKind::OutOfBounds => Self::OutOfBounds,
+            _ => Self::Io,
+        }
+    }
+}
+
+impl From<std::io::Error> for FlashError {
+    fn from(_: std::io::Error) -> Self {
+        Self::Io
+    }
+}
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Io => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// NOR flash emulated using a file.
+pub struct FileFlash {
+    file: File,
+    power_loss_after: Option<u64>,
+    bit_flips: Option<u64>,
+    rng_state: u64,
+}
+
+impl FileFlash {
+    /// Opens the backing file, filling it with erased bytes up to the flash size if needed.
+    fn open(path: &str) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len = usize::try_from(file.metadata()?.len()).unwrap_or(usize::MAX);
+        if len > SIZE {
+            return Err(std::io::Error::other(format!(
+                "file is larger than the flash size ({SIZE} bytes)"
+            )));
+        }
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&vec![ERASED; SIZE - len])?;
+
+        Ok(Self {
+            file,
+            power_loss_after: env_u64("ARIEL_NATIVE_STORAGE_POWER_LOSS_AFTER"),
+            bit_flips: env_u64("ARIEL_NATIVE_STORAGE_BIT_FLIPS").filter(|n| *n > 0),
+            rng_state: env_u64("ARIEL_NATIVE_STORAGE_SEED").unwrap_or(1).max(1),
+        })
+    }
+
+    /// Writes `bytes` at `offset`, simulating a power loss if configured.
+    fn write_through(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        self.file.seek(SeekFrom::Start(u64::from(offset)))?;
+
+        match self.power_loss_after {
+            Some(0) => {
+                let (written, _) = bytes.split_at(bytes.len() / 2);
+                self.file.write_all(written)?;
+                self.file.sync_all()?;
+                ariel_os_log::warn!("storage: simulating power loss");
+                std::process::abort();
+            }
+            Some(ref mut remaining) => *remaining -= 1,
+            None => {}
+        }
+
+        self.file.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Returns the next value of a xorshift pseudo-random number generator.
+    fn next_random(&mut self) -> u64 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        self.rng_state
+    }
+}
+
+impl ErrorType for FileFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for FileFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+
+        self.file.seek(SeekFrom::Start(u64::from(offset)))?;
+        self.file.read_exact(bytes)?;
+
+        if let Some(one_in) = self.bit_flips
+            && !bytes.is_empty()
+            && self.next_random().is_multiple_of(one_in)
+        {
+            let bit = usize::try_from(self.next_random()).unwrap_or_default() % (bytes.len() * 8);
+            if let Some(byte) = bytes.get_mut(bit / 8) {
+                *byte ^= 1 << (bit % 8);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl NorFlash for FileFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+
+        self.write_through(from, &vec![ERASED; (to - from) as usize])
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        let mut current = vec![0; bytes.len()];
+        self.file.seek(SeekFrom::Start(u64::from(offset)))?;
+        self.file.read_exact(&mut current)?;
+
+        // Writing can only clear bits, which also allows multiple writes.
+        for (current, byte) in current.iter_mut().zip(bytes) {
+            *current &= byte;
+        }
+
+        self.write_through(offset, &current)
+    }
+}
+
+// Writes only clearing bits can be repeated, as `sequential-storage` does to mark items as erased.
+impl MultiwriteNorFlash for FileFlash {}
+
+/// Returns the value of the given environment variable, parsed as an integer.
+///
+/// # Panics
+///
+/// Panics if the variable is set but is not an integer.
+fn env_u64(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => panic!("{name} must be an integer, got {value:?}: {e}"),
+    }
+}
+
+/// Opens the file-backed flash as configured in the environment.
+///
+/// # Panics
+///
+/// Panics if the backing file cannot be opened or has an unexpected size.
+pub fn init(_peripherals: &mut crate::OptionalPeripherals) -> Flash {
+    let path =
+        std::env::var("ARIEL_NATIVE_STORAGE").unwrap_or_else(|_| "ariel-os-storage.bin".to_owned());
+    ariel_os_log::info!("storage: using file {}", path.as_str());
+
+    match FileFlash::open(&path) {
+        Ok(flash) => BlockingAsync::new(flash),
+        Err(e) => panic!("Error opening storage file {path}: {e}"),
+    }
+}
@@ -0,0 +1,59 @@
+//! Provides the time driver.
+//!
+//! By default, time follows the host's monotonic clock.
+//! With the `simulated-time` feature, time is simulated instead, see [`simulated`].
+
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant as StdInstant,
+};
+
+#[cfg(feature = "simulated-time")]
+mod simulated;
+#[cfg(not(feature = "simulated-time"))]
+mod wall_clock;
+
+/// Wakes up the host thread handling the timer queue.
+#[derive(Debug)]
+struct Signaler {
+    mutex: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Signaler {
+    const fn new() -> Self {
+        Self {
+            mutex: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Waits until signaled, or until `until` is reached, if any.
+    fn wait(&self, until: Option<StdInstant>) {
+        let mut signaled = self.mutex.lock().unwrap();
+        while !*signaled {
+            match until {
+                Some(until) => {
+                    let now = StdInstant::now();
+                    if now >= until {
+                        break;
+                    }
+                    let (guard, timeout) =
+                        self.condvar.wait_timeout(signaled, until - now).unwrap();
+                    signaled = guard;
+                    if timeout.timed_out() {
+                        break;
+                    }
+                }
+                None => signaled = self.condvar.wait(signaled).unwrap(),
+            }
+        }
+        *signaled = false;
+    }
+
+    fn signal(&self) {
+        let mut signaled = self.mutex.lock().unwrap();
+        *signaled = true;
+        self.condvar.notify_one();
+    }
+}
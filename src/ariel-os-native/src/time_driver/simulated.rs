@@ -0,0 +1,81 @@
+//! Simulated time driver.
+//!
+//! Time starts at zero, and only advances while no thread is runnable, i.e., while all threads,
+//! including the ones running Embassy executors, are blocked.
+//! Time then jumps right to the next timer expiration, so that waiting for a timer takes no host
+//! time, and timing does not depend on the load of the host.
+//!
+//! Consequently, busy-waiting for time to pass, e.g., with `embassy_time::block_for()`, never
+//! returns, and blocking host calls that are not visible to the scheduler do not let time advance.
+
+use std::{sync::Mutex, thread};
+
+use embassy_time_driver::Driver;
+use embassy_time_queue_utils::Queue;
+
+use super::Signaler;
+
+struct TimeDriver {
+    signaler: Signaler,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Current simulated time, in ticks.
+    now: u64,
+    queue: Queue,
+    /// Whether the thread advancing time has been started.
+    started: bool,
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: TimeDriver = TimeDriver {
+    signaler: Signaler::new(),
+    inner: Mutex::new(Inner {
+        now: 0,
+        queue: Queue::new(),
+        started: false,
+    }),
+});
+
+impl Driver for TimeDriver {
+    fn now(&self) -> u64 {
+        self.inner.lock().unwrap().now
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &core::task::Waker) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.started {
+            inner.started = true;
+            thread::spawn(advance_thread);
+        }
+        if inner.queue.schedule_wake(at, waker) {
+            self.signaler.signal();
+        }
+    }
+}
+
+/// Wakes up the expired timers once all threads are blocked, and advances time to the next
+/// expiration if that did not make any thread runnable.
+fn advance_thread() {
+    loop {
+        ariel_os_threads::wait_for_idle();
+
+        let mut inner = DRIVER.inner.lock().unwrap();
+        let now = inner.now;
+        let next_expiration = inner.queue.next_expiration(now);
+
+        if !ariel_os_threads::is_idle() {
+            // Let the threads that have been woken up run first, they may schedule earlier
+            // timers.
+            continue;
+        }
+
+        if next_expiration == u64::MAX {
+            drop(inner);
+            // Nothing can happen until a timer is scheduled.
+            DRIVER.signaler.wait(None);
+        } else {
+            inner.now = next_expiration;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Time driver following the host's monotonic clock.
+//!
+//! This is based on the upstream `embassy-time` std driver.
+//! The state is protected by a critical section instead of a host mutex, so that a thread cannot
+//! be preempted while holding it with `single-core`.
+
+use core::cell::RefCell;
+use std::{
+    thread,
+    time::{Duration as StdDuration, Instant as StdInstant},
+};
+
+use critical_section::Mutex;
+use embassy_time_driver::Driver;
+use embassy_time_queue_utils::Queue;
+
+use super::Signaler;
+
+struct TimeDriver {
+    signaler: Signaler,
+    inner: Mutex<RefCell<Inner>>,
+}
+
+struct Inner {
+    zero_instant: Option<StdInstant>,
+    queue: Queue,
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: TimeDriver = TimeDriver {
+    signaler: Signaler::new(),
+    inner: Mutex::new(RefCell::new(Inner {
+        zero_instant: None,
+        queue: Queue::new(),
+    })),
+});
+
+impl Inner {
+    fn init(&mut self) -> StdInstant {
+        *self.zero_instant.get_or_insert_with(|| {
+            thread::spawn(alarm_thread);
+            StdInstant::now()
+        })
+    }
+}
+
+impl Driver for TimeDriver {
+    fn now(&self) -> u64 {
+        let zero = critical_section::with(|cs| self.inner.borrow_ref_mut(cs).init());
+        u64::try_from(zero.elapsed().as_micros()).unwrap_or(u64::MAX)
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &core::task::Waker) {
+        critical_section::with(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            inner.init();
+            if inner.queue.schedule_wake(at, waker) {
+                self.signaler.signal();
+            }
+        });
+    }
+}
+
+/// Wakes up the expired timers, and waits for the next expiration.
+fn alarm_thread() {
+    let zero = critical_section::with(|cs| DRIVER.inner.borrow_ref_mut(cs).init());
+    loop {
+        let now = DRIVER.now();
+        let next_expiration =
+            critical_section::with(|cs| DRIVER.inner.borrow_ref_mut(cs).queue.next_expiration(now));
+
+        // Avoid overflowing on far-away expirations, e.g., `u64::MAX` if there is none.
+        let until = zero
+            .checked_add(StdDuration::from_micros(next_expiration))
+            .unwrap_or_else(|| StdInstant::now() + StdDuration::from_secs(1));
+
+        DRIVER.signaler.wait(Some(until));
+    }
+}
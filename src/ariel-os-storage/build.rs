@@ -1,8 +1,26 @@
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
 const KIBIBYTES: u32 = 1024;
 
 fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    // Native uses an emulated flash, which does not need to be placed by the linker.
+    if is_in_current_contexts(&["native"]) {
+        let storage_size_total = u32_from_env_or("CONFIG_NATIVE_STORAGE_SIZE", 8 * KIBIBYTES);
+        let flash_page_size = u32_from_env_or("CONFIG_NATIVE_STORAGE_PAGE_SIZE", 4 * KIBIBYTES);
+        check_geometry(storage_size_total, flash_page_size);
+        write_page_count(out, storage_size_total / flash_page_size);
+
+        println!("cargo:rerun-if-env-changed=CARGO_CFG_CONTEXT");
+        println!("cargo:rerun-if-env-changed=CONFIG_NATIVE_STORAGE_SIZE");
+        println!("cargo:rerun-if-env-changed=CONFIG_NATIVE_STORAGE_PAGE_SIZE");
+        return;
+    }
+
     // NOTE(hal): values of `flash_page_size` from the datasheets, confirmed by HAL's constants.
     // Important: only homogeneous flash organizations are currently supported.
     // Trying to restrict the storage size to the subset of homogeneous flash would not work as it
@@ -31,11 +49,11 @@ fn main() {
         panic!("MCU not supported");
     };
 
-    // `sequential-storage` needs at least two flash pages.
-    assert!(storage_size_total / flash_page_size >= 2);
+    check_geometry(storage_size_total, flash_page_size);
+
+    write_page_count(out, storage_size_total / flash_page_size);
 
     // Put the linker script somewhere the linker can find it
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
     let mut storage_template = std::fs::read_to_string("storage.ld.in").unwrap();
     storage_template = storage_template.replace("${ALIGNMENT}", &format!("{flash_page_size}"));
@@ -57,3 +75,30 @@ fn is_in_current_contexts(contexts: &[&str]) -> bool {
     // Contexts cannot include commas.
     context_var.split(',').any(|c| contexts.contains(&c))
 }
+
+/// Checks that the storage is made of whole flash pages, as many as `sequential-storage` needs.
+fn check_geometry(storage_size_total: u32, flash_page_size: u32) {
+    assert!(
+        flash_page_size > 0 && storage_size_total.is_multiple_of(flash_page_size),
+        "storage size ({storage_size_total}) must be a multiple of the page size ({flash_page_size})"
+    );
+    // `sequential-storage` needs at least two flash pages.
+    assert!(
+        storage_size_total / flash_page_size >= 2,
+        "storage must span at least two pages of {flash_page_size} bytes"
+    );
+}
+
+/// Writes the number of flash pages used by storage, needed to size the caches.
+fn write_page_count(out: &Path, page_count: u32) {
+    std::fs::write(
+        out.join("page_count.rs"),
+        format!("/// Number of flash pages used by the global storage.\nconst PAGE_COUNT: usize = {page_count};\n"),
+    )
+    .unwrap();
+}
+
+/// Returns the value of the given environment variable, or `default` if it is not set.
+fn u32_from_env_or(name: &str, default: u32) -> u32 {
+    std::env::var(name).map_or(default, |value| value.parse().unwrap())
+}
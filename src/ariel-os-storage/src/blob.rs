@@ -0,0 +1,456 @@
+//! Storage of byte values larger than [`DATA_BUFFER_SIZE`](crate::DATA_BUFFER_SIZE), split into
+//! chunks stored as separate items.
+//!
+//! A blob is stored as a header item under its key, pointing to chunk items stored under keys
+//! derived from it. Chunks are written to one of two generations, alternating on each write, and
+//! the header is only updated once all chunks of the new generation have been written: a blob
+//! interrupted while being written keeps its previous value.
+use core::fmt::Write as _;
+
+use arrayvec::{ArrayString, ArrayVec};
+use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash};
+use sequential_storage::{
+    cache::{KeyCacheImpl, NoCache},
+    map::{SerializationError, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, MAX_KEY_LEN, PostcardValue, SchemaTag, Storage, storage::KEYS_BATCH};
+
+/// Maximum number of value bytes stored in a single chunk of a blob.
+pub const BLOB_CHUNK_SIZE: usize = 128;
+
+/// Maximum length of the key of a blob.
+///
+/// This leaves room for the suffix of the keys of its chunks.
+pub const MAX_BLOB_KEY_LEN: usize = MAX_KEY_LEN - CHUNK_SUFFIX_LEN;
+
+/// Separator between the key of a blob and the suffix identifying its chunks.
+pub(crate) const CHUNK_SEPARATOR: char = '\0';
+
+/// Size of the buffer needed to read or write a chunk item, including its key.
+pub(crate) const CHUNK_BUFFER_SIZE: usize = BLOB_CHUNK_SIZE + MAX_KEY_LEN + 16;
+
+/// Maximum length of the chunk key suffix: separator, generation, `.` and a `u16` index.
+const CHUNK_SUFFIX_LEN: usize = 8;
+
+/// Tag of the header items of blobs.
+const BLOB_TAG: SchemaTag = SchemaTag {
+    id: u32::MAX,
+    version: 0,
+};
+
+/// Header item of a blob.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BlobHeader {
+    len: u32,
+    generation: u8,
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Stores a byte value of any length, splitting it into chunks.
+    ///
+    /// It will overwrite the last blob that has the same key.
+    /// See [`Storage::blob_writer()`] to write a blob incrementally.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn insert_blob(
+        &mut self,
+        key: &str,
+        data: &[u8],
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        let mut writer = self.blob_writer(key).await?;
+        writer.write(data).await?;
+        writer.finish().await
+    }
+
+    /// Reads the blob associated with the given key into `buffer`.
+    ///
+    /// Returns the length of the blob, or `None` if no blob with the key is found.
+    /// Returns [`Error::BufferTooSmall`] if the blob does not fit into `buffer`.
+    /// See [`Storage::blob_reader()`] to read a blob incrementally.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn get_blob(
+        &mut self,
+        key: &str,
+        buffer: &mut [u8],
+    ) -> Result<Option<usize>, Error<<F as ErrorType>::Error>> {
+        let Some(mut reader) = self.blob_reader(key).await? else {
+            return Ok(None);
+        };
+        let len = reader.len();
+        let buffer = buffer
+            .get_mut(..len)
+            .ok_or(Error::BufferTooSmall { required: len })?;
+
+        let mut read = 0;
+        while read < len {
+            let Some(rest) = buffer.get_mut(read..) else {
+                break;
+            };
+            read += reader.read(rest).await?;
+        }
+        Ok(Some(len))
+    }
+
+    /// Returns a [`BlobWriter`] replacing the blob stored under the given key.
+    ///
+    /// The previous blob remains readable until [`BlobWriter::finish()`] returns.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn blob_writer(
+        &mut self,
+        key: &str,
+    ) -> Result<BlobWriter<'_, F, C>, Error<<F as ErrorType>::Error>> {
+        assert!(key.len() <= MAX_BLOB_KEY_LEN);
+
+        // A value that is not a blob gets overwritten as well.
+        let generation = match self.blob_header(key).await {
+            Ok(Some(header)) => (header.generation + 1) % 2,
+            Ok(None) | Err(Error::SchemaMismatch { .. }) => 0,
+            Err(err) => return Err(err),
+        };
+
+        Ok(BlobWriter {
+            storage: self,
+            key: ArrayString::from(key).unwrap(),
+            generation,
+            chunk: [0; BLOB_CHUNK_SIZE],
+            chunk_len: 0,
+            chunk_index: 0,
+            len: 0,
+        })
+    }
+
+    /// Returns a [`BlobReader`] over the blob stored under the given key.
+    ///
+    /// If no blob with the key is found, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn blob_reader(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<BlobReader<'_, F, C>>, Error<<F as ErrorType>::Error>> {
+        assert!(key.len() <= MAX_BLOB_KEY_LEN);
+
+        let Some(header) = self.blob_header(key).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(BlobReader {
+            storage: self,
+            key: ArrayString::from(key).unwrap(),
+            generation: header.generation,
+            len: header.len as usize,
+            position: 0,
+        }))
+    }
+
+    async fn blob_header(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<BlobHeader>, Error<<F as ErrorType>::Error>> {
+        let Some(header) = self.get_raw::<PostcardValue<BlobHeader>>(key).await? else {
+            return Ok(None);
+        };
+        if header.tag() != BLOB_TAG {
+            return Err(Error::SchemaMismatch {
+                expected: BLOB_TAG,
+                found: header.tag(),
+            });
+        }
+        Ok(Some(header.into_inner()))
+    }
+
+    /// Returns whether the given chunk key is not part of the current value of its blob.
+    async fn is_stale_chunk(
+        &mut self,
+        chunk_key: &str,
+    ) -> Result<bool, Error<<F as ErrorType>::Error>> {
+        let Some((key, suffix)) = chunk_key.split_once(CHUNK_SEPARATOR) else {
+            return Ok(false);
+        };
+        let header = match self.blob_header(key).await {
+            Ok(header) => header,
+            Err(Error::SchemaMismatch { .. }) => None,
+            Err(err) => return Err(err),
+        };
+        let Some(header) = header else {
+            return Ok(true);
+        };
+
+        let chunk = suffix.split_once('.').and_then(|(generation, index)| {
+            Some((generation.parse::<u8>().ok()?, index.parse::<usize>().ok()?))
+        });
+        let chunk_count = (header.len as usize).div_ceil(BLOB_CHUNK_SIZE);
+        Ok(chunk.is_none_or(|(generation, index)| {
+            generation != header.generation || index >= chunk_count
+        }))
+    }
+}
+
+impl<F: MultiwriteNorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Deletes a blob and all its chunks from flash.
+    ///
+    /// <div class="warning">
+    /// This is really slow!
+    ///
+    /// See [`Storage::remove()`], which is called for every chunk.
+    /// </div>
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn remove_blob(&mut self, key: &str) -> Result<(), Error<<F as ErrorType>::Error>> {
+        assert!(key.len() <= MAX_BLOB_KEY_LEN);
+
+        // Removing the header first makes the blob disappear at once.
+        self.remove(key).await?;
+        self.remove_stale_chunks(key).await
+    }
+
+    /// Deletes the chunks stored under the given blob key that are not part of its current value.
+    ///
+    /// [`BlobWriter::finish()`] leaves the chunks of the previous value behind, as does a write
+    /// interrupted by power loss. The global [`insert_blob()`](crate::insert_blob) calls this.
+    ///
+    /// <div class="warning">
+    /// This is really slow!
+    ///
+    /// See [`Storage::remove()`], which is called for every stale chunk.
+    /// </div>
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_BLOB_KEY_LEN`.
+    pub async fn remove_stale_chunks(
+        &mut self,
+        key: &str,
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        assert!(key.len() <= MAX_BLOB_KEY_LEN);
+
+        let mut chunk_prefix = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+        chunk_prefix.push(CHUNK_SEPARATOR);
+        self.remove_stale_chunks_with_prefix(&chunk_prefix).await
+    }
+
+    /// Deletes the chunks of all blobs that are not part of their current value.
+    ///
+    /// This reclaims the flash used by blob writes interrupted by power loss, when the blob has
+    /// not been written again since.
+    ///
+    /// <div class="warning">
+    /// This is really slow!
+    ///
+    /// The flash is read once per [`KEYS_BATCH`] chunks, and the header of the blob of each chunk
+    /// is read as well. See [`Storage::remove()`], which is called for every stale chunk.
+    /// </div>
+    pub async fn remove_all_stale_chunks(&mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        self.remove_stale_chunks_with_prefix("").await
+    }
+
+    /// Deletes the stale chunks whose keys start with `prefix`, of any blob if it is empty.
+    pub(crate) async fn remove_stale_chunks_with_prefix(
+        &mut self,
+        prefix: &str,
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        // Keys are returned in the order of their items in flash, which removing other items
+        // does not change: chunks found to be current are skipped by the following passes.
+        let mut current = 0;
+        loop {
+            let mut candidates = ArrayVec::<ArrayString<MAX_KEY_LEN>, KEYS_BATCH>::new();
+            let mut keys = self.keys_with_prefix(prefix);
+            let mut skipped = 0;
+            while !candidates.is_full() {
+                let Some((key, _)) = keys.next_item().await? else {
+                    break;
+                };
+                if !key.contains(CHUNK_SEPARATOR) {
+                    continue;
+                }
+                if skipped < current {
+                    skipped += 1;
+                    continue;
+                }
+                candidates.push(key);
+            }
+            if candidates.is_empty() {
+                return Ok(());
+            }
+
+            for chunk_key in candidates {
+                if self.is_stale_chunk(&chunk_key).await? {
+                    self.remove(&chunk_key).await?;
+                } else {
+                    current += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Writer of a blob, returned by [`Storage::blob_writer()`].
+///
+/// Data is buffered until a full chunk is available.
+/// The blob is only replaced once [`BlobWriter::finish()`] is called.
+pub struct BlobWriter<'s, F, C = NoCache> {
+    storage: &'s mut Storage<F, C>,
+    key: ArrayString<MAX_KEY_LEN>,
+    generation: u8,
+    chunk: [u8; BLOB_CHUNK_SIZE],
+    chunk_len: usize,
+    chunk_index: u16,
+    len: u32,
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> BlobWriter<'_, F, C> {
+    /// Appends data to the blob.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), Error<<F as ErrorType>::Error>> {
+        while !data.is_empty() {
+            let free = self.chunk.get_mut(self.chunk_len..).unwrap_or_default();
+            let (head, tail) = data.split_at(free.len().min(data.len()));
+            free.iter_mut().zip(head).for_each(|(dst, src)| *dst = *src);
+            self.chunk_len += head.len();
+            data = tail;
+
+            if self.chunk_len == BLOB_CHUNK_SIZE {
+                self.flush_chunk().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining buffered data and replaces the previous blob.
+    ///
+    /// The chunks of the previous blob are left in flash, see
+    /// [`Storage::remove_stale_chunks()`].
+    pub async fn finish(mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        if self.chunk_len > 0 {
+            self.flush_chunk().await?;
+        }
+
+        let header = BlobHeader {
+            len: self.len,
+            generation: self.generation,
+        };
+        self.storage
+            .insert_raw(&self.key, PostcardValue::tagged(header, BLOB_TAG))
+            .await
+    }
+
+    async fn flush_chunk(&mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        let chunk_key = chunk_key(&self.key, self.generation, self.chunk_index);
+        let chunk = ChunkBytes(self.chunk.get(..self.chunk_len).unwrap_or_default());
+        let mut data_buffer = [0; CHUNK_BUFFER_SIZE];
+        self.storage
+            .store_with_buffer(&chunk_key, &chunk, &mut data_buffer)
+            .await?;
+
+        #[expect(clippy::cast_possible_truncation)]
+        let chunk_len = self.chunk_len as u32;
+        self.len += chunk_len;
+        self.chunk_len = 0;
+        self.chunk_index = self
+            .chunk_index
+            .checked_add(1)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        Ok(())
+    }
+}
+
+/// Reader of a blob, returned by [`Storage::blob_reader()`].
+pub struct BlobReader<'s, F, C = NoCache> {
+    storage: &'s mut Storage<F, C>,
+    key: ArrayString<MAX_KEY_LEN>,
+    generation: u8,
+    len: usize,
+    position: usize,
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> BlobReader<'_, F, C> {
+    /// Returns the length of the blob in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the blob is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads data from the blob into `buffer`, returning how many bytes were read.
+    ///
+    /// Returns `0` once the end of the blob has been reached.
+    /// Returns [`Error::Corrupted`] if a chunk of the blob is missing.
+    pub async fn read(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error<<F as ErrorType>::Error>> {
+        let mut data_buffer = [0; CHUNK_BUFFER_SIZE];
+        let mut read = 0;
+
+        while self.position < self.len {
+            let Some(dst) = buffer.get_mut(read..).filter(|dst| !dst.is_empty()) else {
+                break;
+            };
+
+            #[expect(clippy::cast_possible_truncation)]
+            let chunk_index = (self.position / BLOB_CHUNK_SIZE) as u16;
+            let chunk_key = chunk_key(&self.key, self.generation, chunk_index);
+            let Some(ChunkBytes(chunk)) = self
+                .storage
+                .fetch_with_buffer(&chunk_key, &mut data_buffer)
+                .await?
+            else {
+                return Err(Error::Corrupted);
+            };
+            let src = chunk
+                .get(self.position % BLOB_CHUNK_SIZE..)
+                .filter(|src| !src.is_empty())
+                .ok_or(Error::Corrupted)?;
+
+            let len = src.len().min(dst.len());
+            dst.iter_mut().zip(src).for_each(|(dst, src)| *dst = *src);
+            read += len;
+            self.position += len;
+        }
+
+        Ok(read)
+    }
+}
+
+/// Returns the key of the given chunk of a blob.
+fn chunk_key(key: &str, generation: u8, index: u16) -> ArrayString<MAX_KEY_LEN> {
+    let mut chunk_key = ArrayString::new();
+    // Cannot fail as the blob key length is checked when creating readers and writers.
+    let _ = write!(chunk_key, "{key}{CHUNK_SEPARATOR}{generation}.{index}");
+    chunk_key
+}
+
+/// The raw bytes of a chunk, or of an already serialized value.
+pub(crate) struct ChunkBytes<'d>(pub(crate) &'d [u8]);
+
+impl<'d> Value<'d> for ChunkBytes<'d> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        buffer
+            .get_mut(..self.0.len())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(self.0);
+        Ok(self.0.len())
+    }
+
+    fn deserialize_from(buffer: &'d [u8]) -> Result<(Self, usize), SerializationError> {
+        Ok((Self(buffer), buffer.len()))
+    }
+}
@@ -0,0 +1,218 @@
+//! Encrypted and authenticated namespaces of the global storage.
+use ariel_os_hal::hal::storage::{Flash, FlashError};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit as _, Nonce, Tag, aead::AeadInPlace as _};
+use hkdf::Hkdf;
+use rand_core::RngCore as _;
+use sequential_storage::map::SerializationError;
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::Sha256;
+
+use crate::{
+    DATA_BUFFER_SIZE, Error, GlobalCache, Namespace, OS_NAMESPACE, SchemaTag, Storage,
+    blob::CHUNK_BUFFER_SIZE,
+    lock,
+    postcard_value::{TaggedBytes, deserialize_value, serialize_value},
+};
+
+/// Length of the root key and of the derived keys.
+const KEY_LEN: usize = 32;
+/// Length of the nonce stored in front of each encrypted value.
+const NONCE_LEN: usize = 12;
+/// Length of the authentication tag stored after each encrypted value.
+const TAG_LEN: usize = 16;
+
+/// Key of the root key in the namespace of the OS, stored in plaintext.
+const ROOT_KEY_KEY: &str = "root-key";
+
+/// Tag of encrypted values.
+const ENCRYPTED_TAG: SchemaTag = SchemaTag {
+    id: u32::MAX - 1,
+    version: 0,
+};
+
+/// Handle on a [`Namespace`] whose values are encrypted and authenticated.
+///
+/// Values are encrypted using ChaCha20-Poly1305, with a key derived from a random root key
+/// generated on first use and from the device identifier, if available.
+/// The key of each value is authenticated as well, so that values cannot be swapped between keys.
+/// Values that have been tampered with or corrupted are not decoded, and [`Error::Tampered`] is
+/// returned instead.
+///
+/// <div class="warning">
+/// This is obfuscation, not confidentiality: the root key is stored in plaintext in the same
+/// flash, under `ariel-os/root-key`, and the device identifier is not secret.
+/// Anyone able to read the entire flash can decrypt the values, and forge values that
+/// authenticate.
+/// It only protects values against accidental corruption, casual inspection of partial dumps,
+/// and being swapped between keys.
+/// </div>
+///
+/// Encrypted values take 28 bytes more than plaintext ones, and have to fit in
+/// [`DATA_BUFFER_SIZE`] once serialized.
+///
+/// Example:
+///
+/// ```ignore
+/// const SECRETS: EncryptedNamespace = EncryptedNamespace::new("my-app-secrets");
+///
+/// SECRETS.insert("api-token", [0u8; 16]).await.unwrap();
+/// let token: Option<[u8; 16]> = SECRETS.get("api-token").await.unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptedNamespace {
+    namespace: Namespace,
+}
+
+impl EncryptedNamespace {
+    /// Creates a new [`EncryptedNamespace`] handle.
+    ///
+    /// See [`Namespace::new()`].
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            namespace: Namespace::new(name),
+        }
+    }
+
+    /// Limits the flash space used by the values of this namespace to `quota` bytes.
+    ///
+    /// See [`Namespace::with_quota()`].
+    #[must_use]
+    pub const fn with_quota(self, quota: u32) -> Self {
+        Self {
+            namespace: self.namespace.with_quota(quota),
+        }
+    }
+
+    /// Returns the underlying [`Namespace`], e.g., to list, remove or clear its keys.
+    ///
+    /// Reading encrypted values through it returns [`Error::SchemaMismatch`].
+    #[must_use]
+    pub const fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// Encrypts and stores a key-value pair into this namespace.
+    ///
+    /// It will overwrite the last value that has the same key.
+    pub async fn insert<V: Serialize>(&self, key: &str, value: V) -> Result<(), Error<FlashError>> {
+        let prefixed_key = self.namespace.key(key);
+        let mut s = lock().await;
+        let cipher = self.cipher(&mut s).await?;
+
+        let mut buffer = [0; NONCE_LEN + DATA_BUFFER_SIZE + TAG_LEN];
+        let (nonce, rest) = buffer.split_at_mut(NONCE_LEN);
+        ariel_os_random::crypto_rng().fill_bytes(nonce);
+        let (payload, tag) = rest.split_at_mut(DATA_BUFFER_SIZE);
+
+        let plaintext = serialize_value(&value, payload)?;
+        let plaintext_len = plaintext.len();
+        let computed_tag = cipher
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), prefixed_key.as_bytes(), plaintext)
+            .map_err(|_| SerializationError::InvalidData)?;
+        tag.copy_from_slice(&computed_tag);
+
+        // Moves the tag right after the ciphertext.
+        let sealed_len = NONCE_LEN + plaintext_len + TAG_LEN;
+        buffer.copy_within(NONCE_LEN + DATA_BUFFER_SIZE.., NONCE_LEN + plaintext_len);
+        let bytes = buffer
+            .get(..sealed_len)
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        let record = TaggedBytes {
+            tag: ENCRYPTED_TAG,
+            bytes,
+        };
+        self.namespace
+            .insert_in(&mut s, key, record, &mut [0; CHUNK_BUFFER_SIZE])
+            .await
+    }
+
+    /// Gets and decrypts the last stored value from this namespace that is associated with the
+    /// given key.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    /// If the value fails authentication, [`Error::Tampered`] is returned.
+    /// If the value was not stored encrypted, [`Error::SchemaMismatch`] is returned.
+    pub async fn get<V: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, Error<FlashError>> {
+        let prefixed_key = self.namespace.key(key);
+        let mut s = lock().await;
+        let cipher = self.cipher(&mut s).await?;
+
+        let mut data_buffer = [0; CHUNK_BUFFER_SIZE];
+        let Some(record) = s
+            .fetch_with_buffer::<TaggedBytes<'_>>(&prefixed_key, &mut data_buffer)
+            .await?
+        else {
+            return Ok(None);
+        };
+        if record.tag != ENCRYPTED_TAG {
+            return Err(Error::SchemaMismatch {
+                expected: ENCRYPTED_TAG,
+                found: record.tag,
+            });
+        }
+
+        let mut buffer = [0; NONCE_LEN + DATA_BUFFER_SIZE + TAG_LEN];
+        let sealed = buffer
+            .get_mut(..record.bytes.len())
+            .ok_or(Error::Tampered)?;
+        sealed.copy_from_slice(record.bytes);
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(Error::Tampered);
+        }
+
+        let (nonce, rest) = sealed.split_at_mut(NONCE_LEN);
+        let (payload, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+        cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(nonce),
+                prefixed_key.as_bytes(),
+                payload,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| Error::Tampered)?;
+
+        Ok(Some(deserialize_value(payload)?))
+    }
+
+    /// Deletes an item from this namespace.
+    ///
+    /// See [`Namespace::remove()`].
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`.
+    #[cfg(not(context = "stm32"))]
+    pub async fn remove(&self, key: &str) -> Result<(), Error<FlashError>> {
+        self.namespace.remove(key).await
+    }
+
+    /// Returns the cipher of this namespace, generating the root key if needed.
+    async fn cipher(
+        &self,
+        storage: &mut Storage<Flash, GlobalCache>,
+    ) -> Result<ChaCha20Poly1305, Error<FlashError>> {
+        let root_key_key = OS_NAMESPACE.key(ROOT_KEY_KEY);
+        let root_key = if let Some(root_key) = storage.get::<[u8; KEY_LEN]>(&root_key_key).await? {
+            root_key
+        } else {
+            ariel_os_log::info!("storage: generating root key");
+            let mut root_key = [0; KEY_LEN];
+            ariel_os_random::crypto_rng().fill_bytes(&mut root_key);
+            storage.insert(&root_key_key, root_key).await?;
+            root_key
+        };
+
+        // Binds the keys to this device, if it has an identifier.
+        let device_id = ariel_os_identity::device_id_bytes().ok();
+        let salt = device_id.as_ref().map(AsRef::as_ref);
+
+        let mut key = [0; KEY_LEN];
+        Hkdf::<Sha256>::new(salt, &root_key)
+            .expand(self.namespace.name().as_bytes(), &mut key)
+            .map_err(|_| SerializationError::InvalidData)?;
+
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
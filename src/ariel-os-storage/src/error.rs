@@ -0,0 +1,46 @@
+//! Errors returned by the storage API.
+use sequential_storage::map::SerializationError;
+
+use crate::schema::SchemaTag;
+
+/// Error type of storage operations.
+#[derive(Debug, PartialEq)]
+pub enum Error<E> {
+    /// The underlying [`sequential_storage`] map returned an error.
+    Storage(sequential_storage::Error<E>),
+    /// The stored value carries a different [`SchemaTag`] than the requested type, and could
+    /// not be migrated.
+    SchemaMismatch {
+        /// Tag of the requested type.
+        expected: SchemaTag,
+        /// Tag found in storage.
+        found: SchemaTag,
+    },
+    /// The provided buffer is too small for the stored value.
+    BufferTooSmall {
+        /// Size of the stored value.
+        required: usize,
+    },
+    /// The stored value is inconsistent, e.g., a chunk of a blob is missing.
+    Corrupted,
+    /// An encrypted value failed authentication: it was tampered with, corrupted, or written with
+    /// another key.
+    Tampered,
+    /// The operation is not supported by the flash, e.g., removing items through a transaction
+    /// on flash not implementing `MultiwriteNorFlash`.
+    Unsupported,
+    /// Storing the value would exceed the quota of its [`Namespace`](crate::Namespace).
+    QuotaExceeded,
+}
+
+impl<E> From<sequential_storage::Error<E>> for Error<E> {
+    fn from(err: sequential_storage::Error<E>) -> Self {
+        Self::Storage(err)
+    }
+}
+
+impl<E> From<SerializationError> for Error<E> {
+    fn from(err: SerializationError) -> Self {
+        Self::Storage(sequential_storage::Error::SerializationError(err))
+    }
+}
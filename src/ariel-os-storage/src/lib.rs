@@ -1,16 +1,42 @@
 //! Provides key-value pair persistent storage on flash.
 //!
-//! Currently the same type used for serializing must be used for deserializing.
-//! While not doing so won't cause unsafety, it might return garbage data, or panic.
+//! Values stored using [`insert()`] are untagged and stored as plain Postcard, as in earlier
+//! versions: [`get()`] does not check their type, so the same type used for serializing must be
+//! used for deserializing. While not doing so won't cause unsafety, it might return garbage data.
+//! [`get()`] only rejects values stored with a [`SchemaTag`].
+//!
+//! Types implementing [`Versioned`] can instead be stored using [`insert_versioned()`]:
+//! reading them back using [`get_versioned()`] checks their type identifier, returning
+//! [`Error::SchemaMismatch`] on mismatch, and migrates records written with older schema versions.
+//!
+//! All functions return the [`Error`] of this crate. Up to Ariel OS 0.5, they returned the error
+//! of `sequential-storage`, which is now wrapped in [`Error::Storage`].
+//!
+//! Byte values larger than [`DATA_BUFFER_SIZE`] can be stored as blobs, split into chunks, using
+//! [`insert_blob()`] and [`get_blob()`], or incrementally through [`Storage::blob_writer()`] and
+//! [`Storage::blob_reader()`].
+//!
+//! Components sharing the global storage should use their own [`Namespace`] to avoid collisions.
+//! Related values can be updated all-or-nothing using a [`Transaction`].
+//! With the `encryption` feature, values of an [`EncryptedNamespace`] are encrypted and
+//! authenticated.
 
 #![cfg_attr(not(test), no_std)]
 #![deny(missing_docs)]
-#![expect(unsafe_code)]
+// Native does not read the flash range from linker symbols.
+#![cfg_attr(not(context = "native"), expect(unsafe_code))]
 // TODO: overhaul errors
 #![expect(clippy::missing_errors_doc)]
 
+mod blob;
+#[cfg(feature = "encryption")]
+mod encrypted;
+mod error;
+mod namespace;
 mod postcard_value;
+mod schema;
 mod storage;
+mod transaction;
 
 use core::ops::Range;
 
@@ -24,11 +50,49 @@ use embassy_sync::{
     once_lock::OnceLock,
 };
 
+pub use blob::{BLOB_CHUNK_SIZE, BlobReader, BlobWriter, MAX_BLOB_KEY_LEN};
+#[cfg(feature = "encryption")]
+pub use encrypted::EncryptedNamespace;
+pub use namespace::Namespace;
 pub use storage::*;
+pub use transaction::{TRANSACTION_SIZE, Transaction};
+
+#[cfg(any(feature = "cache-page-state", feature = "cache-key-pointer"))]
+include!(concat!(env!("OUT_DIR"), "/page_count.rs"));
+
+/// Number of keys whose location is cached with the `cache-key-pointer` feature.
+#[cfg(feature = "cache-key-pointer")]
+const CACHE_KEYS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_STORAGE_CACHE_KEYS",
+    8,
+    "number of storage keys whose location is cached"
+);
+
+/// Cache used by the global storage, selected through Cargo features.
+#[cfg(feature = "cache-key-pointer")]
+pub type GlobalCache = sequential_storage::cache::KeyPointerCache<
+    PAGE_COUNT,
+    arrayvec::ArrayString<MAX_KEY_LEN>,
+    CACHE_KEYS,
+>;
+/// Cache used by the global storage, selected through Cargo features.
+#[cfg(all(feature = "cache-page-state", not(feature = "cache-key-pointer")))]
+pub type GlobalCache = sequential_storage::cache::PageStateCache<PAGE_COUNT>;
+/// Cache used by the global storage, selected through Cargo features.
+#[cfg(not(any(feature = "cache-page-state", feature = "cache-key-pointer")))]
+pub type GlobalCache = sequential_storage::cache::NoCache;
 
-static STORAGE: OnceLock<Mutex<CriticalSectionRawMutex, Storage<Flash>>> = OnceLock::new();
+static STORAGE: OnceLock<Mutex<CriticalSectionRawMutex, Storage<Flash, GlobalCache>>> =
+    OnceLock::new();
 
-const MARKER_KEY: &str = "ARIEL_INIT_MARK";
+/// Namespace of the values stored by the OS itself.
+const OS_NAMESPACE: Namespace = Namespace::new("ariel-os");
+
+const MARKER_KEY: &str = "init-mark";
+/// Key of the marker before it was moved into the [`OS_NAMESPACE`].
+const LEGACY_MARKER_KEY: &str = "ARIEL_INIT_MARK";
+// Changing this erases the storage of deployed devices on upgrade; only do so when the on-flash
+// layout becomes incompatible. Untagged values keep their earlier layout, see `PostcardValue`.
 const MARKER_VALUE: u8 = 0;
 
 /// Gets a [`Range`] from the linker that can be used for a global [`Storage`].
@@ -37,6 +101,7 @@ const MARKER_VALUE: u8 = 0;
 /// This function is also the place to configure a platform dependent `OFFSET`,
 /// which configures an offset between the linker flash address map and the
 /// flash driver address map.
+#[cfg(not(context = "native"))]
 fn flash_range_from_linker() -> Range<u32> {
     #[cfg(all(context = "nrf", not(context = "nrf5340-net")))]
     const OFFSET: usize = 0x0;
@@ -64,13 +129,32 @@ fn flash_range_from_linker() -> Range<u32> {
     start..end
 }
 
+/// Gets a [`Range`] covering the entire emulated flash on native.
+#[cfg(context = "native")]
+fn flash_range_from_flash(flash: &Flash) -> Range<u32> {
+    use embedded_storage_async::nor_flash::ReadNorFlash as _;
+
+    #[expect(clippy::cast_possible_truncation)]
+    let end = flash.capacity() as u32;
+
+    0..end
+}
+
 fn init_(p: &mut OptionalPeripherals) {
     use ariel_os_log::info;
-    let flash_range = flash_range_from_linker();
-    info!("storage: using flash range {:?}", &flash_range);
 
     let flash = flash_init(p);
-    let _ = STORAGE.init(Mutex::new(Storage::new(flash, flash_range)));
+
+    #[cfg(not(context = "native"))]
+    let flash_range = flash_range_from_linker();
+    #[cfg(context = "native")]
+    let flash_range = flash_range_from_flash(&flash);
+    info!("storage: using flash range {:?}", &flash_range);
+    let _ = STORAGE.init(Mutex::new(Storage::with_cache(
+        flash,
+        flash_range,
+        GlobalCache::new(),
+    )));
 }
 
 /// Initializes the global storage.
@@ -79,7 +163,7 @@ fn init_(p: &mut OptionalPeripherals) {
 ///
 /// # Panics
 ///
-/// Panics when initializing the flash fails.
+/// Panics when initializing the flash or completing an interrupted transaction fails.
 #[doc(hidden)]
 pub async fn init(p: &mut OptionalPeripherals) {
     init_(p);
@@ -92,16 +176,29 @@ pub async fn init(p: &mut OptionalPeripherals) {
     embassy_time::block_for(embassy_time::Duration::from_millis(10));
 
     // Use a marker to ensure that this storage is initialized.
-    if Ok(Some(MARKER_VALUE)) != get::<u8>(MARKER_KEY).await {
-        ariel_os_log::info!("storage: initializing");
-        erase_all().await.unwrap();
+    if Ok(Some(MARKER_VALUE)) != OS_NAMESPACE.get::<u8>(MARKER_KEY).await {
+        if Ok(Some(MARKER_VALUE)) == get::<u8>(LEGACY_MARKER_KEY).await {
+            ariel_os_log::info!("storage: migrating marker");
+            migrate_marker().await.unwrap();
+        } else {
+            ariel_os_log::info!("storage: initializing");
+            erase_all().await.unwrap();
+        }
     }
+
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`.
+    #[cfg(not(context = "stm32"))]
+    lock().await.recover().await.unwrap();
+    #[cfg(context = "stm32")]
+    lock().await.recover_inserts().await.unwrap();
 }
 
 /// Stores a key-value pair into flash memory.
 ///
 /// It will overwrite the last value that has the same key.
-pub async fn insert<'d, V>(key: &str, value: V) -> Result<(), sequential_storage::Error<FlashError>>
+/// The value is stored untagged: reading it back as another type is not detected, see
+/// [`insert_versioned()`] for values whose type is checked.
+pub async fn insert<'d, V>(key: &str, value: V) -> Result<(), Error<FlashError>>
 where
     V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
 {
@@ -111,15 +208,33 @@ where
 /// Gets the last stored value from the flash that is associated with the given key.
 ///
 /// Note: Always [`get()`] the same value type that was [`insert()`]!
+/// Untagged values are not type-checked: [`Error::SchemaMismatch`] is only returned if the value
+/// was stored using [`insert_versioned()`].
 ///
 /// If no value with the key is found, `None` is returned.
-pub async fn get<V>(key: &str) -> Result<Option<V>, sequential_storage::Error<FlashError>>
+pub async fn get<V>(key: &str) -> Result<Option<V>, Error<FlashError>>
 where
     V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
 {
     lock().await.get(key).await
 }
 
+/// Stores a [`Versioned`] value into flash memory, tagged with its current [`SchemaTag`].
+///
+/// It will overwrite the last value that has the same key.
+pub async fn insert_versioned<V: Versioned>(key: &str, value: V) -> Result<(), Error<FlashError>> {
+    lock().await.insert_versioned(key, value).await
+}
+
+/// Gets the last stored [`Versioned`] value that is associated with the given key.
+///
+/// Values stored with an older schema version are migrated, see [`Storage::get_versioned()`].
+///
+/// If no value with the key is found, `None` is returned.
+pub async fn get_versioned<V: Versioned>(key: &str) -> Result<Option<V>, Error<FlashError>> {
+    lock().await.get_versioned(key).await
+}
+
 /// Deletes an item from flash.
 ///
 /// Additional calls to [`get()`] with the same key will return `None` until
@@ -133,20 +248,86 @@ where
 /// </div>
 // STM32 flash drivers do not implement `MultiwriteNorFlash`.
 #[cfg(not(context = "stm32"))]
-pub async fn remove(key: &str) -> Result<(), sequential_storage::Error<FlashError>> {
+pub async fn remove(key: &str) -> Result<(), Error<FlashError>> {
     lock().await.remove(key).await
 }
 
+/// Applies all operations of a [`Transaction`], all-or-nothing.
+///
+/// See [`Storage::commit()`].
+#[cfg(not(context = "stm32"))]
+pub async fn commit(transaction: &Transaction) -> Result<(), Error<FlashError>> {
+    lock().await.commit(transaction).await
+}
+
+/// Applies all operations of a [`Transaction`], all-or-nothing.
+///
+/// STM32 flash drivers do not implement `MultiwriteNorFlash`: transactions can only insert values,
+/// see [`Storage::commit_inserts()`].
+#[cfg(context = "stm32")]
+pub async fn commit(transaction: &Transaction) -> Result<(), Error<FlashError>> {
+    lock().await.commit_inserts(transaction).await
+}
+
+/// Stores a byte value of any length into flash memory.
+///
+/// See [`Storage::insert_blob()`].
+pub async fn insert_blob(key: &str, data: &[u8]) -> Result<(), Error<FlashError>> {
+    let mut s = lock().await;
+    s.insert_blob(key, data).await?;
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`, the chunks of the previous blob
+    // are then left in flash.
+    #[cfg(not(context = "stm32"))]
+    s.remove_stale_chunks(key).await?;
+    Ok(())
+}
+
+/// Reads the blob associated with the given key into `buffer`, returning its length.
+///
+/// See [`Storage::get_blob()`].
+pub async fn get_blob(key: &str, buffer: &mut [u8]) -> Result<Option<usize>, Error<FlashError>> {
+    lock().await.get_blob(key, buffer).await
+}
+
+/// Deletes a blob from flash.
+///
+/// See [`Storage::remove_blob()`].
+// STM32 flash drivers do not implement `MultiwriteNorFlash`.
+#[cfg(not(context = "stm32"))]
+pub async fn remove_blob(key: &str) -> Result<(), Error<FlashError>> {
+    lock().await.remove_blob(key).await
+}
+
 /// Resets the flash in the entire flash range.
-pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
+pub async fn erase_all() -> Result<(), Error<FlashError>> {
     let mut s = lock().await;
     s.erase_all().await?;
-    s.insert(MARKER_KEY, MARKER_VALUE).await
+    s.insert(&OS_NAMESPACE.key(MARKER_KEY), MARKER_VALUE).await
+}
+
+/// Moves the marker from its [`LEGACY_MARKER_KEY`] into the [`OS_NAMESPACE`].
+async fn migrate_marker() -> Result<(), Error<FlashError>> {
+    let mut s = lock().await;
+    s.insert(&OS_NAMESPACE.key(MARKER_KEY), MARKER_VALUE)
+        .await?;
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`, the legacy marker is then left
+    // in place.
+    #[cfg(not(context = "stm32"))]
+    s.remove(LEGACY_MARKER_KEY).await?;
+    Ok(())
+}
+
+/// Returns usage statistics of the global storage.
+///
+/// See [`Storage::stats()`].
+pub async fn stats() -> Result<StorageStats, Error<FlashError>> {
+    lock().await.stats().await
 }
 
 /// Gets a [`MutexGuard`] of the global [`Storage`] object.
 ///
-/// This can be used to implement atomic RMW (like counters).
+/// This can be used to implement atomic RMW (like counters), or to iterate over the stored keys
+/// using [`Storage::keys()`] and [`Storage::keys_with_prefix()`].
 /// *It is not needed for using the global [`get()`], [`insert()`] and [`remove()`] functions.*
 ///
 /// Note: don't forget to drop the mutex guard returned by this.
@@ -163,6 +344,7 @@ pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
 ///     s.insert("counter", value + 1).await.unwrap();
 /// }
 /// ```
-pub async fn lock() -> MutexGuard<'static, CriticalSectionRawMutex, storage::Storage<Flash>> {
+pub async fn lock()
+-> MutexGuard<'static, CriticalSectionRawMutex, storage::Storage<Flash, GlobalCache>> {
     STORAGE.get().await.lock().await
 }
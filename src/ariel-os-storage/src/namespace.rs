@@ -0,0 +1,224 @@
+//! Namespaced handles on the global storage.
+use ariel_os_hal::hal::storage::FlashError;
+use arrayvec::ArrayString;
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash};
+use sequential_storage::{
+    cache::KeyCacheImpl,
+    map::{Key as _, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, MAX_KEY_LEN, PostcardValue, SchemaTag, Storage, Versioned, lock,
+    storage::{DATA_BUFFER_SIZE, item_len},
+};
+
+/// Separator between the name of a namespace and the keys inside it.
+const SEPARATOR: &str = "/";
+
+/// Handle on a part of the global storage, isolated from other namespaces.
+///
+/// Keys are transparently prefixed with the name of the namespace and a `/` separator, so that
+/// components using different namespaces cannot collide with or wipe each other's values.
+/// A namespace can optionally be given a quota, limiting the flash space its values may use.
+///
+/// Example:
+///
+/// ```ignore
+/// const CONFIG: Namespace = Namespace::new("my-app").with_quota(512);
+///
+/// CONFIG.insert("interval", 10u32).await.unwrap();
+/// let interval: Option<u32> = CONFIG.get("interval").await.unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Namespace {
+    name: &'static str,
+    quota: Option<u32>,
+}
+
+impl Namespace {
+    /// Creates a new [`Namespace`] handle.
+    ///
+    /// The name should not contain `/`, as this would allow it to overlap with other
+    /// namespaces.
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
+        Self { name, quota: None }
+    }
+
+    /// Limits the flash space used by the values of this namespace to `quota` bytes.
+    ///
+    /// Inserts that would exceed the quota return [`Error::QuotaExceeded`].
+    /// Sizes are estimated from the serialized keys and values, including item headers.
+    #[must_use]
+    pub const fn with_quota(self, quota: u32) -> Self {
+        Self {
+            quota: Some(quota),
+            ..self
+        }
+    }
+
+    /// Returns the name of this namespace.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the prefix of all keys of this namespace in the global storage.
+    ///
+    /// This can be used with [`Storage::keys_with_prefix()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is longer than `MAX_KEY_LEN - 1`.
+    #[must_use]
+    pub fn prefix(&self) -> ArrayString<MAX_KEY_LEN> {
+        self.key("")
+    }
+
+    /// Returns the key under which `key` is stored in the global storage.
+    ///
+    /// This can be used when accessing the global [`Storage`] directly through [`lock()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefixed key is longer than `MAX_KEY_LEN`.
+    #[must_use]
+    pub fn key(&self, key: &str) -> ArrayString<MAX_KEY_LEN> {
+        let mut prefixed = ArrayString::new();
+        prefixed.push_str(self.name);
+        prefixed.push_str(SEPARATOR);
+        prefixed.push_str(key);
+        prefixed
+    }
+
+    /// Stores a key-value pair into this namespace.
+    ///
+    /// It will overwrite the last value that has the same key.
+    pub async fn insert<'d, V>(&self, key: &str, value: V) -> Result<(), Error<FlashError>>
+    where
+        V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        self.insert_in(
+            &mut *lock().await,
+            key,
+            value.into(),
+            &mut [0; DATA_BUFFER_SIZE],
+        )
+        .await
+    }
+
+    /// Gets the last stored value from this namespace that is associated with the given key.
+    ///
+    /// See [`Storage::get()`].
+    pub async fn get<V>(&self, key: &str) -> Result<Option<V>, Error<FlashError>>
+    where
+        V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        lock().await.get(&self.key(key)).await
+    }
+
+    /// Stores a [`Versioned`] value into this namespace.
+    ///
+    /// See [`Storage::insert_versioned()`].
+    pub async fn insert_versioned<V: Versioned>(
+        &self,
+        key: &str,
+        value: V,
+    ) -> Result<(), Error<FlashError>> {
+        let value = PostcardValue::tagged(value, SchemaTag::of::<V>());
+        self.insert_in(&mut *lock().await, key, value, &mut [0; DATA_BUFFER_SIZE])
+            .await
+    }
+
+    /// Gets the last stored [`Versioned`] value from this namespace.
+    ///
+    /// See [`Storage::get_versioned()`].
+    pub async fn get_versioned<V: Versioned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, Error<FlashError>> {
+        lock().await.get_versioned(&self.key(key)).await
+    }
+
+    /// Deletes an item from this namespace.
+    ///
+    /// See [`Storage::remove()`].
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`.
+    #[cfg(not(context = "stm32"))]
+    pub async fn remove(&self, key: &str) -> Result<(), Error<FlashError>> {
+        lock().await.remove(&self.key(key)).await
+    }
+
+    /// Deletes all items of this namespace, leaving other namespaces untouched.
+    ///
+    /// <div class="warning">
+    /// This is really slow!
+    ///
+    /// Every item is looked up and removed individually.
+    /// </div>
+    // STM32 flash drivers do not implement `MultiwriteNorFlash`.
+    #[cfg(not(context = "stm32"))]
+    pub async fn clear(&self) -> Result<(), Error<FlashError>> {
+        let prefix = self.prefix();
+        let mut s = lock().await;
+        // This also removes the chunks of blobs.
+        while let Some((key, _)) = s.keys_with_prefix(&prefix).next_item().await? {
+            s.remove(&key).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the estimated flash space currently used by the values of this namespace, in
+    /// bytes.
+    pub async fn usage(&self) -> Result<u32, Error<FlashError>> {
+        usage_in(&mut *lock().await, &self.prefix(), "").await
+    }
+
+    /// Inserts an already wrapped value into `storage` using `data_buffer`, enforcing the quota if
+    /// any.
+    pub(crate) async fn insert_in<'d, F, C, V>(
+        &self,
+        storage: &mut Storage<F, C>,
+        key: &str,
+        value: V,
+        data_buffer: &mut [u8],
+    ) -> Result<(), Error<<F as ErrorType>::Error>>
+    where
+        F: NorFlash,
+        C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+        V: Value<'d>,
+    {
+        let key = self.key(key);
+
+        if let Some(quota) = self.quota {
+            let value_len = value.serialize_into(data_buffer)?;
+            let key_len = key.serialize_into(data_buffer)?;
+
+            // The value being overwritten does not count towards the quota.
+            let usage =
+                usage_in(storage, &self.prefix(), &key).await? + item_len::<F>(key_len, value_len);
+            if usage > quota {
+                return Err(Error::QuotaExceeded);
+            }
+        }
+
+        storage.store_with_buffer(&key, &value, data_buffer).await
+    }
+}
+
+/// Sums the estimated sizes of the items with the given prefix, except `excluded_key`.
+async fn usage_in<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>>(
+    storage: &mut Storage<F, C>,
+    prefix: &str,
+    excluded_key: &str,
+) -> Result<u32, Error<<F as ErrorType>::Error>> {
+    let mut usage = 0;
+    let mut keys = storage.keys_with_prefix(prefix);
+    while let Some((key, item_len)) = keys.next_item().await? {
+        if key.as_str() != excluded_key {
+            usage += item_len;
+        }
+    }
+    Ok(usage)
+}
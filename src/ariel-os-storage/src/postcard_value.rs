@@ -1,20 +1,43 @@
 //! A [`Storage`][crate::storage::Storage] value serialized using Postcard.
 use core::ops::Deref;
 
-use postcard::{from_bytes, to_slice};
+use postcard::{from_bytes, take_from_bytes, to_slice};
 use sequential_storage::map::{SerializationError, Value};
 use serde::{Deserialize, Serialize};
 
+use crate::schema::SchemaTag;
+
+/// Bytes preceding the [`SchemaTag`] of a tagged value.
+///
+/// Untagged values are stored as plain Postcard, like before tags were introduced, so that values
+/// written by earlier versions stay readable. An untagged value whose serialization happens to
+/// start with these bytes is stored with an explicit [`SchemaTag::UNTAGGED`] header instead.
+const TAG_MAGIC: [u8; 4] = [0xff, b'T', b'A', b'G'];
+
 /// A [`Value`] serialized using Postcard.
+///
+/// Unless the value is untagged, the serialized value is preceded by the [`SchemaTag`] it was
+/// stored with.
 #[derive(Debug)]
 pub struct PostcardValue<T> {
+    tag: SchemaTag,
     value: T,
 }
 
 impl<'d, T: Serialize + Deserialize<'d>> PostcardValue<T> {
     /// Wraps an object in a [`PostcardValue`].
+    ///
+    /// The value is tagged with [`SchemaTag::UNTAGGED`].
     pub const fn from(value: T) -> Self {
-        Self { value }
+        Self::tagged(value, SchemaTag::UNTAGGED)
+    }
+    /// Wraps an object in a [`PostcardValue`] carrying the given [`SchemaTag`].
+    pub const fn tagged(value: T, tag: SchemaTag) -> Self {
+        Self { tag, value }
+    }
+    /// Returns the [`SchemaTag`] of this [`PostcardValue`].
+    pub const fn tag(&self) -> SchemaTag {
+        self.tag
     }
     /// Turns this [`PostcardValue`] into the wrapped object.
     pub fn into_inner(self) -> T {
@@ -38,22 +61,114 @@ impl<T> Deref for PostcardValue<T> {
 
 impl<'d, T: Serialize + Deserialize<'d>> Value<'d> for PostcardValue<T> {
     fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-        let used = to_slice(&self.value, buffer).map_err(|e| match e {
-            postcard::Error::SerializeBufferFull => SerializationError::BufferTooSmall,
-            _ => SerializationError::Custom(0),
-        })?;
+        if self.tag == SchemaTag::UNTAGGED {
+            let used = serialize_value(&self.value, buffer)?;
+            if !used.starts_with(&TAG_MAGIC) {
+                return Ok(used.len());
+            }
+        }
+
+        let header_len = serialize_header(self.tag, buffer)?;
+        let buffer = buffer
+            .get_mut(header_len..)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        let used = serialize_value(&self.value, buffer)?;
+
+        Ok(header_len + used.len())
+    }
+
+    /// # Note
+    /// This will assume that the entire buffer is used for deserialization and thus discard it.
+    fn deserialize_from(buffer: &'d [u8]) -> Result<(Self, usize), SerializationError> {
+        let (record, used) = TaggedBytes::deserialize_from(buffer)?;
+        let value = deserialize_value(record.bytes)?;
+
+        Ok((
+            Self {
+                tag: record.tag,
+                value,
+            },
+            used,
+        ))
+    }
+}
+
+/// A stored record whose value has not been deserialized yet.
+#[derive(Debug)]
+pub(crate) struct TaggedBytes<'d> {
+    pub(crate) tag: SchemaTag,
+    pub(crate) bytes: &'d [u8],
+}
+
+impl<'d> Value<'d> for TaggedBytes<'d> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let header_len = if self.tag == SchemaTag::UNTAGGED && !self.bytes.starts_with(&TAG_MAGIC) {
+            0
+        } else {
+            serialize_header(self.tag, buffer)?
+        };
+        let len = header_len + self.bytes.len();
+        buffer
+            .get_mut(header_len..len)
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(self.bytes);
 
-        Ok(used.len())
+        Ok(len)
     }
 
     /// # Note
     /// This will assume that the entire buffer is used for deserialization and thus discard it.
     fn deserialize_from(buffer: &'d [u8]) -> Result<(Self, usize), SerializationError> {
-        let value = from_bytes(buffer).map_err(|e| match e {
+        // Untagged values are stored as they were before tags were introduced.
+        let Some(tagged) = buffer.strip_prefix(&TAG_MAGIC) else {
+            return Ok((
+                Self {
+                    tag: SchemaTag::UNTAGGED,
+                    bytes: buffer,
+                },
+                buffer.len(),
+            ));
+        };
+        let (tag, bytes) = take_from_bytes(tagged).map_err(|e| match e {
             postcard::Error::DeserializeUnexpectedEnd => SerializationError::InvalidData,
             _ => SerializationError::Custom(0),
         })?;
 
-        Ok((Self { value }, buffer.len()))
+        Ok((Self { tag, bytes }, buffer.len()))
     }
 }
+
+/// Writes the [`TAG_MAGIC`] and `tag` into `buffer`, returning their length.
+fn serialize_header(tag: SchemaTag, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+    buffer
+        .get_mut(..TAG_MAGIC.len())
+        .ok_or(SerializationError::BufferTooSmall)?
+        .copy_from_slice(&TAG_MAGIC);
+    let buffer = buffer
+        .get_mut(TAG_MAGIC.len()..)
+        .ok_or(SerializationError::BufferTooSmall)?;
+    let tag_len = serialize_value(&tag, buffer)?.len();
+
+    Ok(TAG_MAGIC.len() + tag_len)
+}
+
+/// Serializes `value` into `buffer`, returning the used part of the buffer.
+pub(crate) fn serialize_value<'b, T: Serialize + ?Sized>(
+    value: &T,
+    buffer: &'b mut [u8],
+) -> Result<&'b mut [u8], SerializationError> {
+    to_slice(value, buffer).map_err(|e| match e {
+        postcard::Error::SerializeBufferFull => SerializationError::BufferTooSmall,
+        _ => SerializationError::Custom(0),
+    })
+}
+
+/// Deserializes a value from the entire `buffer`.
+pub(crate) fn deserialize_value<'d, T: Deserialize<'d>>(
+    buffer: &'d [u8],
+) -> Result<T, SerializationError> {
+    from_bytes(buffer).map_err(|e| match e {
+        postcard::Error::DeserializeUnexpectedEnd => SerializationError::InvalidData,
+        _ => SerializationError::Custom(0),
+    })
+}
@@ -0,0 +1,86 @@
+//! Schema tags attached to stored values, and versioned value migrations.
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Tag identifying the type and schema version a value was written with.
+///
+/// Values stored through the plain [`Storage::insert()`](crate::Storage::insert) are untagged and
+/// stored as plain Postcard; other values are stored behind their tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaTag {
+    /// Identifier of the stored type; `0` is reserved for untagged values.
+    pub id: u32,
+    /// Version of the schema of the stored type.
+    pub version: u16,
+}
+
+impl SchemaTag {
+    /// Tag used for values stored through the plain [`Storage::insert()`](crate::Storage::insert).
+    pub const UNTAGGED: Self = Self { id: 0, version: 0 };
+
+    /// Returns the tag of the current schema version of `V`.
+    #[must_use]
+    pub const fn of<V: Versioned>() -> Self {
+        Self {
+            id: V::SCHEMA_ID,
+            version: V::SCHEMA_VERSION,
+        }
+    }
+}
+
+/// A type whose stored representation carries an explicit identifier and schema version.
+///
+/// Values of such types are stored and read through
+/// [`Storage::insert_versioned()`](crate::Storage::insert_versioned) and
+/// [`Storage::get_versioned()`](crate::Storage::get_versioned).
+/// When a record written with an older [`SCHEMA_VERSION`](Versioned::SCHEMA_VERSION) is read,
+/// [`Versioned::migrate()`] is called to upgrade it, and the upgraded value is written back.
+///
+/// Example:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct ConfigV1 {
+///     interval: u32,
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Config {
+///     interval: u32,
+///     enabled: bool,
+/// }
+///
+/// impl Versioned for Config {
+///     const SCHEMA_ID: u32 = 0xc0f1;
+///     const SCHEMA_VERSION: u16 = 2;
+///
+///     fn migrate(from_version: u16, bytes: &[u8]) -> Option<Self> {
+///         match from_version {
+///             1 => {
+///                 let old: ConfigV1 = postcard::from_bytes(bytes).ok()?;
+///                 Some(Config { interval: old.interval, enabled: true })
+///             }
+///             _ => None,
+///         }
+///     }
+/// }
+/// ```
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Identifier of this type, must be non-zero and unique among the stored types.
+    const SCHEMA_ID: u32;
+    /// Current schema version of this type, starting at `1`.
+    ///
+    /// Version `0` stands for values stored untagged, e.g., before the type became [`Versioned`].
+    const SCHEMA_VERSION: u16;
+
+    /// Upgrades a record stored with an older schema version.
+    ///
+    /// `bytes` is the Postcard-serialized value as it was written with `from_version`, which is `0`
+    /// if the value was stored untagged using [`Storage::insert()`](crate::Storage::insert).
+    /// Returns `None` if no migration from that version exists, which makes the read fail
+    /// with [`Error::SchemaMismatch`](crate::Error::SchemaMismatch).
+    #[must_use]
+    fn migrate(from_version: u16, bytes: &[u8]) -> Option<Self> {
+        let _ = (from_version, bytes);
+        None
+    }
+}
@@ -2,15 +2,24 @@
 //! a flash range and backend.
 use core::ops::Range;
 
-use arrayvec::ArrayString;
+use arrayvec::{ArrayString, ArrayVec};
 use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash};
 use sequential_storage::{
-    cache::NoCache,
+    cache::{KeyCacheImpl, NoCache},
     erase_all,
-    map::{Value, fetch_item, remove_item, store_item},
+    map::{
+        Key as _, SerializationError, Value, fetch_all_items, fetch_item, remove_item, store_item,
+    },
 };
 
+use crate::{
+    blob::{CHUNK_BUFFER_SIZE, CHUNK_SEPARATOR},
+    postcard_value::{TaggedBytes, deserialize_value, serialize_value},
+};
+
+pub use crate::error::Error;
 pub use crate::postcard_value::PostcardValue;
+pub use crate::schema::{SchemaTag, Versioned};
 pub use serde::{Deserialize, Serialize};
 
 /// Maximum key length.
@@ -18,21 +27,47 @@ pub const MAX_KEY_LEN: usize = 64usize;
 /// Data buffer length.
 pub const DATA_BUFFER_SIZE: usize = 128usize;
 
+/// Size of the header `sequential-storage` writes in front of each item, before alignment.
+///
+/// `sequential-storage` does not expose this; the value matches its 6.0 item format, which is
+/// why the dependency is pinned to that minor version.
+const ITEM_HEADER_SIZE: usize = 8;
+
+/// Number of keys [`Keys`] collects in a single pass over the flash.
+pub(crate) const KEYS_BATCH: usize = 8;
+
 /// Object holding an instance of a key-value pair storage.
 ///
 /// You should probably look into using the global instance accessible via
 /// `ariel_os_storage::storage::{get,insert,remove}`.
-pub struct Storage<F> {
+///
+/// Lookups go through a cache of type `C`, see [`Storage::with_cache()`].
+#[expect(clippy::struct_field_names)]
+pub struct Storage<F, C = NoCache> {
     flash: F,
     storage_range: Range<u32>,
+    cache: C,
 }
 
 impl<F: NorFlash> Storage<F> {
-    /// Creates a new [`Storage`] instance.
+    /// Creates a new [`Storage`] instance, without a cache.
     pub const fn new(flash: F, storage_range: Range<u32>) -> Storage<F> {
+        Self::with_cache(flash, storage_range, NoCache::new())
+    }
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Creates a new [`Storage`] instance using the given cache.
+    ///
+    /// Caches provided by [`sequential_storage::cache`] avoid scanning the flash on every
+    /// operation, e.g., `PageStateCache` caches the state of flash pages, and
+    /// `KeyPointerCache` additionally caches the location of recently used keys.
+    /// The cache must not be shared with other [`Storage`] instances.
+    pub const fn with_cache(flash: F, storage_range: Range<u32>, cache: C) -> Storage<F, C> {
         Self {
             flash,
             storage_range,
+            cache,
         }
     }
 
@@ -44,18 +79,18 @@ impl<F: NorFlash> Storage<F> {
     pub async fn get_raw<V: for<'d> Value<'d>>(
         &mut self,
         key: &str,
-    ) -> Result<Option<V>, sequential_storage::Error<<F as ErrorType>::Error>> {
+    ) -> Result<Option<V>, Error<<F as ErrorType>::Error>> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
 
-        fetch_item::<_, V, _>(
+        Ok(fetch_item::<_, V, _>(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
         )
-        .await
+        .await?)
     }
 
     /// Inserts a [`Value`] into this [`Storage`] instance.
@@ -67,28 +102,30 @@ impl<F: NorFlash> Storage<F> {
         &mut self,
         key: &str,
         value: V,
-    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
-        store_item(
+        Ok(store_item(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
             &value,
         )
-        .await
+        .await?)
     }
 
     /// Stores a key-value pair into flash memory.
     ///
     /// It will overwrite the last value that has the same key.
+    /// The value is stored untagged: reading it back as another type is not detected, see
+    /// [`Storage::insert_versioned()`] for values whose type is checked.
     pub async fn insert<'d, V>(
         &mut self,
         key: &str,
         value: V,
-    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>>
+    ) -> Result<(), Error<<F as ErrorType>::Error>>
     where
         V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
     {
@@ -98,40 +135,245 @@ impl<F: NorFlash> Storage<F> {
     /// Gets the last stored value from the flash that is associated with the given key.
     ///
     /// If no value with the key is found, `None` is returned.
+    /// If the value was stored with [`Storage::insert_versioned()`],
+    /// [`Error::SchemaMismatch`] is returned; untagged values are not type-checked.
     ///
     /// # Panics
     ///
     /// Currently panics if `key.len() > MAX_KEY_LEN`.
-    pub async fn get<V>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<V>, sequential_storage::Error<<F as ErrorType>::Error>>
+    pub async fn get<V>(&mut self, key: &str) -> Result<Option<V>, Error<<F as ErrorType>::Error>>
     where
         V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
     {
-        let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
 
-        let postcard_value = fetch_item::<_, PostcardValue<V>, _>(
+        let Some(record) = self.fetch_record(key, &mut data_buffer).await? else {
+            return Ok(None);
+        };
+        if record.tag != SchemaTag::UNTAGGED {
+            return Err(Error::SchemaMismatch {
+                expected: SchemaTag::UNTAGGED,
+                found: record.tag,
+            });
+        }
+
+        Ok(Some(deserialize_value(record.bytes)?))
+    }
+
+    /// Stores a [`Versioned`] value into flash memory, tagged with its current [`SchemaTag`].
+    ///
+    /// It will overwrite the last value that has the same key.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub async fn insert_versioned<V: Versioned>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        self.insert_raw(key, PostcardValue::tagged(value, SchemaTag::of::<V>()))
+            .await
+    }
+
+    /// Gets the last stored [`Versioned`] value that is associated with the given key.
+    ///
+    /// If the value was stored with an older schema version of `V`, or untagged using
+    /// [`Storage::insert()`], it is upgraded using [`Versioned::migrate()`] and the upgraded value
+    /// is written back.
+    /// If the value was stored as another type, or cannot be migrated,
+    /// [`Error::SchemaMismatch`] is returned.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub async fn get_versioned<V: Versioned>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<V>, Error<<F as ErrorType>::Error>> {
+        let expected = SchemaTag::of::<V>();
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+
+        let Some(record) = self.fetch_record(key, &mut data_buffer).await? else {
+            return Ok(None);
+        };
+        let found = record.tag;
+        if found == expected {
+            return Ok(Some(deserialize_value(record.bytes)?));
+        }
+
+        // Only older versions of the same type, or values stored before the type was versioned,
+        // can be migrated.
+        let value = if found == SchemaTag::UNTAGGED
+            || (found.id == expected.id && found.version < expected.version)
+        {
+            V::migrate(found.version, record.bytes)
+        } else {
+            None
+        };
+        let Some(value) = value else {
+            return Err(Error::SchemaMismatch { expected, found });
+        };
+
+        let bytes = serialize_value(&value, &mut data_buffer)?;
+        self.insert_raw(
+            key,
+            TaggedBytes {
+                tag: expected,
+                bytes,
+            },
+        )
+        .await?;
+
+        Ok(Some(value))
+    }
+
+    /// Fetches the undecoded record stored under `key`, using `data_buffer` as backing memory.
+    async fn fetch_record<'d>(
+        &mut self,
+        key: &str,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<TaggedBytes<'d>>, Error<<F as ErrorType>::Error>> {
+        self.fetch_with_buffer(key, data_buffer).await
+    }
+
+    /// Fetches a [`Value`] using `data_buffer`, which may be larger than [`DATA_BUFFER_SIZE`].
+    pub(crate) async fn fetch_with_buffer<'d, V: Value<'d>>(
+        &mut self,
+        key: &str,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<V>, Error<<F as ErrorType>::Error>> {
+        let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+
+        Ok(fetch_item::<_, V, _>(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
-            &mut data_buffer,
+            &mut self.cache,
+            data_buffer,
             &key,
         )
-        .await?;
-        Ok(postcard_value.map(PostcardValue::into_inner))
+        .await?)
     }
 
-    /// Resets the flash in the entire flash range of this [`Storage`] instance.
-    pub async fn erase_all(
+    /// Stores a [`Value`] using `data_buffer`, which may be larger than [`DATA_BUFFER_SIZE`].
+    pub(crate) async fn store_with_buffer<'d, V: Value<'d>>(
         &mut self,
-    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
-        erase_all(&mut self.flash, self.storage_range.clone()).await
+        key: &str,
+        value: &V,
+        data_buffer: &mut [u8],
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+
+        Ok(store_item(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut self.cache,
+            data_buffer,
+            &key,
+            value,
+        )
+        .await?)
+    }
+
+    /// Returns an iterator over the keys currently stored in this [`Storage`] instance.
+    ///
+    /// Each key is returned once, in the order its current item was written.
+    ///
+    /// <div class="warning">
+    /// This is slow!
+    ///
+    /// Items in flash have to be read once per [`KEYS_BATCH`] keys to skip items that have been
+    /// overwritten.
+    /// </div>
+    pub fn keys(&mut self) -> Keys<'_, '_, F, C> {
+        self.keys_with_prefix("")
+    }
+
+    /// Returns an iterator over the stored keys starting with `prefix`.
+    ///
+    /// This can be used to list keys of a hierarchy, e.g., all keys starting with `"wifi/"`.
+    /// See [`Storage::keys()`].
+    pub fn keys_with_prefix<'p>(&mut self, prefix: &'p str) -> Keys<'_, 'p, F, C> {
+        Keys {
+            storage: self,
+            prefix,
+            position: 0,
+            batch: ArrayVec::new(),
+            done: false,
+        }
+    }
+
+    /// Returns usage statistics of the flash range of this [`Storage`] instance.
+    ///
+    /// <div class="warning">
+    /// This is slow!
+    ///
+    /// The entire flash range is read, and items are iterated as with [`Storage::keys()`].
+    /// </div>
+    pub async fn stats(&mut self) -> Result<StorageStats, Error<<F as ErrorType>::Error>> {
+        #[expect(clippy::cast_possible_truncation)]
+        let page_size = F::ERASE_SIZE as u32;
+        let mut read_buffer = [0; DATA_BUFFER_SIZE];
+
+        // Erased bytes at the end of a page can still be written to.
+        let mut free = 0;
+        let mut page_start = self.storage_range.start;
+        while page_start < self.storage_range.end {
+            let mut chunk_end = page_start + page_size;
+            'page: while chunk_end > page_start {
+                #[expect(clippy::cast_possible_truncation)]
+                let chunk_len = (chunk_end - page_start).min(DATA_BUFFER_SIZE as u32);
+                let chunk = read_buffer
+                    .get_mut(..chunk_len as usize)
+                    .ok_or(SerializationError::BufferTooSmall)?;
+                self.flash
+                    .read(chunk_end - chunk_len, chunk)
+                    .await
+                    .map_err(|value| sequential_storage::Error::Storage { value })?;
+
+                for byte in chunk.iter().rev() {
+                    if *byte != 0xff {
+                        break 'page;
+                    }
+                    free += 1;
+                }
+                chunk_end -= chunk_len;
+            }
+            page_start += page_size;
+        }
+
+        let mut live = 0;
+        let mut keys = self.keys();
+        while let Some((_, item_len)) = keys.next_item().await? {
+            live += item_len;
+        }
+
+        let total = self.storage_range.end - self.storage_range.start;
+        let used = total - free;
+
+        Ok(StorageStats {
+            total,
+            used,
+            free,
+            erasable: used.saturating_sub(live),
+        })
+    }
+
+    /// Resets the flash in the entire flash range of this [`Storage`] instance.
+    ///
+    /// The cache is reset as well.
+    pub async fn erase_all(&mut self) -> Result<(), Error<<F as ErrorType>::Error>>
+    where
+        C: Default,
+    {
+        self.cache = C::default();
+        Ok(erase_all(&mut self.flash, self.storage_range.clone()).await?)
     }
 }
 
-impl<F: MultiwriteNorFlash> Storage<F> {
+impl<F: MultiwriteNorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
     /// Deletes an item from flash.
     ///
     /// Additional calls to [`Storage::get()`] with the same key will return `None` until
@@ -147,19 +389,149 @@ impl<F: MultiwriteNorFlash> Storage<F> {
     /// # Panics
     ///
     /// Currently panics if `key.len() > MAX_KEY_LEN`.
-    pub async fn remove(
-        &mut self,
-        key: &str,
-    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+    pub async fn remove(&mut self, key: &str) -> Result<(), Error<<F as ErrorType>::Error>> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
-        remove_item(
+        Ok(remove_item(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
         )
-        .await
+        .await?)
+    }
+}
+
+/// Usage statistics of a [`Storage`], returned by [`Storage::stats()`].
+///
+/// All values are in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Size of the flash range.
+    pub total: u32,
+    /// Bytes that have been written to, including page and item headers.
+    pub used: u32,
+    /// Erased bytes that can still be written to without erasing a page.
+    ///
+    /// `sequential-storage` always keeps one page free for garbage collection, which is included
+    /// here.
+    pub free: u32,
+    /// Estimated used bytes not holding current items, which garbage collection can reclaim.
+    pub erasable: u32,
+}
+
+/// Iterator over the keys of a [`Storage`], returned by [`Storage::keys()`] and
+/// [`Storage::keys_with_prefix()`].
+pub struct Keys<'s, 'p, F, C = NoCache> {
+    storage: &'s mut Storage<F, C>,
+    prefix: &'p str,
+    /// Number of items read by the previous passes.
+    position: usize,
+    /// Current keys found by the last pass, with their item size, not returned yet.
+    batch: ArrayVec<(ArrayString<MAX_KEY_LEN>, u32), KEYS_BATCH>,
+    /// Whether the passes have reached the last item.
+    done: bool,
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Keys<'_, '_, F, C> {
+    /// Returns the next stored key, or `None` when all keys have been returned.
+    pub async fn next(
+        &mut self,
+    ) -> Result<Option<ArrayString<MAX_KEY_LEN>>, Error<<F as ErrorType>::Error>> {
+        while let Some((key, _)) = self.next_item().await? {
+            // Chunks are part of the blob stored under their key prefix.
+            if !key.contains(CHUNK_SEPARATOR) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the next stored key together with the estimated size of its item in flash.
+    ///
+    /// Unlike [`Keys::next()`], this also returns the keys of blob chunks.
+    pub(crate) async fn next_item(
+        &mut self,
+    ) -> Result<Option<(ArrayString<MAX_KEY_LEN>, u32)>, Error<<F as ErrorType>::Error>> {
+        while self.batch.is_empty() && !self.done {
+            self.fill_batch().await?;
+        }
+        Ok(self.batch.pop_at(0))
+    }
+
+    /// Collects the next [`KEYS_BATCH`] candidate keys in a single pass over the flash, and drops
+    /// those that are overwritten later on.
+    async fn fill_batch(&mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        let mut data_buffer = [0; CHUNK_BUFFER_SIZE];
+
+        let storage = &mut *self.storage;
+        let mut items = fetch_all_items::<ArrayString<MAX_KEY_LEN>, _, _>(
+            &mut storage.flash,
+            storage.storage_range.clone(),
+            &mut storage.cache,
+            &mut data_buffer,
+        )
+        .await?;
+
+        for _ in 0..self.position {
+            if items
+                .next::<SkippedValue>(&mut data_buffer)
+                .await?
+                .is_none()
+            {
+                self.done = true;
+                return Ok(());
+            }
+        }
+
+        while !self.batch.is_full() {
+            let Some((candidate, SkippedValue(value_len))) = items.next(&mut data_buffer).await?
+            else {
+                self.done = true;
+                return Ok(());
+            };
+            self.position += 1;
+
+            if !candidate.starts_with(self.prefix) {
+                continue;
+            }
+            // Items are returned in the order they were written: only the last item with a given
+            // key is current.
+            self.batch.retain(|(key, _)| *key != candidate);
+            let key_len = candidate.serialize_into(&mut data_buffer)?;
+            self.batch
+                .push((candidate, item_len::<F>(key_len, value_len)));
+        }
+
+        // Keys overwritten after this batch are found again by a later pass.
+        while let Some((key, SkippedValue(_))) = items.next(&mut data_buffer).await? {
+            self.batch.retain(|(candidate, _)| *candidate != key);
+            if self.batch.is_empty() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the estimated size in flash of an item with the given serialized key and value lengths.
+#[expect(clippy::cast_possible_truncation)]
+pub(crate) fn item_len<F: NorFlash>(key_len: usize, value_len: usize) -> u32 {
+    (ITEM_HEADER_SIZE.next_multiple_of(F::WRITE_SIZE)
+        + (key_len + value_len).next_multiple_of(F::WRITE_SIZE)) as u32
+}
+
+/// A [`Value`] that is not deserialized, only recording its length.
+struct SkippedValue(usize);
+
+impl Value<'_> for SkippedValue {
+    fn serialize_into(&self, _buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        Err(SerializationError::InvalidFormat)
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        Ok((Self(buffer.len()), buffer.len()))
     }
 }
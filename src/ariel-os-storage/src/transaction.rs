@@ -0,0 +1,309 @@
+//! Atomic updates of multiple keys.
+//!
+//! Operations of a [`Transaction`] are first written to a journal, stored as a blob. Once the
+//! journal has been written, the operations are applied and the journal is cleared. If power is
+//! lost while applying them, the journal is replayed by [`Storage::recover()`]: a transaction is
+//! either applied entirely, or not at all.
+use arrayvec::ArrayString;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash};
+use postcard::take_from_bytes;
+use sequential_storage::{
+    cache::KeyCacheImpl,
+    map::{Key as _, SerializationError, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DATA_BUFFER_SIZE, Error, MAX_KEY_LEN, OS_NAMESPACE, PostcardValue, SchemaTag, Storage,
+    Versioned, blob::ChunkBytes, postcard_value::serialize_value,
+};
+
+/// Maximum size of the serialized operations of a [`Transaction`].
+pub const TRANSACTION_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_STORAGE_TRANSACTION_SIZE",
+    512,
+    "maximum size of the serialized operations of a storage transaction"
+);
+
+/// Key of the journal blob in the namespace of the OS.
+const JOURNAL_KEY: &str = "transaction";
+
+/// Buffer the journal is read into when recovering, kept off the stack.
+static JOURNAL_BUFFER: Mutex<CriticalSectionRawMutex, [u8; TRANSACTION_SIZE]> =
+    Mutex::new([0; TRANSACTION_SIZE]);
+
+/// Operation of a [`Transaction`], as written to the journal.
+#[derive(Debug, Serialize, Deserialize)]
+enum Operation<'a> {
+    /// Stores the serialized `value` under `key`.
+    Insert { key: &'a str, value: &'a [u8] },
+    /// Deletes the item stored under `key`.
+    Remove { key: &'a str },
+}
+
+/// Set of inserts and removes committed all-or-nothing, even across power loss.
+///
+/// Operations are serialized into a buffer of [`TRANSACTION_SIZE`] bytes, which can be
+/// configured using `CONFIG_STORAGE_TRANSACTION_SIZE`, and are applied in order by
+/// [`Storage::commit()`], or by [`Storage::commit_inserts()`] if they only insert values.
+///
+/// Example:
+///
+/// ```ignore
+/// let mut transaction = Transaction::new();
+/// transaction.insert("wifi/ssid", ssid)?;
+/// transaction.insert("wifi/password", password)?;
+/// storage::commit(&transaction).await?;
+/// ```
+#[derive(Debug)]
+pub struct Transaction {
+    buffer: [u8; TRANSACTION_SIZE],
+    len: usize,
+    removes: bool,
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transaction {
+    /// Creates an empty [`Transaction`].
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; TRANSACTION_SIZE],
+            len: 0,
+            removes: false,
+        }
+    }
+
+    /// Adds storing a key-value pair to this transaction.
+    ///
+    /// Returns [`SerializationError::BufferTooSmall`] if the transaction is full, or if the key
+    /// and the value do not fit into [`DATA_BUFFER_SIZE`].
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub fn insert<'d, V>(&mut self, key: &str, value: V) -> Result<(), SerializationError>
+    where
+        V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        self.insert_raw(key, &value.into())
+    }
+
+    /// Adds storing a [`Versioned`] value to this transaction.
+    ///
+    /// See [`Transaction::insert()`] and [`Storage::insert_versioned()`].
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub fn insert_versioned<V: Versioned>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> Result<(), SerializationError> {
+        self.insert_raw(key, &PostcardValue::tagged(value, SchemaTag::of::<V>()))
+    }
+
+    /// Adds deleting an item to this transaction.
+    ///
+    /// Such transactions can only be committed on flash implementing [`MultiwriteNorFlash`].
+    /// Returns [`SerializationError::BufferTooSmall`] if the transaction is full.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub fn remove(&mut self, key: &str) -> Result<(), SerializationError> {
+        let _ = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+        self.push(&Operation::Remove { key })?;
+        self.removes = true;
+        Ok(())
+    }
+
+    /// Returns whether this transaction has no operations.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds storing an already wrapped value to this transaction.
+    fn insert_raw<'d, V: Value<'d>>(
+        &mut self,
+        key: &str,
+        value: &V,
+    ) -> Result<(), SerializationError> {
+        // The item has to fit into the data buffer once the transaction is applied.
+        let mut buffer = [0; DATA_BUFFER_SIZE];
+        let key_len = ArrayString::<MAX_KEY_LEN>::from(key)
+            .unwrap()
+            .serialize_into(&mut buffer)?;
+        let value_buffer = buffer
+            .get_mut(key_len..)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        let value_len = value.serialize_into(value_buffer)?;
+        let value = value_buffer
+            .get(..value_len)
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        self.push(&Operation::Insert { key, value })
+    }
+
+    /// Appends a serialized operation to the buffer.
+    fn push(&mut self, operation: &Operation<'_>) -> Result<(), SerializationError> {
+        let buffer = self
+            .buffer
+            .get_mut(self.len..)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        self.len += serialize_value(operation, buffer)?.len();
+        Ok(())
+    }
+
+    /// Returns the serialized operations.
+    fn as_bytes(&self) -> &[u8] {
+        self.buffer.get(..self.len).unwrap_or_default()
+    }
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Applies all operations of a [`Transaction`] that only inserts values, all-or-nothing.
+    ///
+    /// Unlike [`Storage::commit()`], this does not need flash implementing
+    /// [`MultiwriteNorFlash`]; the chunks of previous journals are then left in flash.
+    /// Returns [`Error::Unsupported`] if the transaction removes items.
+    /// If this returns an error after the journal has been written, the transaction is completed
+    /// by the next call to [`Storage::recover_inserts()`].
+    pub async fn commit_inserts(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        if transaction.removes {
+            return Err(Error::Unsupported);
+        }
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let journal_key = OS_NAMESPACE.key(JOURNAL_KEY);
+        // Writing the journal is the point from which the transaction will be applied.
+        self.insert_blob(&journal_key, transaction.as_bytes())
+            .await?;
+        self.apply_inserts(transaction.as_bytes()).await?;
+        self.insert_blob(&journal_key, &[]).await
+    }
+
+    /// Completes a transaction committed by [`Storage::commit_inserts()`] and interrupted by
+    /// power loss, if any.
+    ///
+    /// This is the counterpart of [`Storage::recover()`] for flash not implementing
+    /// [`MultiwriteNorFlash`]. The global storage calls it during initialization on such flash.
+    pub async fn recover_inserts(&mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        let mut buffer = JOURNAL_BUFFER.lock().await;
+        if let Some(journal) = self.read_journal(&mut buffer).await? {
+            ariel_os_log::info!("storage: completing interrupted transaction");
+            self.apply_inserts(journal).await?;
+            self.insert_blob(&OS_NAMESPACE.key(JOURNAL_KEY), &[])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads a non-empty journal into `buffer`, returning it.
+    async fn read_journal<'b>(
+        &mut self,
+        buffer: &'b mut [u8; TRANSACTION_SIZE],
+    ) -> Result<Option<&'b [u8]>, Error<<F as ErrorType>::Error>> {
+        let journal_key = OS_NAMESPACE.key(JOURNAL_KEY);
+        let Some(len) = self.get_blob(&journal_key, buffer).await? else {
+            return Ok(None);
+        };
+        let journal = buffer.get(..len).ok_or(Error::Corrupted)?;
+        Ok(Some(journal).filter(|journal| !journal.is_empty()))
+    }
+
+    /// Applies the serialized operations of a journal that only inserts values.
+    async fn apply_inserts(
+        &mut self,
+        mut journal: &[u8],
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        while let Some(operation) = take_operation(&mut journal)? {
+            match operation {
+                Operation::Insert { key, value } => self.insert_raw(key, ChunkBytes(value)).await?,
+                Operation::Remove { .. } => return Err(Error::Unsupported),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: MultiwriteNorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Applies all operations of a [`Transaction`], all-or-nothing.
+    ///
+    /// The operations are first written to a journal, so this takes about twice the flash
+    /// writes of applying them individually.
+    /// If this returns an error after the journal has been written, the transaction is completed
+    /// by the next call to [`Storage::recover()`].
+    /// See [`Storage::commit_inserts()`] for flash not implementing [`MultiwriteNorFlash`].
+    pub async fn commit(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), Error<<F as ErrorType>::Error>> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let journal_key = OS_NAMESPACE.key(JOURNAL_KEY);
+        // Writing the journal is the point from which the transaction will be applied.
+        self.insert_blob(&journal_key, transaction.as_bytes())
+            .await?;
+        self.apply(transaction.as_bytes()).await?;
+        self.insert_blob(&journal_key, &[]).await?;
+        self.remove_stale_chunks(&journal_key).await
+    }
+
+    /// Completes a transaction interrupted by power loss, if any.
+    ///
+    /// This must be called before using a [`Storage`] instance on which [`Storage::commit()`] may
+    /// have been interrupted. The global storage does so during initialization.
+    /// Only the stale chunks of the journal are removed, see [`Storage::remove_all_stale_chunks()`]
+    /// for those of other blobs.
+    pub async fn recover(&mut self) -> Result<(), Error<<F as ErrorType>::Error>> {
+        {
+            let mut buffer = JOURNAL_BUFFER.lock().await;
+            let Some(journal) = self.read_journal(&mut buffer).await? else {
+                return Ok(());
+            };
+            ariel_os_log::info!("storage: completing interrupted transaction");
+            self.apply(journal).await?;
+        }
+
+        let journal_key = OS_NAMESPACE.key(JOURNAL_KEY);
+        self.insert_blob(&journal_key, &[]).await?;
+        self.remove_stale_chunks(&journal_key).await
+    }
+
+    /// Applies the serialized operations of a journal.
+    async fn apply(&mut self, mut journal: &[u8]) -> Result<(), Error<<F as ErrorType>::Error>> {
+        while let Some(operation) = take_operation(&mut journal)? {
+            match operation {
+                Operation::Insert { key, value } => self.insert_raw(key, ChunkBytes(value)).await?,
+                Operation::Remove { key } => self.remove(key).await?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the first serialized operation off `journal`, if any.
+fn take_operation<'a, E>(journal: &mut &'a [u8]) -> Result<Option<Operation<'a>>, Error<E>> {
+    if journal.is_empty() {
+        return Ok(None);
+    }
+    let (operation, rest) = take_from_bytes(journal).map_err(|_| Error::Corrupted)?;
+    *journal = rest;
+    Ok(Some(operation))
+}
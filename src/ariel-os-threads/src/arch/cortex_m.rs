@@ -7,6 +7,12 @@ use cortex_m::peripheral::{SCB, scb::SystemHandler};
 #[cfg(not(any(armv6m, armv7m, armv8m)))]
 compile_error!("no supported ARM variant selected");
 
+#[cfg(feature = "stack-guard")]
+mod stack_guard;
+
+#[cfg(feature = "stack-guard")]
+pub use stack_guard::stack_guard_end;
+
 // Default EXC_RETURN value used for newly created threads when returning to
 // Thread mode. We know FPU hasn't been used because the thread hasn't run.
 #[cfg(any(armv7m_eabihf, armv8m_eabihf))]
@@ -98,6 +104,8 @@ impl Arch for Cpu {
             let mut p = cortex_m::Peripherals::steal();
             p.SCB.set_priority(SystemHandler::PendSV, 0xFF);
         }
+        #[cfg(feature = "stack-guard")]
+        stack_guard::init();
         Self::schedule();
     }
 
@@ -347,6 +355,12 @@ unsafe extern "C" fn sched() -> u64 {
                 cortex_m::register::psplim::write(next.stack_lowest as u32)
             };
 
+            #[cfg(all(feature = "stack-guard", armv7m))]
+            // SAFETY: moving the stack guard as part of context switch
+            unsafe {
+                stack_guard::set(next.stack_lowest)
+            };
+
             let next_high_regs = next.data.high_regs.as_ptr();
 
             Some((current_high_regs as u32, next_high_regs as u32))
@@ -0,0 +1,148 @@
+//! Stack overflow detection using the hardware of the core.
+//!
+//! On ARMv7-M, the lowest 32-byte aligned block of the stack of the running thread is made
+//! inaccessible using an MPU region, so that an overflow raises a MemManage fault.
+//! On ARMv8-M, the PSP limit register, which is always set to the lowest stack address, raises a
+//! UsageFault on overflow.
+//! In both cases, the fault handler panics with the ID of the offending thread.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::SCHEDULER;
+
+#[cfg(armv6m)]
+compile_error!("`stack-guard` is not supported on ARMv6-M, use `stack-canary` instead");
+
+/// System Handler Control and State Register.
+const SHCSR: *mut u32 = 0xE000_ED24 as *mut u32;
+/// Configurable Fault Status Register.
+const CFSR: *const u32 = 0xE000_ED28 as *const u32;
+
+/// Size of the MPU guard region, which is also its alignment.
+#[cfg(armv7m)]
+const GUARD_SIZE: usize = 32;
+
+#[cfg(armv7m)]
+mod mpu {
+    /// MPU Type Register.
+    pub const TYPE: *const u32 = 0xE000_ED90 as *const u32;
+    /// MPU Control Register.
+    pub const CTRL: *mut u32 = 0xE000_ED94 as *mut u32;
+    /// MPU Region Number Register.
+    pub const RNR: *mut u32 = 0xE000_ED98 as *mut u32;
+    /// MPU Region Base Address Register.
+    pub const RBAR: *mut u32 = 0xE000_ED9C as *mut u32;
+    /// MPU Region Attribute and Size Register.
+    pub const RASR: *mut u32 = 0xE000_EDA0 as *mut u32;
+}
+
+/// Returns the lowest address above the guard of a stack starting at `lowest`.
+pub fn stack_guard_end(lowest: usize) -> usize {
+    #[cfg(armv7m)]
+    {
+        lowest.next_multiple_of(GUARD_SIZE) + GUARD_SIZE
+    }
+    #[cfg(armv8m)]
+    {
+        lowest
+    }
+}
+
+/// Enables the stack guard and the fault handler.
+///
+/// # Panics
+///
+/// Panics on ARMv7-M if the core has no MPU.
+pub fn init() {
+    // SAFETY: configuring the MPU and the fault handlers before threads are started.
+    unsafe {
+        #[cfg(armv7m)]
+        {
+            let regions = (read_volatile(mpu::TYPE) >> 8) & 0xFF;
+            assert!(regions > 0, "`stack-guard` requires an MPU");
+            // Enable the MPU, keeping the default memory map as background region.
+            write_volatile(mpu::CTRL, 0b101);
+            // Enable MemManage faults.
+            write_volatile(SHCSR, read_volatile(SHCSR) | (1 << 16));
+        }
+        #[cfg(armv8m)]
+        {
+            // Enable UsageFaults.
+            write_volatile(SHCSR, read_volatile(SHCSR) | (1 << 18));
+        }
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Moves the MPU guard region to the stack starting at `lowest`.
+///
+/// Uses the last MPU region, which takes precedence over the others.
+///
+/// # Safety
+///
+/// Must only be called during a context switch, with `lowest` the lowest stack address of the
+/// next thread.
+#[cfg(armv7m)]
+pub unsafe fn set(lowest: usize) {
+    let base = stack_guard_end(lowest) - GUARD_SIZE;
+    // SAFETY: reconfiguring the MPU region reserved for the stack guard.
+    unsafe {
+        let region = ((read_volatile(mpu::TYPE) >> 8) & 0xFF) - 1;
+        write_volatile(mpu::RNR, region);
+        write_volatile(mpu::RBAR, base as u32);
+        // No access, execute never, size 2^(4+1) bytes, enabled.
+        write_volatile(
+            mpu::RASR,
+            (1 << 28) | ((GUARD_SIZE.trailing_zeros() - 1) << 1) | 1,
+        );
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Panics with the ID of the thread that was running when the fault occurred.
+fn stack_overflow() -> ! {
+    // The scheduler may be borrowed by the thread that overflowed, so it is accessed directly.
+    // SAFETY: only reading the current thread ID, the thread does not resume.
+    let thread_id = critical_section::with(|cs| unsafe { &*SCHEDULER.as_ptr(cs) }.current_tid());
+    match thread_id {
+        Some(thread_id) => panic!("stack overflow in thread {}", usize::from(thread_id)),
+        None => panic!("stack overflow"),
+    }
+}
+
+/// MemManage fault handler.
+///
+/// # Safety
+///
+/// - must not be called manually
+#[cfg(armv7m)]
+#[cortex_m_rt::exception]
+unsafe fn MemoryManagement() -> ! {
+    // SAFETY: reading a fault status register.
+    let cfsr = unsafe { read_volatile(CFSR) };
+    // The guard is the only region without access, so data access violations and stacking
+    // errors are overflows.
+    if cfsr & 0b1_0010 != 0 {
+        stack_overflow();
+    }
+    panic!("MemManage fault, CFSR: {:#x}", cfsr);
+}
+
+/// UsageFault handler.
+///
+/// # Safety
+///
+/// - must not be called manually
+#[cfg(armv8m)]
+#[cortex_m_rt::exception]
+unsafe fn UsageFault() -> ! {
+    // SAFETY: reading a fault status register.
+    let cfsr = unsafe { read_volatile(CFSR) };
+    // STKOF: stack pointer limit violation.
+    if cfsr & (1 << 20) != 0 {
+        stack_overflow();
+    }
+    panic!("UsageFault, CFSR: {:#x}", cfsr);
+}
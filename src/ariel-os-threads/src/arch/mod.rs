@@ -1,5 +1,8 @@
 use crate::Thread;
 
+#[cfg(all(feature = "stack-guard", not(context = "cortex-m")))]
+compile_error!("`stack-guard` is only supported on Cortex-M, use `stack-canary` instead");
+
 /// Arch-specific implementations for the scheduler.
 pub trait Arch {
     const DEFAULT_THREAD_DATA: Self::ThreadData;
@@ -55,7 +58,7 @@ cfg_select! {
     }
     context = "native" => {
         mod native;
-        pub use native::Cpu;
+        pub use native::{Cpu, is_idle, wait_for_idle};
     }
     _ => {
         pub struct Cpu;
@@ -84,3 +87,13 @@ pub type ThreadData = <Cpu as Arch>::ThreadData;
 pub fn schedule() {
     Cpu::schedule();
 }
+
+/// Returns the lowest address above the stack guard of a stack starting at `lowest`.
+///
+/// Without the `stack-guard` feature, this is `lowest`.
+pub fn stack_guard_end(lowest: usize) -> usize {
+    cfg_select! {
+        all(feature = "stack-guard", context = "cortex-m") => cortex_m::stack_guard_end(lowest),
+        _ => lowest,
+    }
+}
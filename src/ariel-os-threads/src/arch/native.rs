@@ -4,23 +4,85 @@ use std::cell::Cell;
 
 use std::sync::atomic::{AtomicU32, Ordering};
 
-use crate::{Arch, SCHEDULER, ThreadId, ThreadState, thread::Thread};
+use crate::{Arch, ThreadId, thread::Thread};
+#[cfg(feature = "infini-core")]
+use crate::{SCHEDULER, ThreadState};
 
 pub struct Cpu;
 
 mod critical_section;
+#[cfg(feature = "single-core")]
+mod single_core;
 
 static THREAD_RUNNABLE: [AtomicU32; crate::THREAD_COUNT] =
     [const { AtomicU32::new(0) }; crate::THREAD_COUNT];
 
+/// Number of threads that are runnable, i.e., whose entry in `THREAD_RUNNABLE` is set.
+static RUNNABLE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Returns whether no thread is runnable, i.e., all threads are blocked or have ended.
+pub fn is_idle() -> bool {
+    RUNNABLE_COUNT.load(Ordering::Acquire) == 0
+}
+
+/// Blocks the calling host thread until no thread is runnable.
+///
+/// This must not be called from a thread.
+pub fn wait_for_idle() {
+    loop {
+        let count = RUNNABLE_COUNT.load(Ordering::Acquire);
+        if count == 0 {
+            return;
+        }
+        atomic_wait::wait(&RUNNABLE_COUNT, count);
+    }
+}
+
+fn set_runnable(n: usize) {
+    if THREAD_RUNNABLE[n].swap(1, Ordering::Acquire) == 0 {
+        RUNNABLE_COUNT.fetch_add(1, Ordering::AcqRel);
+        atomic_wait::wake_one(&THREAD_RUNNABLE[n]);
+    }
+}
+
+fn clear_runnable(n: usize) {
+    if THREAD_RUNNABLE[n].swap(0, Ordering::Release) == 1
+        && RUNNABLE_COUNT.fetch_sub(1, Ordering::AcqRel) == 1
+    {
+        atomic_wait::wake_all(&RUNNABLE_COUNT);
+    }
+}
+
+#[cfg(feature = "single-core")]
+fn is_runnable(thread_id: ThreadId) -> bool {
+    THREAD_RUNNABLE[usize::from(thread_id)].load(Ordering::Acquire) == 1
+}
+
+/// Blocks the calling host thread until the thread is runnable.
+///
+/// This only uses atomics and futexes, so that it can be called from a signal handler.
+fn wait_until_runnable(thread_id: ThreadId) {
+    let runnable = &THREAD_RUNNABLE[usize::from(thread_id)];
+    while runnable.load(Ordering::Acquire) == 0 {
+        atomic_wait::wait(runnable, 0);
+    }
+}
+
 #[derive(Debug)]
 pub struct ThreadData {
     thread: Option<std::thread::Thread>,
+    /// Host thread to send the preemption signal to.
+    #[cfg(feature = "single-core")]
+    pthread: libc::pthread_t,
 }
 
 impl ThreadData {
     pub const fn new() -> Self {
-        Self { thread: None }
+        Self {
+            thread: None,
+            #[cfg(feature = "single-core")]
+            pthread: 0,
+        }
     }
 }
 
@@ -34,12 +96,20 @@ impl Arch for Cpu {
     type ThreadData = ThreadData;
     const DEFAULT_THREAD_DATA: Self::ThreadData = ThreadData::new();
 
-    fn setup_stack(thread: &mut Thread, _stack: &mut [u8], func: fn(), arg: Option<usize>) {
+    fn setup_stack(thread: &mut Thread, stack: &mut [u8], func: fn(), arg: Option<usize>) {
         let thread_id = thread.tid;
 
+        // Threads run on host stacks, the stack is only kept for stack overflow detection and
+        // reporting.
+        let stack_range = stack.as_mut_ptr_range();
+        thread.stack_lowest = stack_range.start as usize;
+        thread.stack_highest = stack_range.end as usize;
+        // SAFETY: the stack is not used by the thread.
+        unsafe { thread.stack_paint_init(thread.stack_highest) };
+
         let handle = std::thread::spawn(move || {
             ThreadData::ID.with(|x| x.set(Some(thread_id)));
-            atomic_wait::wait(&THREAD_RUNNABLE[usize::from(thread_id)], 0);
+            wait_until_runnable(thread_id);
 
             // We use catch_unwind here to catch if a thread panics.
             // In that case, we abort the process, which is as close as it gets
@@ -73,22 +143,32 @@ impl Arch for Cpu {
                 std::process::abort();
             }
 
-            SCHEDULER.with_mut(|mut scheduler| {
-                scheduler.set_state(thread_id, ThreadState::Invalid);
-            });
+            crate::join::exit_current();
         });
 
         thread.data.thread = Some(handle.thread().clone());
+        #[cfg(feature = "single-core")]
+        {
+            use std::os::unix::thread::JoinHandleExt as _;
+            thread.data.pthread = handle.as_pthread_t();
+        }
     }
 
+    #[cfg(feature = "single-core")]
+    fn start_threading() {
+        single_core::start();
+        loop {
+            std::thread::park();
+        }
+    }
+
+    #[cfg(feature = "infini-core")]
     fn start_threading() {
         loop {
             SCHEDULER.with(|scheduler| {
                 for (n, thread) in scheduler.threads.iter().enumerate() {
                     if thread.state == ThreadState::Running {
-                        if THREAD_RUNNABLE[n].swap(1, Ordering::Acquire) == 0 {
-                            atomic_wait::wake_one(&THREAD_RUNNABLE[n]);
-                        }
+                        set_runnable(n);
                     }
                 }
             });
@@ -97,20 +177,22 @@ impl Arch for Cpu {
         }
     }
 
-    fn schedule() {}
+    fn schedule() {
+        #[cfg(feature = "single-core")]
+        single_core::schedule();
+    }
 
     fn wfi() {
         unimplemented!()
     }
 
+    #[cfg(feature = "infini-core")]
     fn set_running(thread_id: ThreadId) {
-        let n = usize::from(thread_id);
-        if THREAD_RUNNABLE[n].swap(1, Ordering::Acquire) == 0 {
-            atomic_wait::wake_one(&THREAD_RUNNABLE[n]);
-        }
+        set_runnable(usize::from(thread_id));
     }
 
+    #[cfg(feature = "infini-core")]
     fn set_stopped(thread_id: ThreadId) {
-        THREAD_RUNNABLE[usize::from(thread_id)].store(0, Ordering::Release);
+        clear_runnable(usize::from(thread_id));
     }
 }
@@ -24,14 +24,24 @@
 //! 3. We wrap the regular `StdCriticalSection` in `ArielCriticalSection`
 //! 4. In the wrapped critical section, on `release()`, after the wrapped critical section ends, we
 //!    block the thread on its `AtomicLock`.
+//!
+//! With `single-core`, the scheduler additionally runs on `release()` right before the wrapped
+//! critical section ends, like the ISR on our MCU platforms, see `single_core`.
 
 #![expect(unsafe_code)]
 
+use std::cell::Cell;
+
 mod upstream;
 use upstream::StdCriticalSection;
 
 critical_section::set_impl!(ArielCriticalSection);
 
+std::thread_local! {
+    /// Whether the host thread is acquiring, holding, or releasing the critical section.
+    pub(super) static IN_CRITICAL_SECTION: Cell<bool> = const { Cell::new(false) };
+}
+
 struct ArielCriticalSection;
 
 // SAFETY:
@@ -42,8 +52,28 @@ unsafe impl critical_section::Impl for ArielCriticalSection {
     // SAFETY:
     // This is delegating safety to the upstream implementation.
     unsafe fn acquire() -> bool {
-        // SAFETY: delegating safety to upstream implementation
-        unsafe { StdCriticalSection::acquire() }
+        loop {
+            IN_CRITICAL_SECTION.set(true);
+            // SAFETY: delegating safety to upstream implementation
+            let nested_cs = unsafe { StdCriticalSection::acquire() };
+
+            // With single-core, a thread that has been switched away from may still be running
+            // until it has been preempted, but must not access any shared state until it is
+            // switched to again.
+            #[cfg(feature = "single-core")]
+            if !nested_cs
+                && let Some(thread_id) = crate::ThreadData::ID.get()
+                && !super::is_runnable(thread_id)
+            {
+                // SAFETY: delegating safety to upstream implementation
+                unsafe { StdCriticalSection::release(false) };
+                IN_CRITICAL_SECTION.set(false);
+                super::wait_until_runnable(thread_id);
+                continue;
+            }
+
+            return nested_cs;
+        }
     }
 
     // SAFETY:
@@ -52,13 +82,19 @@ unsafe impl critical_section::Impl for ArielCriticalSection {
     unsafe fn release(nested_cs: bool) {
         if !nested_cs {
             let thread_id = crate::ThreadData::ID.get();
+            #[cfg(feature = "single-core")]
+            // SAFETY: this is the end of the outermost critical section, which is still held.
+            unsafe {
+                super::single_core::sched();
+            }
             // SAFETY: delegating safety to upstream implementation
             unsafe { StdCriticalSection::release(false) }
+            IN_CRITICAL_SECTION.set(false);
             // The critical section technically ends here.
-            // For infinicore, we now block until the thread is runnable again,
-            // indicated by the threads entry in `THREAD_RUNNABLE`.
+            // We now block until the thread is runnable again, indicated by the threads entry in
+            // `THREAD_RUNNABLE`.
             if let Some(thread_id) = thread_id {
-                atomic_wait::wait(&super::THREAD_RUNNABLE[usize::from(thread_id)], 0);
+                super::wait_until_runnable(thread_id);
 
                 // For regular multicore, setting a thread to `Runnable` (e.g., by doing a
                 // `threadlist::pop()`) triggers a scheduler run by pending an ISR that implicitly
@@ -73,11 +109,15 @@ unsafe impl critical_section::Impl for ArielCriticalSection {
                 // To work around this, we explicitly get/release the (global) critical section
                 // here again, making sure the thread that woke this thread up finishes it's
                 // critical section first.
-                //
-                // SAFETY: delegating safety to upstream implementation
-                unsafe { StdCriticalSection::acquire() };
-                // SAFETY: delegating safety to upstream implementation
-                unsafe { StdCriticalSection::release(false) };
+                // With single-core, the scheduler only runs when that critical section ends
+                // anyway.
+                #[cfg(feature = "infini-core")]
+                {
+                    // SAFETY: delegating safety to upstream implementation
+                    unsafe { StdCriticalSection::acquire() };
+                    // SAFETY: delegating safety to upstream implementation
+                    unsafe { StdCriticalSection::release(false) };
+                }
             }
         }
     }
@@ -0,0 +1,114 @@
+//! Single-core scheduling on native.
+//!
+//! Only the current thread is runnable, all other threads block on their entry in
+//! `THREAD_RUNNABLE`.
+//!
+//! Like the ISR on our MCU platforms, the scheduler runs at the end of the outermost critical
+//! section in which it has been triggered, on whichever host thread that is: a thread, or e.g.
+//! the alarm thread of the time driver.
+//! A running thread that is switched away from by another host thread is preempted by a signal,
+//! whose handler blocks it until it is switched to again.
+//! As the signal is delivered asynchronously, the thread may keep running for a short while, but
+//! it blocks before entering a critical section, and a thread that is inside a critical section
+//! when the signal arrives blocks once it has left it.
+//!
+//! Host calls are not aware of the scheduler: a thread that is preempted while holding a host
+//! lock, e.g., while printing to stdout, blocks other threads that need that lock until it is
+//! switched to again.
+
+#![expect(unsafe_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use critical_section::CriticalSection;
+
+use super::{
+    ThreadData, clear_runnable, critical_section::IN_CRITICAL_SECTION, set_runnable,
+    wait_until_runnable,
+};
+use crate::SCHEDULER;
+
+/// Whether the scheduler has been triggered.
+static SCHEDULE_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Returns the signal used to preempt threads.
+fn preempt_signal() -> libc::c_int {
+    libc::SIGRTMIN()
+}
+
+/// Installs the preemption signal handler and switches to the first thread.
+pub(super) fn start() {
+    // SAFETY: the handler only uses atomics, futexes and constant-initialized thread-locals, which
+    // is async-signal-safe, and it restores `errno`.
+    unsafe {
+        let mut action: libc::sigaction = core::mem::zeroed();
+        action.sa_sigaction = on_preempt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&raw mut action.sa_mask);
+        assert_eq!(
+            libc::sigaction(preempt_signal(), &raw const action, core::ptr::null_mut()),
+            0,
+            "failed to install the preemption signal handler"
+        );
+    }
+
+    schedule();
+}
+
+/// Triggers the scheduler at the end of the current critical section.
+pub(super) fn schedule() {
+    critical_section::with(|_| SCHEDULE_PENDING.store(true, Ordering::Relaxed));
+}
+
+/// Switches to the next thread if the scheduler has been triggered.
+///
+/// # Safety
+///
+/// Must only be called at the end of the outermost critical section, while it is still held.
+pub(super) unsafe fn sched() {
+    // SAFETY: the critical section is held.
+    let cs = unsafe { CriticalSection::new() };
+
+    // Scheduling may trigger the scheduler again, e.g., when starting a time slice.
+    while SCHEDULE_PENDING.swap(false, Ordering::Relaxed) {
+        SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let next_tid = scheduler.get_next_tid();
+            let current_tid = core::mem::replace(scheduler.current_tid_mut(), next_tid);
+            if next_tid == current_tid {
+                return;
+            }
+
+            if let Some(current_tid) = current_tid {
+                clear_runnable(usize::from(current_tid));
+                // A thread that switches away from itself blocks at the end of the critical
+                // section.
+                if ThreadData::ID.get() != Some(current_tid) {
+                    let pthread = scheduler.get_unchecked(current_tid).data.pthread;
+                    // SAFETY: the host threads of threads never end.
+                    unsafe { libc::pthread_kill(pthread, preempt_signal()) };
+                }
+            }
+            if let Some(next_tid) = next_tid {
+                set_runnable(usize::from(next_tid));
+            }
+        });
+    }
+}
+
+/// Blocks the preempted thread until it is switched to again.
+///
+/// A thread that is inside a critical section blocks at its end instead.
+extern "C" fn on_preempt(_signal: libc::c_int) {
+    if IN_CRITICAL_SECTION.get() {
+        return;
+    }
+    let Some(thread_id) = ThreadData::ID.get() else {
+        return;
+    };
+
+    // SAFETY: `errno` is thread-local.
+    let errno = unsafe { *libc::__errno_location() };
+    wait_until_runnable(thread_id);
+    // SAFETY: `errno` is thread-local.
+    unsafe { *libc::__errno_location() = errno };
+}
@@ -0,0 +1,129 @@
+//! Runtime information about threads.
+
+#[cfg(feature = "stats")]
+use embassy_time::Duration;
+
+use crate::{
+    CoreAffinity, RunqueueId, SCHEDULER, THREAD_COUNT, ThreadId, ThreadState,
+    thread::stack_unused_len,
+};
+
+/// Number of stack bytes scanned per critical section when measuring the stack usage of a thread.
+const STACK_SCAN_CHUNK: usize = 256;
+
+/// Snapshot of the state of a thread, as returned by [`info()`] and [`thread_info()`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ThreadInfo {
+    /// ID of the thread.
+    pub thread_id: ThreadId,
+    /// State of the thread.
+    pub state: ThreadState,
+    /// Current priority of the thread.
+    pub priority: RunqueueId,
+    /// Cores the thread can be scheduled on.
+    pub core_affinity: CoreAffinity,
+    /// Size of the stack of the thread, in bytes.
+    pub stack_size: usize,
+    /// Peak amount of stack used by the thread, in bytes, measured through stack painting.
+    ///
+    /// This is a lower bound, see `ariel_os::debug::stack::Stack`, and is zero on native.
+    pub stack_peak_usage: usize,
+    /// Number of times the thread has been switched to.
+    ///
+    /// This is zero on native, where each thread runs on its own host thread.
+    #[cfg(feature = "stats")]
+    pub context_switches: u32,
+    /// Time the thread has spent running.
+    #[cfg(feature = "stats")]
+    pub cpu_time: Duration,
+}
+
+impl ThreadInfo {
+    /// Returns the amount of stack that has never been used by the thread, in bytes.
+    #[must_use]
+    pub fn stack_free_min(&self) -> usize {
+        self.stack_size - self.stack_peak_usage
+    }
+}
+
+/// Returns a snapshot of each existing thread, in order of their [`ThreadId`].
+///
+/// Each thread is inspected when the returned iterator is advanced. Measuring its stack usage runs
+/// in `O(n)` of its stack size, but the stack is scanned in small chunks, each in a separate
+/// critical section, so that interrupts are not held off for the duration of the scan.
+///
+/// Example, printing a `ps`-style table:
+///
+/// ```ignore
+/// for thread in ariel_os::thread::info() {
+///     info!(
+///         "{:?} {:?} prio={} stack={}/{}",
+///         thread.thread_id,
+///         thread.state,
+///         usize::from(thread.priority),
+///         thread.stack_peak_usage,
+///         thread.stack_size,
+///     );
+/// }
+/// ```
+pub fn info() -> impl Iterator<Item = ThreadInfo> {
+    (0..THREAD_COUNT).filter_map(|i| thread_info(ThreadId::new(i as u8)))
+}
+
+/// Returns a snapshot of a thread, or `None` if no thread exists with this [`ThreadId`], or if it
+/// ended while being inspected.
+///
+/// See [`info()`].
+#[must_use]
+pub fn thread_info(thread_id: ThreadId) -> Option<ThreadInfo> {
+    let (mut info, stack) = SCHEDULER.with(|scheduler| {
+        if !scheduler.is_valid_tid(thread_id) {
+            return None;
+        }
+        let thread = scheduler.get_unchecked(thread_id);
+
+        #[cfg(feature = "core-affinity")]
+        let core_affinity = thread.core_affinity;
+        #[cfg(not(feature = "core-affinity"))]
+        let core_affinity = CoreAffinity::no_affinity();
+
+        let info = ThreadInfo {
+            thread_id,
+            state: thread.state,
+            priority: thread.prio,
+            core_affinity,
+            stack_size: thread.stack_highest - thread.stack_lowest,
+            stack_peak_usage: 0,
+            #[cfg(feature = "stats")]
+            context_switches: thread.context_switches,
+            #[cfg(feature = "stats")]
+            cpu_time: Duration::from_ticks(thread.cpu_ticks),
+        };
+        Some((info, thread.stack_painted_range()))
+    })?;
+
+    let mut unused = 0;
+    for start in stack.clone().step_by(STACK_SCAN_CHUNK) {
+        let chunk = start..(start + STACK_SCAN_CHUNK).min(stack.end);
+        let chunk_len = chunk.len();
+        let chunk_unused = SCHEDULER.with(|scheduler| {
+            // The thread may have finished since the previous chunk, and its stack been freed or
+            // handed to another thread.
+            if !scheduler.is_valid_tid(thread_id)
+                || scheduler.get_unchecked(thread_id).stack_painted_range() != stack
+            {
+                return None;
+            }
+            // SAFETY: the thread is valid and still owns the stack the chunk is part of.
+            Some(unsafe { stack_unused_len(chunk) })
+        })?;
+        unused += chunk_unused;
+        if chunk_unused < chunk_len {
+            break;
+        }
+    }
+    info.stack_peak_usage = stack.len() - unused;
+
+    Some(info)
+}
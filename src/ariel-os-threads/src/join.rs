@@ -0,0 +1,219 @@
+//! Joining threads and retrieving their exit value.
+
+use core::marker::PhantomData;
+
+use embassy_time::{Duration, Instant};
+
+use crate::{SCHEDULER, ThreadId, ThreadState, Word, sync::WaitQueue};
+
+/// Threads waiting for any thread to finish.
+static JOIN_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Owned permission to join a thread, i.e., to wait for it to finish and get its exit value.
+///
+/// Returned by [`create_joinable()`](crate::create_joinable()) and
+/// [`create_joinable_noarg()`](crate::create_joinable_noarg()).
+/// `R` is the type returned by the thread function.
+///
+/// The thread slot of a finished thread is only reused once it has been joined, or once its
+/// [`JoinHandle`] has been dropped.
+/// Dropping the [`JoinHandle`] detaches the thread: it keeps running, but cannot be joined
+/// anymore.
+#[derive(Debug)]
+pub struct JoinHandle<R: Word = ()> {
+    thread_id: ThreadId,
+    _exit_value: PhantomData<fn() -> R>,
+}
+
+impl<R: Word> JoinHandle<R> {
+    pub(crate) fn new(thread_id: ThreadId) -> Self {
+        Self {
+            thread_id,
+            _exit_value: PhantomData,
+        }
+    }
+
+    /// Returns the [`ThreadId`] of the thread.
+    #[must_use]
+    pub fn thread_id(&self) -> ThreadId {
+        self.thread_id
+    }
+
+    /// Returns whether the thread has returned from its function.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        SCHEDULER.with(|scheduler| {
+            scheduler.get_unchecked(self.thread_id).state == ThreadState::Finished
+        })
+    }
+
+    /// Waits for the thread to finish (blocking).
+    ///
+    /// Returns the value returned by the thread function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    #[expect(
+        clippy::must_use_candidate,
+        reason = "threads returning `()` are joined just to wait for them"
+    )]
+    pub fn join(self) -> R {
+        loop {
+            if let Some(exit_value) = critical_section::with(|cs| {
+                let exit_value = self.try_reap();
+                if exit_value.is_none() {
+                    JOIN_QUEUE.wait_cs(cs);
+                }
+                exit_value
+            }) {
+                core::mem::forget(self);
+                return exit_value;
+            }
+        }
+    }
+
+    /// Waits for the thread to finish, with deadline (blocking).
+    ///
+    /// Returns the value returned by the thread function.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`JoinHandle`] back if the thread did not finish before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn join_until(self, deadline: Instant) -> Result<R, Self> {
+        loop {
+            if let Some(exit_value) = self.try_reap() {
+                core::mem::forget(self);
+                return Ok(exit_value);
+            }
+            if Instant::now() >= deadline {
+                return Err(self);
+            }
+            // Other threads finishing wake this thread up as well.
+            JOIN_QUEUE.wait_until_with_check(deadline, |cs| {
+                SCHEDULER.with_cs(cs, |scheduler| {
+                    scheduler.get_unchecked(self.thread_id).state == ThreadState::Finished
+                })
+            });
+        }
+    }
+
+    /// Waits for the thread to finish, with timeout (blocking).
+    ///
+    /// See [`Self::join_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`JoinHandle`] back if the thread did not finish within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn join_timeout(self, timeout: Duration) -> Result<R, Self> {
+        self.join_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Frees the thread slot and returns the exit value, if the thread has finished.
+    fn try_reap(&self) -> Option<R> {
+        SCHEDULER.with_mut(|mut scheduler| {
+            let thread = scheduler.get_unchecked_mut(self.thread_id);
+            if thread.state != ThreadState::Finished {
+                return None;
+            }
+            let exit_value = thread.entry_or_exit;
+            scheduler.release(self.thread_id);
+            // SAFETY: the value has been stored by `run()` or `run_with_arg()` with the same `R`.
+            Some(unsafe { R::from_word(exit_value) })
+        })
+    }
+}
+
+impl<R: Word> Drop for JoinHandle<R> {
+    fn drop(&mut self) {
+        SCHEDULER.with_mut(|mut scheduler| {
+            let thread = scheduler.get_unchecked_mut(self.thread_id);
+            if thread.state == ThreadState::Finished {
+                scheduler.release(self.thread_id);
+            } else {
+                thread.joinable = false;
+            }
+        });
+    }
+}
+
+/// Runs the function of the current thread, and stores its exit value.
+///
+/// Used as thread function for threads created with
+/// [`create_joinable_noarg()`](crate::create_joinable_noarg()).
+pub(crate) fn run<R: Word>() {
+    // SAFETY: `create_joinable_noarg()` stored a `fn() -> R` as `fn()`.
+    let func = unsafe { core::mem::transmute::<*const (), fn() -> R>(entry()) };
+    set_exit_value(func());
+}
+
+/// Runs the function of the current thread with `arg`, and stores its exit value.
+///
+/// Used as thread function for threads created with [`create_joinable()`](crate::create_joinable()).
+pub(crate) fn run_with_arg<R: Word>(arg: usize) {
+    // SAFETY: `create_joinable()` stored a `fn(T) -> R` as `fn()`, with `T: Arguable`. The safety of
+    // calling it with a `usize` is enforced by the `Arguable` trait.
+    let func = unsafe { core::mem::transmute::<*const (), fn(usize) -> R>(entry()) };
+    set_exit_value(func(arg));
+}
+
+/// Returns the function of the current thread.
+fn entry() -> *const () {
+    SCHEDULER.with_mut(|mut scheduler| {
+        let entry = scheduler.current().map_or(0, |thread| thread.entry_or_exit);
+        assert!(
+            entry != 0,
+            "thread should have been created with an entry function"
+        );
+        entry as *const ()
+    })
+}
+
+fn set_exit_value<R: Word>(value: R) {
+    SCHEDULER.with_mut(|mut scheduler| {
+        if let Some(thread) = scheduler.current() {
+            thread.entry_or_exit = value.into_word();
+        }
+    });
+}
+
+/// Ends the current thread, after it returned from its function.
+///
+/// The thread slot is kept until the thread is joined if the thread has a [`JoinHandle`].
+/// Otherwise, a heap-allocated stack of the thread is freed on the next context switch.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub(crate) fn exit_current() {
+    critical_section::with(|_| {
+        SCHEDULER.with_mut(|mut scheduler| {
+            let thread = scheduler.current().unwrap();
+            let thread_id = thread.tid;
+            let state = if thread.joinable {
+                ThreadState::Finished
+            } else {
+                ThreadState::Invalid
+            };
+            scheduler.set_state(thread_id, state);
+
+            // The stack of a detached thread is freed as soon as the thread has been switched
+            // away from.
+            #[cfg(feature = "alloc")]
+            if state == ThreadState::Invalid {
+                scheduler.free_heap_stacks();
+            }
+        });
+        // Joiners only run once the outer critical section ends, at which point the state of
+        // this thread has been updated.
+        JOIN_QUEUE.notify_all();
+    });
+}
@@ -2,24 +2,66 @@
 //!
 //! Implements a scheduler based on fixed priorities and preemption.
 //! Within one priority level, threads are scheduled cooperatively.
-//! This means that there is no time slicing that would equally distribute CPU time among same-priority threads.
+//! This means that by default, there is no time slicing that would equally distribute CPU time among same-priority threads.
 //! **Instead, you need to use [`yield_same()`] to explicitly yield to another thread with the same priority.**
+//! With the `time-slice` feature, a thread that has ready peers of the same priority is
+//! preempted after running for `TIME_SLICE_US` microseconds, which can be configured using
+//! `CONFIG_THREAD_TIME_SLICE_US`.
 //! If no thread is ready, the core is prompted to enter deep sleep until a next thread is ready.
 //!
 //! Threads should be implemented using the `ariel_os_macros::thread` proc macro, which takes care
 //! of calling the necessary initialization methods and linking the thread function element it into the binary.
 //! A [`ThreadId`] between 0 and [`THREAD_COUNT`] is assigned to each thread in the order in
 //! which the threads are declared.
+//! [`THREAD_COUNT`] can be configured using `CONFIG_THREAD_COUNT`.
+//!
+//! With the `alloc` feature, threads can also be spawned at runtime with a heap-allocated stack,
+//! using `spawn()`; the stack is freed once the thread has ended.
 //!
 //! Optionally, the stacksize and a priority between 1 and [`SCHED_PRIO_LEVELS`] can be configured.
 //! By default, the stack size is 2048 bytes and priority is 1.
 //!
 //! # Synchronization
 //!
-//! The `threading` module supports three basic synchronization primitives:
-//! - [`Channel`](sync::Channel): synchronous (blocking) channel for sending data between threads
+//! The `threading` module supports these basic synchronization primitives:
+//! - [`Channel`](sync::Channel): rendezvous (blocking) channel for sending data between threads
+//! - [`Queue`](sync::Queue): bounded queue for sending data between threads and async tasks
 //! - [`Lock`](sync::Lock): basic locking object
+//! - [`Mutex`](sync::Mutex): mutual exclusion lock with priority inheritance
+//! - [`Event`](sync::Event): event that threads and async tasks can wait for
+//! - [`Semaphore`](sync::Semaphore): counting semaphore, e.g., for resource pools
+//! - [`RwLock`](sync::RwLock): reader-writer lock with writer preference
+//! - [`EventGroup`](sync::EventGroup): shared event bits that any number of threads can wait for
 //! - [`thread_flags`]: thread-flag implementation for signaling between threads
+//!
+//! The blocking operations of these primitives (and [`JoinHandle::join()`]) also come in
+//! `_until()` and `_timeout()` variants, which give up with a [`TimeoutError`] once a deadline is
+//! reached.
+//!
+//! # Joining threads
+//!
+//! Threads started using [`create_joinable()`] or [`create_joinable_noarg()`] may return a value
+//! implementing [`Word`]. The returned [`JoinHandle`] allows to wait for the thread to finish and
+//! to get that value.
+//!
+//! # Software timers
+//!
+//! The [`timer`] module provides one-shot and periodic timers, which set thread flags, signal an
+//! [`Event`](sync::Event), or run a callback in a dedicated timer-service thread when they expire.
+//!
+//! # Thread-local storage
+//!
+//! [`tls::LocalSlot`] gives each thread its own value, stored in a fixed number of per-thread
+//! slots. There are none by default, as each one takes a word in every thread slot.
+//!
+//! # Stack overflow detection
+//!
+//! With the `stack-canary` feature, a canary word is written at the lowest address of each thread
+//! stack, and checked whenever the thread is switched away from.
+//! With the `stack-guard` feature (ARMv7-M and ARMv8-M only), the lowest bytes of the stack of
+//! the running thread are protected using the MPU on ARMv7-M, or using the stack limit register on
+//! ARMv8-M.
+//! In both cases, an overflow panics with the [`ThreadId`] of the offending thread.
 
 #![cfg_attr(not(any(test, context = "native")), no_std)]
 #![cfg_attr(target_arch = "xtensa", feature(asm_experimental_arch))]
@@ -34,13 +76,25 @@
     reason = "should be addressed eventually"
 )]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 mod arch;
 mod autostart_thread;
 mod blocker;
 mod core_affinity;
 mod ensure_once;
+mod info;
+mod join;
+#[cfg(feature = "alloc")]
+mod spawn;
 mod thread;
 mod threadlist;
+#[cfg(all(
+    feature = "time-slice",
+    any(feature = "single-core", feature = "multi-core")
+))]
+mod time_slice;
 mod timeout;
 
 #[cfg(feature = "multi-core")]
@@ -48,6 +102,8 @@ mod smp;
 
 pub mod sync;
 pub mod thread_flags;
+pub mod timer;
+pub mod tls;
 
 #[doc(hidden)]
 pub mod macro_reexports {
@@ -67,19 +123,29 @@ pub mod events {
 pub use ariel_os_runqueue::{RunqueueId, ThreadId};
 pub use blocker::block_on;
 pub use core_affinity::CoreAffinity;
+pub use info::{ThreadInfo, info, thread_info};
+pub use join::JoinHandle;
+#[cfg(feature = "alloc")]
+pub use spawn::{SpawnError, spawn, spawn_noarg};
 pub use thread_flags as flags;
-pub use timeout::{sleep, sleep_until};
+pub use timeout::{TimeoutError, sleep, sleep_until};
 
 #[cfg(feature = "multi-core")]
 pub use smp::isr_stack_core1_get_limits;
 
+// Used by the simulated time driver of `ariel-os-native`.
+#[cfg(context = "native")]
+#[doc(hidden)]
+pub use arch::{is_idle, wait_for_idle};
+
 use arch::{Arch, Cpu, ThreadData, schedule};
 
 #[cfg(not(feature = "infini-core"))]
 use ariel_os_runqueue::RunQueue;
 
 use ensure_once::EnsureOnce;
-use thread::{Thread, ThreadState};
+use thread::Thread;
+pub use thread::ThreadState;
 
 #[cfg(feature = "multi-core")]
 use smp::{Multicore, schedule_on_core};
@@ -87,10 +153,46 @@ use smp::{Multicore, schedule_on_core};
 use static_cell::ConstStaticCell;
 
 /// The number of possible priority levels.
-pub const SCHED_PRIO_LEVELS: usize = THREAD_COUNT;
+///
+/// This can be configured using `CONFIG_SCHED_PRIO_LEVELS`, up to the number of bits of a
+/// `usize`.
+pub const SCHED_PRIO_LEVELS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_SCHED_PRIO_LEVELS",
+    16,
+    "number of thread priority levels"
+);
+
+const _: () = assert!(
+    SCHED_PRIO_LEVELS > 0 && SCHED_PRIO_LEVELS <= usize::BITS as usize,
+    "the number of priority levels must be between 1 and the number of bits of a `usize`"
+);
 
 /// The maximum number of concurrent threads that can be created.
-pub const THREAD_COUNT: usize = 16;
+///
+/// This can be configured using `CONFIG_THREAD_COUNT`.
+pub const THREAD_COUNT: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_COUNT",
+    16,
+    "maximum number of concurrent threads"
+);
+
+/// Length of the time slice of threads that have ready peers of the same priority, in
+/// microseconds.
+///
+/// This can be configured using `CONFIG_THREAD_TIME_SLICE_US`.
+/// With `infini-core` (native), where threads run in parallel, there is no time slicing.
+#[cfg(feature = "time-slice")]
+pub const TIME_SLICE_US: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_TIME_SLICE_US",
+    10_000,
+    "length of the time slice of same-priority threads (in microseconds)"
+);
+
+// `ThreadId`s are stored as `u8`, with `0xFF` used as special value by the runqueue.
+const _: () = assert!(
+    THREAD_COUNT > 0 && THREAD_COUNT < 255,
+    "`CONFIG_THREAD_COUNT` must be between 1 and 254"
+);
 
 /// Number of processor cores.
 pub const CORE_COUNT: usize = {
@@ -127,6 +229,21 @@ struct Scheduler {
     current_threads: [Option<ThreadId>; CORE_COUNT],
     #[cfg(feature = "single-core")]
     current_thread: Option<ThreadId>,
+
+    /// Whether an ended thread may still own a heap-allocated stack.
+    #[cfg(feature = "alloc")]
+    heap_stacks_pending: bool,
+
+    /// Time at which the current thread of each core has been switched to, in ticks.
+    #[cfg(feature = "stats")]
+    switched_in_at: [Option<u64>; CORE_COUNT],
+
+    /// Time slice state.
+    #[cfg(all(
+        feature = "time-slice",
+        any(feature = "single-core", feature = "multi-core")
+    ))]
+    time_slice: time_slice::TimeSlice,
 }
 
 impl Scheduler {
@@ -140,6 +257,15 @@ impl Scheduler {
             current_threads: [None; CORE_COUNT],
             #[cfg(feature = "single-core")]
             current_thread: None,
+            #[cfg(feature = "alloc")]
+            heap_stacks_pending: false,
+            #[cfg(feature = "stats")]
+            switched_in_at: [None; CORE_COUNT],
+            #[cfg(all(
+                feature = "time-slice",
+                any(feature = "single-core", feature = "multi-core")
+            ))]
+            time_slice: time_slice::TimeSlice::new(),
         }
     }
 
@@ -207,15 +333,32 @@ impl Scheduler {
         prio: RunqueueId,
         _core_affinity: Option<CoreAffinity>,
     ) -> Option<ThreadId> {
+        #[cfg(feature = "alloc")]
+        self.free_heap_stacks();
+
         let (thread, tid) = self.get_unused()?;
         thread.prio = prio;
         thread.tid = tid;
         thread.state = ThreadState::Parked;
+        thread.entry_or_exit = 0;
+        thread.joinable = false;
+        thread.tls_used = 0;
+        #[cfg(feature = "stats")]
+        {
+            thread.context_switches = 0;
+            thread.cpu_ticks = 0;
+        }
 
         // At least native needs the `tid` field populated, so we call this
         // after populating `thread` with the already known info.
         Cpu::setup_stack(thread, stack, func, arg);
 
+        // SAFETY: the stack has just been set up, and is not active yet.
+        #[cfg(feature = "stack-canary")]
+        unsafe {
+            thread.stack_canary_init();
+        }
+
         #[cfg(feature = "core-affinity")]
         {
             thread.core_affinity = _core_affinity.unwrap_or_default();
@@ -247,15 +390,64 @@ impl Scheduler {
     }
 
     /// Returns an unused [`ThreadId`] / Thread slot.
+    ///
+    /// Slots of ended threads that have not been switched away from yet are skipped.
     fn get_unused(&mut self) -> Option<(&mut Thread, ThreadId)> {
         for i in 0..THREAD_COUNT {
-            if self.threads[i].state == ThreadState::Invalid {
+            if self.threads[i].state == ThreadState::Invalid
+                && !self.is_current(ThreadId::new(i as u8))
+            {
                 return Some((&mut self.threads[i], ThreadId::new(i as u8)));
             }
         }
         None
     }
 
+    /// Releases the slot of an ended thread, so that it can be reused.
+    fn release(&mut self, thread_id: ThreadId) {
+        self.set_state(thread_id, ThreadState::Invalid);
+
+        #[cfg(feature = "alloc")]
+        self.free_heap_stacks();
+    }
+
+    /// Frees the heap-allocated stacks of all ended threads.
+    ///
+    /// The stack of a thread that is still current is kept until the next call, which happens
+    /// at the latest on the next context switch.
+    #[cfg(feature = "alloc")]
+    fn free_heap_stacks(&mut self) {
+        self.heap_stacks_pending = false;
+        for i in 0..THREAD_COUNT {
+            if self.threads[i].state != ThreadState::Invalid || self.threads[i].heap_stack.is_none()
+            {
+                continue;
+            }
+            if self.is_current(ThreadId::new(i as u8)) {
+                self.heap_stacks_pending = true;
+                continue;
+            }
+            if let Some(stack) = self.threads[i].heap_stack.take() {
+                // SAFETY: the thread has ended, and has been switched away from.
+                unsafe { stack.free() };
+            }
+        }
+    }
+
+    /// Returns whether the thread is the current thread on any core.
+    fn is_current(&self, thread_id: ThreadId) -> bool {
+        cfg_select! {
+            any(feature = "single-core", feature = "multi-core") => {
+                self.is_running(thread_id).is_some()
+            }
+            // On native, threads have their own stack anyway.
+            _ => {
+                let _ = thread_id;
+                false
+            }
+        }
+    }
+
     /// Checks if a thread with valid state exists for this `thread_id`.
     fn is_valid_tid(&self, thread_id: ThreadId) -> bool {
         if usize::from(thread_id) >= THREAD_COUNT {
@@ -281,6 +473,11 @@ impl Scheduler {
             #[cfg(not(feature = "infini-core"))]
             self.runqueue.add(tid, prio);
             self.schedule_if_higher_prio(tid, prio);
+            #[cfg(all(
+                feature = "time-slice",
+                any(feature = "single-core", feature = "multi-core")
+            ))]
+            self.time_slice_on_ready(prio);
 
             #[cfg(feature = "infini-core")]
             Cpu::set_running(tid);
@@ -289,6 +486,12 @@ impl Scheduler {
             // if it itself initiated it.
             debug_assert_eq!(Some(tid), self.current_tid());
 
+            // SAFETY: the thread is still running on its stack.
+            #[cfg(feature = "stack-canary")]
+            unsafe {
+                self.get_unchecked(tid).stack_canary_check();
+            }
+
             #[cfg(feature = "infini-core")]
             Cpu::set_stopped(tid);
 
@@ -440,9 +643,69 @@ impl Scheduler {
     /// On multi-core, the thread is removed so that subsequent calls will each
     /// return a different thread. This prevents that a thread is picked multiple
     /// times by the scheduler when it is invoked on different cores.
+    ///
+    /// With the `stack-canary` feature, this also checks the stack canary of the current thread.
+    /// With the `stats` feature, this also accounts the CPU time of the current thread and the
+    /// context switch to the next one.
+    /// With the `time-slice` feature, this also preempts the current thread if its time slice has
+    /// expired, and starts the time slice of the next one.
     #[allow(dead_code, reason = "used in scheduler implementation")]
     #[cfg(any(feature = "single-core", feature = "multi-core"))]
     fn get_next_tid(&mut self) -> Option<ThreadId> {
+        // SAFETY: the current thread, if any, has not been switched away from, so its stack is
+        // still valid.
+        #[cfg(feature = "stack-canary")]
+        if let Some(current) = self.current() {
+            unsafe { current.stack_canary_check() };
+        }
+
+        // Free the stacks of detached threads that have ended and been switched away from.
+        // On some architectures, this runs on the stack of the current thread, which is why an
+        // ended current thread keeps its stack until the next context switch.
+        #[cfg(feature = "alloc")]
+        if self.heap_stacks_pending {
+            self.free_heap_stacks();
+        }
+
+        #[cfg(any(feature = "stats", feature = "time-slice"))]
+        let now = embassy_time::Instant::now().as_ticks();
+
+        #[cfg(feature = "stats")]
+        let current_tid = {
+            let current_tid = self.current_tid();
+            if let Some(switched_in_at) = self.switched_in_at[usize::from(core_id())].take()
+                && let Some(current) = current_tid.map(|tid| self.get_unchecked_mut(tid))
+            {
+                current.cpu_ticks += now.saturating_sub(switched_in_at);
+            }
+            current_tid
+        };
+
+        #[cfg(all(feature = "time-slice", feature = "single-core"))]
+        self.time_slice_expire(now);
+
+        let next_tid = self.next_from_runqueue();
+
+        #[cfg(feature = "time-slice")]
+        if let Some(next_tid) = next_tid {
+            self.time_slice_start(next_tid, now);
+        }
+
+        #[cfg(feature = "stats")]
+        if let Some(next_tid) = next_tid {
+            self.switched_in_at[usize::from(core_id())] = Some(now);
+            if current_tid != Some(next_tid) {
+                let next = self.get_unchecked_mut(next_tid);
+                next.context_switches = next.context_switches.wrapping_add(1);
+            }
+        }
+
+        next_tid
+    }
+
+    /// Returns the next thread from the runqueue, see [`Self::get_next_tid()`].
+    #[cfg(any(feature = "single-core", feature = "multi-core"))]
+    fn next_from_runqueue(&mut self) -> Option<ThreadId> {
         // On single-core, only read the head of the runqueue.
         #[cfg(feature = "single-core")]
         {
@@ -626,12 +889,93 @@ unsafe impl<T: Sync + Sized> Arguable for &'static T {
     }
 }
 
+/// Trait for types that can be returned by threads, see [`JoinHandle`], and stored in
+/// thread-local slots, see [`tls::LocalSlot`].
+///
+/// # Safety
+///
+/// `from_word()` must return the value that was passed to `into_word()`.
+pub unsafe trait Word: Copy + Send + 'static {
+    /// Returns the value as a machine word.
+    fn into_word(self) -> usize;
+
+    /// Returns the value from a machine word.
+    ///
+    /// # Safety
+    ///
+    /// `word` must have been returned by [`Word::into_word()`] of the same type.
+    unsafe fn from_word(word: usize) -> Self;
+}
+
+// SAFETY: there is nothing to convert.
+unsafe impl Word for () {
+    fn into_word(self) -> usize {
+        0
+    }
+
+    unsafe fn from_word(_word: usize) -> Self {}
+}
+
+// SAFETY: `bool` is converted to `0` or `1` and back.
+unsafe impl Word for bool {
+    fn into_word(self) -> usize {
+        usize::from(self)
+    }
+
+    unsafe fn from_word(word: usize) -> Self {
+        word != 0
+    }
+}
+
+macro_rules! impl_word_for_int {
+    ($($int:ty),*) => {
+        $(
+            // SAFETY: the conversions are lossless for types that fit into a machine word.
+            unsafe impl Word for $int {
+                #[allow(clippy::cast_sign_loss, reason = "converted back by `from_word()`")]
+                fn into_word(self) -> usize {
+                    // Ensure that the value does fit into a single machine word.
+                    const {
+                        assert!(size_of::<$int>() <= size_of::<usize>());
+                    }
+                    self as usize
+                }
+
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_possible_wrap,
+                    reason = "converted from `into_word()`"
+                )]
+                unsafe fn from_word(word: usize) -> Self {
+                    word as $int
+                }
+            }
+        )*
+    };
+}
+
+impl_word_for_int!(u8, u16, u32, usize, i8, i16, i32, isize);
+
+// SAFETY: the pointer is converted back into the same `'static` reference.
+unsafe impl<T: Sync> Word for &'static T {
+    fn into_word(self) -> usize {
+        core::ptr::from_ref::<T>(self) as usize
+    }
+
+    unsafe fn from_word(word: usize) -> Self {
+        // SAFETY: `word` has been returned by `into_word()`, so it is a valid `'static` reference.
+        unsafe { &*(word as *const T) }
+    }
+}
+
 /// Low-level function to create a thread that runs
 /// `func` with `arg`.
 ///
 /// This sets up the stack for the thread and adds it to
 /// the runqueue.
 ///
+/// See [`create_joinable()`] to wait for the thread to finish and get a value it returns.
+///
 /// # Panics
 ///
 /// Panics if more than [`THREAD_COUNT`] concurrent threads have been created.
@@ -673,6 +1017,113 @@ pub fn create_noarg(
     unsafe { create_raw(func, None, stack, prio, core_affinity) }
 }
 
+/// Low-level function to create a joinable thread that runs `func` with `arg`.
+///
+/// Like [`create()`], but the returned [`JoinHandle`] allows to wait for the thread to finish
+/// and get the value returned by `func`. Dropping it detaches the thread.
+///
+/// # Panics
+///
+/// Panics if more than [`THREAD_COUNT`] concurrent threads have been created.
+pub fn create_joinable<T, R>(
+    func: fn(T) -> R,
+    arg: T,
+    stack: &'static mut [u8],
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> JoinHandle<R>
+where
+    T: Arguable + Send,
+    R: Word,
+{
+    let (run, func) = joinable_entry(func);
+
+    unsafe {
+        create_with_entry(
+            run,
+            func,
+            Some(arg.into_arg()),
+            stack,
+            prio,
+            core_affinity,
+            |_| {},
+        )
+    }
+    .expect("Max `THREAD_COUNT` concurrent threads should be created.")
+}
+
+/// Low-level function to create a joinable thread without argument.
+///
+/// See [`create_joinable()`].
+///
+/// # Panics
+///
+/// Panics if more than [`THREAD_COUNT`] concurrent threads have been created.
+pub fn create_joinable_noarg<R: Word>(
+    func: fn() -> R,
+    stack: &'static mut [u8],
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> JoinHandle<R> {
+    let (run, func) = joinable_entry_noarg(func);
+
+    unsafe { create_with_entry(run, func, None, stack, prio, core_affinity, |_| {}) }
+        .expect("Max `THREAD_COUNT` concurrent threads should be created.")
+}
+
+/// Returns the thread function running `func` with an argument and storing its exit value, and
+/// `func` itself, both as `fn()`.
+fn joinable_entry<T: Arguable, R: Word>(func: fn(T) -> R) -> (fn(), fn()) {
+    // Convert `fn(T) -> R` and `fn(usize)` into `fn()`, must go through *const().
+    // SAFETY:
+    // https://doc.rust-lang.org/stable/std/primitive.fn.html#casting-to-and-from-integers
+    // "Transmuting between raw pointers and function pointers (i.e., two pointer types) is fine."
+    unsafe {
+        (
+            core::mem::transmute::<*const (), fn()>(join::run_with_arg::<R> as *const ()),
+            core::mem::transmute::<*const (), fn()>(func as *const ()),
+        )
+    }
+}
+
+/// Returns the thread function running `func` and storing its exit value, and `func` itself as
+/// `fn()`.
+fn joinable_entry_noarg<R: Word>(func: fn() -> R) -> (fn(), fn()) {
+    // SAFETY: transmuting between two pointer types, see `joinable_entry()`.
+    let func = unsafe { core::mem::transmute::<*const (), fn()>(func as *const ()) };
+    (join::run::<R>, func)
+}
+
+/// Creates a joinable thread running `run`, which calls `func` and stores its exit value.
+///
+/// `init` is called on the new thread before it is started.
+///
+/// Returns `None` if there is no free thread slot.
+///
+/// # Safety
+///
+/// `run` and `func` must have been returned by [`joinable_entry()`] or
+/// [`joinable_entry_noarg()`] with the `R` of the returned [`JoinHandle`].
+unsafe fn create_with_entry<R: Word>(
+    run: fn(),
+    func: fn(),
+    arg: Option<usize>,
+    stack: &'static mut [u8],
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+    init: impl FnOnce(&mut Thread),
+) -> Option<JoinHandle<R>> {
+    SCHEDULER.with_mut(|mut scheduler| {
+        let thread_id = scheduler.create(run, arg, stack, RunqueueId::new(prio), core_affinity)?;
+        let thread = scheduler.get_unchecked_mut(thread_id);
+        thread.entry_or_exit = func as usize;
+        thread.joinable = true;
+        init(thread);
+        scheduler.set_state(thread_id, ThreadState::Running);
+        Some(JoinHandle::new(thread_id))
+    })
+}
+
 /// Creates a thread, low-level.
 ///
 /// # Safety
@@ -731,10 +1182,7 @@ pub fn is_valid_tid(thread_id: ThreadId) -> bool {
 /// Panics if this is called outside of a thread context.
 #[allow(unused)]
 fn cleanup() -> ! {
-    SCHEDULER.with_mut(|mut scheduler| {
-        let thread_id = scheduler.current_tid().unwrap();
-        scheduler.set_state(thread_id, ThreadState::Invalid);
-    });
+    join::exit_current();
 
     unreachable!();
 }
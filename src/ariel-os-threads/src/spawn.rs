@@ -0,0 +1,177 @@
+//! Threads with heap-allocated stacks.
+//!
+//! The stack of a thread spawned using [`spawn()`] or [`spawn_noarg()`] is allocated from the
+//! heap, and freed once the thread has ended and its slot is released, i.e., once it has been
+//! joined, or on the first context switch after it has returned if its [`JoinHandle`] has been
+//! dropped.
+
+use alloc::alloc::{Layout, alloc, dealloc};
+use core::ptr::NonNull;
+
+use crate::{
+    Arguable, CoreAffinity, JoinHandle, Word, create_with_entry, joinable_entry,
+    joinable_entry_noarg,
+};
+
+/// Alignment of heap-allocated stacks.
+const STACK_ALIGN: usize = 16;
+
+/// Error returned when a thread could not be spawned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpawnError {
+    /// All [`THREAD_COUNT`](crate::THREAD_COUNT) thread slots are in use.
+    NoFreeSlot,
+    /// The stack could not be allocated.
+    OutOfMemory,
+}
+
+impl core::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoFreeSlot => write!(f, "no free thread slot"),
+            Self::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+impl core::error::Error for SpawnError {}
+
+/// Stack allocated from the heap.
+///
+/// Only the size is stored, the alignment is always [`STACK_ALIGN`].
+#[derive(Debug)]
+pub(crate) struct HeapStack {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+// SAFETY: the stack is only accessed by the thread it belongs to.
+unsafe impl Send for HeapStack {}
+
+impl HeapStack {
+    /// Allocates a stack of `size` bytes.
+    fn alloc(size: usize) -> Option<Self> {
+        let layout = Layout::from_size_align(size, STACK_ALIGN).ok()?;
+        if layout.size() == 0 {
+            return None;
+        }
+        // SAFETY: `layout` has a non-zero size.
+        let ptr = NonNull::new(unsafe { alloc(layout) })?;
+        Some(Self { ptr, size })
+    }
+
+    /// Returns the stack memory.
+    ///
+    /// # Safety
+    ///
+    /// The memory must only be handed to a single thread, and must not be used anymore once
+    /// [`Self::free()`] has been called.
+    unsafe fn as_static_mut(&mut self) -> &'static mut [u8] {
+        // SAFETY: the memory has been allocated with this size; see the function safety doc for
+        // its lifetime.
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.size) }
+    }
+
+    /// Frees the stack.
+    ///
+    /// # Safety
+    ///
+    /// The thread using the stack must have ended and must not be running anymore.
+    pub(crate) unsafe fn free(self) {
+        // SAFETY: the memory has been allocated with the same layout, which has been checked to
+        // be valid in `Self::alloc()`.
+        unsafe {
+            dealloc(
+                self.ptr.as_ptr(),
+                Layout::from_size_align_unchecked(self.size, STACK_ALIGN),
+            );
+        };
+    }
+}
+
+/// Spawns a thread with a heap-allocated stack of `stack_size` bytes, running `func` with `arg`.
+///
+/// See [`create_joinable()`](crate::create_joinable()).
+///
+/// # Errors
+///
+/// Returns [`SpawnError::NoFreeSlot`] if [`THREAD_COUNT`](crate::THREAD_COUNT) concurrent threads
+/// exist already, or [`SpawnError::OutOfMemory`] if the stack could not be allocated.
+pub fn spawn<T, R>(
+    func: fn(T) -> R,
+    arg: T,
+    stack_size: usize,
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> Result<JoinHandle<R>, SpawnError>
+where
+    T: Arguable + Send,
+    R: Word,
+{
+    let (run, func) = joinable_entry(func);
+    spawn_joinable(
+        run,
+        func,
+        Some(arg.into_arg()),
+        stack_size,
+        prio,
+        core_affinity,
+    )
+}
+
+/// Spawns a thread with a heap-allocated stack of `stack_size` bytes, without argument.
+///
+/// See [`spawn()`].
+///
+/// # Errors
+///
+/// Returns [`SpawnError::NoFreeSlot`] if [`THREAD_COUNT`](crate::THREAD_COUNT) concurrent threads
+/// exist already, or [`SpawnError::OutOfMemory`] if the stack could not be allocated.
+pub fn spawn_noarg<R: Word>(
+    func: fn() -> R,
+    stack_size: usize,
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> Result<JoinHandle<R>, SpawnError> {
+    let (run, func) = joinable_entry_noarg(func);
+    spawn_joinable(run, func, None, stack_size, prio, core_affinity)
+}
+
+fn spawn_joinable<R: Word>(
+    run: fn(),
+    func: fn(),
+    arg: Option<usize>,
+    stack_size: usize,
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> Result<JoinHandle<R>, SpawnError> {
+    let mut stack = HeapStack::alloc(stack_size).ok_or(SpawnError::OutOfMemory)?;
+    // SAFETY: the stack is only handed to the new thread, and is only freed once the thread has
+    // ended.
+    let stack_memory = unsafe { stack.as_static_mut() };
+    let mut stack = Some(stack);
+
+    // SAFETY: `run` and `func` come from `joinable_entry()` or `joinable_entry_noarg()`.
+    let handle = unsafe {
+        create_with_entry(
+            run,
+            func,
+            arg,
+            stack_memory,
+            prio,
+            core_affinity,
+            |thread| {
+                thread.heap_stack = stack.take();
+            },
+        )
+    };
+
+    handle.ok_or_else(|| {
+        if let Some(stack) = stack {
+            // SAFETY: no thread has been created with the stack.
+            unsafe { stack.free() };
+        }
+        SpawnError::NoFreeSlot
+    })
+}
@@ -10,9 +10,10 @@ use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 
-use crate::ThreadState;
 use crate::threadlist::ThreadList;
-use critical_section::with;
+use crate::{ThreadState, TimeoutError};
+use critical_section::{CriticalSection, with};
+use embassy_time::{Duration, Instant};
 
 enum ChannelState {
     Idle,
@@ -47,42 +48,83 @@ impl<T: Copy + Send> Channel<T> {
     ///
     /// Panics if this is called outside of a thread context.
     pub fn send(&self, something: &T) {
-        with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                ChannelState::Idle => {
-                    let mut waiters = ThreadList::new();
-                    waiters.put_current(
-                        cs,
-                        crate::ThreadState::ChannelTxBlocked(
-                            core::ptr::from_ref::<T>(something) as usize
-                        ),
-                    );
-                    *state = ChannelState::SendersWaiting(waiters);
-                }
-                ChannelState::ReceiversWaiting(waiters) => {
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelRxBlocked(ptr) = head_state {
-                            // copy over `something`
-                            unsafe { (ptr as *mut T).write(*something) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
+        with(|cs| self.send_cs(cs, something));
+    }
+
+    fn send_cs(&self, cs: CriticalSection<'_>, something: &T) {
+        let state = unsafe { &mut *self.state.get() };
+        let blocked =
+            crate::ThreadState::ChannelTxBlocked(core::ptr::from_ref::<T>(something) as usize);
+        match state {
+            ChannelState::Idle => {
+                let mut waiters = ThreadList::new();
+                waiters.put_current(cs, blocked);
+                *state = ChannelState::SendersWaiting(waiters);
+            }
+            ChannelState::ReceiversWaiting(waiters) => {
+                if let Some((_, head_state)) = waiters.pop(cs) {
+                    if waiters.is_empty(cs) {
+                        *state = ChannelState::Idle;
+                    }
+                    if let ThreadState::ChannelRxBlocked(ptr) = head_state {
+                        // copy over `something`
+                        unsafe { (ptr as *mut T).write(*something) };
                     } else {
-                        unreachable!("unexpected empty thread list");
+                        unreachable!("unexpected thread state");
                     }
-                }
-                ChannelState::SendersWaiting(waiters) => {
-                    waiters.put_current(
-                        cs,
-                        crate::ThreadState::ChannelTxBlocked(core::ptr::from_ref(self) as usize),
-                    );
+                } else {
+                    unreachable!("unexpected empty thread list");
                 }
             }
-        });
+            ChannelState::SendersWaiting(waiters) => {
+                waiters.put_current(cs, blocked);
+            }
+        }
+    }
+
+    /// Send on the channel, with deadline (blocking).
+    ///
+    /// Like [`Self::send()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no receiver took the data before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send_until(&self, something: &T, deadline: Instant) -> Result<(), TimeoutError> {
+        if self.try_send(something) {
+            return Ok(());
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        let sent = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.send_cs(cs, something),
+                |cs| {
+                    // The data has been received if the thread is not waiting anymore.
+                    !self.remove_current(cs)
+                },
+            )
+        };
+        if sent { Ok(()) } else { Err(TimeoutError) }
+    }
+
+    /// Send on the channel, with timeout (blocking).
+    ///
+    /// See [`Self::send_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no receiver took the data within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send_timeout(&self, something: &T, timeout: Duration) -> Result<(), TimeoutError> {
+        self.send_until(something, Instant::now().saturating_add(timeout))
     }
 
     /// Try to send on the channel (non-blocking).
@@ -125,42 +167,116 @@ impl<T: Copy + Send> Channel<T> {
     pub fn recv(&self) -> T {
         let mut res: MaybeUninit<T> = MaybeUninit::uninit();
 
-        with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            let ptr = res.as_mut_ptr();
-            match state {
-                ChannelState::Idle => {
-                    let mut waiters = ThreadList::new();
-                    waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
-                    *state = ChannelState::ReceiversWaiting(waiters);
-                }
-                ChannelState::ReceiversWaiting(waiters) => {
-                    waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
-                    // sender will copy message
-                }
-                ChannelState::SendersWaiting(waiters) => {
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelTxBlocked(other_ptr) = head_state {
-                            // copy over `something`
-                            unsafe { ptr.write(*(other_ptr as *const T)) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
+        with(|cs| self.recv_cs(cs, res.as_mut_ptr()));
+
+        // ensure the compiler honors what happened to memory while the thread
+        // was scheduled away.
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+
+        unsafe { res.assume_init() }
+    }
+
+    fn recv_cs(&self, cs: CriticalSection<'_>, ptr: *mut T) {
+        let state = unsafe { &mut *self.state.get() };
+        match state {
+            ChannelState::Idle => {
+                let mut waiters = ThreadList::new();
+                waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
+                *state = ChannelState::ReceiversWaiting(waiters);
+            }
+            ChannelState::ReceiversWaiting(waiters) => {
+                waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
+                // sender will copy message
+            }
+            ChannelState::SendersWaiting(waiters) => {
+                if let Some((_, head_state)) = waiters.pop(cs) {
+                    if waiters.is_empty(cs) {
+                        *state = ChannelState::Idle;
+                    }
+                    if let ThreadState::ChannelTxBlocked(other_ptr) = head_state {
+                        // copy over `something`
+                        unsafe { ptr.write(*(other_ptr as *const T)) };
                     } else {
-                        unreachable!("unexpected empty thread list");
+                        unreachable!("unexpected thread state");
                     }
+                } else {
+                    unreachable!("unexpected empty thread list");
                 }
             }
-        });
+        }
+    }
+
+    /// Receive on the channel, with deadline (blocking).
+    ///
+    /// Like [`Self::recv()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no sender provided data before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv_until(&self, deadline: Instant) -> Result<T, TimeoutError> {
+        if let Some(something) = self.try_recv() {
+            return Ok(something);
+        }
+        let mut res: MaybeUninit<T> = MaybeUninit::uninit();
+        let ptr = res.as_mut_ptr();
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        let received = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.recv_cs(cs, ptr),
+                |cs| {
+                    // The data has been copied if the thread is not waiting anymore.
+                    !self.remove_current(cs)
+                },
+            )
+        };
 
         // ensure the compiler honors what happened to memory while the thread
         // was scheduled away.
         core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
 
-        unsafe { res.assume_init() }
+        if received {
+            Ok(unsafe { res.assume_init() })
+        } else {
+            Err(TimeoutError)
+        }
+    }
+
+    /// Receive on the channel, with timeout (blocking).
+    ///
+    /// See [`Self::recv_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no sender provided data within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TimeoutError> {
+        self.recv_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Removes the current thread from the threads waiting on the channel.
+    ///
+    /// Returns true if the thread was waiting.
+    fn remove_current(&self, cs: CriticalSection<'_>) -> bool {
+        let state = unsafe { &mut *self.state.get() };
+        let (ChannelState::SendersWaiting(waiters) | ChannelState::ReceiversWaiting(waiters)) =
+            state
+        else {
+            return false;
+        };
+        let removed = waiters.remove_current(cs);
+        if waiters.is_empty(cs) {
+            *state = ChannelState::Idle;
+        }
+        removed
     }
 
     /// Try to send on the channel (non-blocking).
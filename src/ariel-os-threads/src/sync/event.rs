@@ -7,17 +7,25 @@
     reason = "should be addressed eventually"
 )]
 
-use core::cell::UnsafeCell;
+use core::{cell::UnsafeCell, future::poll_fn, task::Poll};
 
-use crate::{ThreadState, threadlist::ThreadList};
+use critical_section::CriticalSection;
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{Duration, Instant};
+
+use crate::{ThreadState, TimeoutError, sync::ASYNC_WAITERS, threadlist::ThreadList};
 
 /// An [`Event`], allowing to notify multiple threads that some event has happened.
 ///
 /// An [`Event`] manages an internal flag that can be set to true with the [`Self::set()`] method and reset
 /// to false with the [`Self::clear()`] method. The [`Self::wait()`] method blocks until the flag is set to true. The
 /// flag is set to false initially.
+///
+/// Async tasks can wait for the same [`Event`] using [`Self::wait_async()`].
 pub struct Event {
     state: UnsafeCell<LockState>,
+    /// Tasks waiting for the event.
+    wakers: UnsafeCell<MultiWakerRegistration<ASYNC_WAITERS>>,
 }
 
 unsafe impl Sync for Event {}
@@ -40,6 +48,7 @@ impl Event {
     pub const fn new() -> Self {
         Self {
             state: UnsafeCell::new(LockState::Locked(ThreadList::new())),
+            wakers: UnsafeCell::new(MultiWakerRegistration::new()),
         }
     }
 
@@ -48,6 +57,7 @@ impl Event {
     pub const fn new_set() -> Self {
         Self {
             state: UnsafeCell::new(LockState::Unlocked),
+            wakers: UnsafeCell::new(MultiWakerRegistration::new()),
         }
     }
 
@@ -69,15 +79,87 @@ impl Event {
     ///
     /// Panics if this is called outside of a thread context.
     pub fn wait(&self) {
-        critical_section::with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                LockState::Unlocked => {}
-                LockState::Locked(waiters) => {
-                    waiters.put_current(cs, ThreadState::LockBlocked);
-                }
+        critical_section::with(|cs| self.wait_cs(cs));
+    }
+
+    fn wait_cs(&self, cs: CriticalSection<'_>) {
+        let state = unsafe { &mut *self.state.get() };
+        match state {
+            LockState::Unlocked => {}
+            LockState::Locked(waiters) => {
+                waiters.put_current(cs, ThreadState::LockBlocked);
             }
-        });
+        }
+    }
+
+    /// Waits for this [`Event`] to be set, with deadline (blocking).
+    ///
+    /// Like [`Self::wait()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the event was not set before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_until(&self, deadline: Instant) -> Result<(), TimeoutError> {
+        if self.is_set() {
+            return Ok(());
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        let set = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.wait_cs(cs),
+                |cs| {
+                    let state = &mut *self.state.get();
+                    match state {
+                        // The event has been set if the thread is not waiting anymore.
+                        LockState::Locked(waiters) => !waiters.remove_current(cs),
+                        LockState::Unlocked => true,
+                    }
+                },
+            )
+        };
+        if set { Ok(()) } else { Err(TimeoutError) }
+    }
+
+    /// Waits for this [`Event`] to be set, with timeout (blocking).
+    ///
+    /// See [`Self::wait_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the event was not set within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), TimeoutError> {
+        self.wait_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Waits for this [`Event`] to be set from an async task.
+    ///
+    /// If the event was set, this function returns directly.
+    /// If the event was unset, the task waits until the event gets set elsewhere, by either a
+    /// thread or a task.
+    pub async fn wait_async(&self) {
+        poll_fn(|cx| {
+            critical_section::with(|_| {
+                let state = unsafe { &*self.state.get() };
+                match state {
+                    LockState::Unlocked => Poll::Ready(()),
+                    LockState::Locked(_) => {
+                        unsafe { &mut *self.wakers.get() }.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await;
     }
 
     /// Clears the event (non-blocking).
@@ -100,7 +182,7 @@ impl Event {
     /// Sets the event.
     ///
     /// If the event was unset, and there were waiters, all waiters will be
-    /// woken up.
+    /// woken up, including async tasks.
     /// If the event was already set, the function just returns.
     pub fn set(&self) {
         critical_section::with(|cs| {
@@ -112,6 +194,7 @@ impl Event {
                     // TODO (opt): A to-be-written `pop_all()` might save cycles.
                     while waiters.pop(cs).is_some() {}
                     *state = LockState::Unlocked;
+                    unsafe { &mut *self.wakers.get() }.wake();
                 }
             }
         });
@@ -0,0 +1,331 @@
+//! This module provides an event group, i.e., a set of event bits that can be waited for.
+
+#![expect(unsafe_code)]
+#![deny(missing_docs)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::cell::{Cell, UnsafeCell};
+
+use critical_section::CriticalSection;
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    ThreadState, TimeoutError,
+    thread_flags::{ThreadFlags, WaitMode},
+    threadlist::ThreadList,
+};
+
+/// Bitmask of the bits of an [`EventGroup`].
+pub type EventBits = ThreadFlags;
+
+/// A set of event bits, which any number of threads can wait for.
+///
+/// Unlike [`thread_flags`](crate::thread_flags), which belong to a single thread, the bits of an
+/// [`EventGroup`] are shared: they are set and cleared by any thread, and all threads whose wait
+/// condition is met are woken up when bits are set.
+/// This allows threads to wait for system state, e.g., for the network to be up, without knowing
+/// about each other.
+///
+/// Wait conditions are evaluated when bits are set: every thread whose condition is met at that
+/// point is woken up and gets the bits it waited for, even if these are cleared again before it
+/// runs.
+/// Waiting threads can optionally clear the bits they waited for when they return; these bits are
+/// cleared once all woken up threads got them.
+///
+/// Example:
+///
+/// ```ignore
+/// const NETWORK_UP: EventBits = 0b01;
+/// const TIME_SYNCED: EventBits = 0b10;
+///
+/// static SYSTEM_STATE: EventGroup = EventGroup::new();
+///
+/// SYSTEM_STATE.wait_all(NETWORK_UP | TIME_SYNCED, false);
+/// ```
+pub struct EventGroup {
+    state: UnsafeCell<EventGroupState>,
+}
+
+unsafe impl Sync for EventGroup {}
+
+/// Wait condition of a thread blocked on an [`EventGroup`], stored on the stack of that thread.
+struct Waiter {
+    mode: WaitMode,
+    clear_on_exit: bool,
+    /// The bits handed over to the thread, once its condition is met.
+    bits: Cell<Option<EventBits>>,
+}
+
+impl Waiter {
+    fn new(mode: WaitMode, clear_on_exit: bool) -> Self {
+        Self {
+            mode,
+            clear_on_exit,
+            bits: Cell::new(None),
+        }
+    }
+
+    fn thread_state(&self) -> ThreadState {
+        ThreadState::EventGroupBlocked(core::ptr::from_ref(self) as usize)
+    }
+}
+
+struct EventGroupState {
+    /// Bits that are currently set.
+    bits: EventBits,
+    /// Threads waiting for bits to be set.
+    waiters: ThreadList,
+}
+
+impl EventGroup {
+    /// Creates a new [`EventGroup`] with all bits cleared.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(EventGroupState {
+                bits: 0,
+                waiters: ThreadList::new(),
+            }),
+        }
+    }
+
+    /// Returns the bits that are currently set.
+    pub fn get(&self) -> EventBits {
+        critical_section::with(|_| unsafe { &*self.state.get() }.bits)
+    }
+
+    /// Sets the bits in `mask`.
+    ///
+    /// All threads whose wait condition is met are woken up, and get the bits they waited for.
+    /// The bits waited for with `clear_on_exit` are cleared afterwards.
+    ///
+    /// This can be used from interrupt handlers.
+    pub fn set(&self, mask: EventBits) {
+        critical_section::with(|cs| {
+            let state = unsafe { &mut *self.state.get() };
+            state.bits |= mask;
+            let bits = state.bits;
+            let mut to_clear = 0;
+            state.waiters.pop_matching(cs, |thread_state| {
+                let ThreadState::EventGroupBlocked(ptr) = thread_state else {
+                    unreachable!("unexpected thread state");
+                };
+                // The waiter is alive as long as its thread is blocked.
+                let waiter = unsafe { &*(ptr as *const Waiter) };
+                if !is_met(bits, waiter.mode) {
+                    return false;
+                }
+                let (WaitMode::Any(mask) | WaitMode::All(mask)) = waiter.mode;
+                waiter.bits.set(Some(bits & mask));
+                if waiter.clear_on_exit {
+                    to_clear |= mask;
+                }
+                true
+            });
+            state.bits &= !to_clear;
+        });
+    }
+
+    /// Clears the bits in `mask`.
+    ///
+    /// Returns those bits in `mask` that were set.
+    ///
+    /// This can be used from interrupt handlers.
+    pub fn clear(&self, mask: EventBits) -> EventBits {
+        critical_section::with(|_| {
+            let state = unsafe { &mut *self.state.get() };
+            let res = state.bits & mask;
+            state.bits &= !mask;
+            res
+        })
+    }
+
+    /// Waits until any of the bits in `mask` is set (blocking).
+    ///
+    /// Returns those bits in `mask` that were set.
+    /// If `clear_on_exit` is true, the bits in `mask` are cleared before returning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_any(&self, mask: EventBits, clear_on_exit: bool) -> EventBits {
+        self.wait(WaitMode::Any(mask), clear_on_exit)
+    }
+
+    /// Waits until any of the bits in `mask` is set, with deadline (blocking).
+    ///
+    /// Like [`Self::wait_any()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if none of the bits was set before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_any_until(
+        &self,
+        mask: EventBits,
+        clear_on_exit: bool,
+        deadline: Instant,
+    ) -> Result<EventBits, TimeoutError> {
+        self.wait_until(WaitMode::Any(mask), clear_on_exit, deadline)
+    }
+
+    /// Waits until any of the bits in `mask` is set, with timeout (blocking).
+    ///
+    /// See [`Self::wait_any_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if none of the bits was set within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_any_timeout(
+        &self,
+        mask: EventBits,
+        clear_on_exit: bool,
+        timeout: Duration,
+    ) -> Result<EventBits, TimeoutError> {
+        self.wait_any_until(mask, clear_on_exit, Instant::now().saturating_add(timeout))
+    }
+
+    /// Waits until all of the bits in `mask` are set (blocking).
+    ///
+    /// Returns `mask`.
+    /// If `clear_on_exit` is true, the bits in `mask` are cleared before returning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_all(&self, mask: EventBits, clear_on_exit: bool) -> EventBits {
+        self.wait(WaitMode::All(mask), clear_on_exit)
+    }
+
+    /// Waits until all of the bits in `mask` are set, with deadline (blocking).
+    ///
+    /// Like [`Self::wait_all()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the bits were not all set before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_all_until(
+        &self,
+        mask: EventBits,
+        clear_on_exit: bool,
+        deadline: Instant,
+    ) -> Result<EventBits, TimeoutError> {
+        self.wait_until(WaitMode::All(mask), clear_on_exit, deadline)
+    }
+
+    /// Waits until all of the bits in `mask` are set, with timeout (blocking).
+    ///
+    /// See [`Self::wait_all_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the bits were not all set within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_all_timeout(
+        &self,
+        mask: EventBits,
+        clear_on_exit: bool,
+        timeout: Duration,
+    ) -> Result<EventBits, TimeoutError> {
+        self.wait_all_until(mask, clear_on_exit, Instant::now().saturating_add(timeout))
+    }
+
+    fn wait(&self, mode: WaitMode, clear_on_exit: bool) -> EventBits {
+        let waiter = Waiter::new(mode, clear_on_exit);
+        critical_section::with(|cs| {
+            waiter.bits.set(self.take_cs(cs, mode, clear_on_exit));
+            if waiter.bits.get().is_none() {
+                let state = unsafe { &mut *self.state.get() };
+                state.waiters.put_current(cs, waiter.thread_state());
+            }
+        });
+        // `set()` hands over the bits when waking up the thread.
+        waiter
+            .bits
+            .get()
+            .expect("thread should only be woken up once its condition is met")
+    }
+
+    fn wait_until(
+        &self,
+        mode: WaitMode,
+        clear_on_exit: bool,
+        deadline: Instant,
+    ) -> Result<EventBits, TimeoutError> {
+        let waiter = Waiter::new(mode, clear_on_exit);
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        unsafe {
+            crate::timeout::with_deadline_check(
+                deadline,
+                |cs| {
+                    // The bits have been handed over by `set()` if the thread got woken up.
+                    if waiter.bits.get().is_none() {
+                        waiter.bits.set(self.take_cs(cs, mode, clear_on_exit));
+                    }
+                    waiter.bits.get().is_some()
+                },
+                |cs| {
+                    let state = &mut *self.state.get();
+                    state.waiters.put_current(cs, waiter.thread_state());
+                },
+                |cs| {
+                    let state = &mut *self.state.get();
+                    state.waiters.remove_current(cs);
+                },
+            )
+        };
+        waiter.bits.get().ok_or(TimeoutError)
+    }
+
+    /// Returns the bits `mode` waits for if its condition is met, clearing them if
+    /// `clear_on_exit` is true.
+    fn take_cs(
+        &self,
+        _cs: CriticalSection<'_>,
+        mode: WaitMode,
+        clear_on_exit: bool,
+    ) -> Option<EventBits> {
+        let state = unsafe { &mut *self.state.get() };
+        if !is_met(state.bits, mode) {
+            return None;
+        }
+        let (WaitMode::Any(mask) | WaitMode::All(mask)) = mode;
+        let res = state.bits & mask;
+        if clear_on_exit {
+            state.bits &= !mask;
+        }
+        Some(res)
+    }
+}
+
+impl Default for EventGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether `bits` meet the wait condition `mode`.
+fn is_met(bits: EventBits, mode: WaitMode) -> bool {
+    match mode {
+        WaitMode::Any(mask) => bits & mask != 0,
+        WaitMode::All(mask) => bits & mask == mask,
+    }
+}
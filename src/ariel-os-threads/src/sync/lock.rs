@@ -8,7 +8,10 @@
 
 use core::cell::UnsafeCell;
 
-use crate::{ThreadState, threadlist::ThreadList};
+use critical_section::CriticalSection;
+use embassy_time::{Duration, Instant};
+
+use crate::{ThreadState, TimeoutError, threadlist::ThreadList};
 
 /// A basic locking object.
 ///
@@ -62,15 +65,66 @@ impl Lock {
     ///
     /// Panics if this is called outside of a thread context.
     pub fn acquire(&self) {
-        critical_section::with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                LockState::Unlocked => *state = LockState::Locked(ThreadList::new()),
-                LockState::Locked(waiters) => {
-                    waiters.put_current(cs, ThreadState::LockBlocked);
-                }
+        critical_section::with(|cs| self.acquire_cs(cs));
+    }
+
+    fn acquire_cs(&self, cs: CriticalSection<'_>) {
+        let state = unsafe { &mut *self.state.get() };
+        match state {
+            LockState::Unlocked => *state = LockState::Locked(ThreadList::new()),
+            LockState::Locked(waiters) => {
+                waiters.put_current(cs, ThreadState::LockBlocked);
             }
-        });
+        }
+    }
+
+    /// Get this lock, with deadline (blocking).
+    ///
+    /// Like [`Self::acquire()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the lock could not be acquired before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_until(&self, deadline: Instant) -> Result<(), TimeoutError> {
+        if self.try_acquire() {
+            return Ok(());
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        let acquired = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.acquire_cs(cs),
+                |cs| {
+                    let state = &mut *self.state.get();
+                    match state {
+                        // The lock has been handed over if the thread is not waiting anymore.
+                        LockState::Locked(waiters) => !waiters.remove_current(cs),
+                        LockState::Unlocked => false,
+                    }
+                },
+            )
+        };
+        if acquired { Ok(()) } else { Err(TimeoutError) }
+    }
+
+    /// Get this lock, with timeout (blocking).
+    ///
+    /// See [`Self::acquire_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the lock could not be acquired within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Result<(), TimeoutError> {
+        self.acquire_until(Instant::now().saturating_add(timeout))
     }
 
     /// Get the lock (non-blocking).
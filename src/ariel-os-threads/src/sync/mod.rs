@@ -1,12 +1,26 @@
 //! Synchronization primitives.
+//!
+//! [`Queue`] and [`Event`] can be used from both threads and async tasks.
 mod channel;
 mod event;
+mod event_group;
 mod lock;
 mod mutex;
+mod queue;
+mod rwlock;
+mod semaphore;
 mod wait_queue;
 
 pub use channel::Channel;
 pub use event::Event;
+pub use event_group::{EventBits, EventGroup};
 pub use lock::Lock;
 pub use mutex::{Mutex, MutexGuard};
+pub use queue::{Queue, SendTimeoutError};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
 pub use wait_queue::WaitQueue;
+
+/// Number of async tasks that can wait on a primitive before they get woken up spuriously to
+/// make room, in which case they wait again.
+const ASYNC_WAITERS: usize = 4;
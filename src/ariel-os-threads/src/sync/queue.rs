@@ -0,0 +1,412 @@
+//! Bounded queue implementation for sending data between threads and async tasks.
+
+#![expect(unsafe_code)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::cell::{Cell, UnsafeCell};
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::task::Poll;
+
+use critical_section::{CriticalSection, with};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{Duration, Instant};
+
+use crate::sync::ASYNC_WAITERS;
+use crate::threadlist::ThreadList;
+use crate::{ThreadState, TimeoutError};
+
+/// Error returned when a value could not be sent on a [`Queue`] before a deadline.
+///
+/// Holds the value that could not be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SendTimeoutError<T>(pub T);
+
+impl<T> From<SendTimeoutError<T>> for TimeoutError {
+    fn from(_: SendTimeoutError<T>) -> Self {
+        TimeoutError
+    }
+}
+
+/// Bounded multi-producer, multi-consumer queue for sending data between threads.
+///
+/// Unlike the rendezvous [`Channel`](super::Channel), the queue buffers up to `N` values: sending
+/// only blocks while the buffer is full, and receiving only blocks while it is empty.
+/// Values are moved through the queue, so `T` does not need to be [`Copy`].
+///
+/// The queue can also be used from async tasks through [`Self::send_async()`] and
+/// [`Self::recv_async()`], both with threads and with other tasks on the other end.
+///
+/// Example:
+///
+/// ```ignore
+/// static QUEUE: Queue<Message, 4> = Queue::new();
+/// ```
+pub struct Queue<T, const N: usize> {
+    state: UnsafeCell<QueueState<T, N>>,
+}
+
+struct QueueState<T, const N: usize> {
+    /// Ring buffer of the values in flight.
+    buffer: [MaybeUninit<T>; N],
+    /// Index of the oldest value in `buffer`.
+    head: usize,
+    /// Number of values in `buffer`.
+    len: usize,
+    /// Threads waiting for a value.
+    receivers: ThreadList,
+    /// Threads waiting for free space.
+    senders: ThreadList,
+    /// Tasks waiting for a value.
+    rx_wakers: MultiWakerRegistration<ASYNC_WAITERS>,
+    /// Tasks waiting for free space.
+    tx_wakers: MultiWakerRegistration<ASYNC_WAITERS>,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T: Send, const N: usize> Queue<T, N> {
+    /// Returns a new [`Queue`].
+    ///
+    /// # Panics
+    ///
+    /// Fails to compile if `N` is zero.
+    #[must_use]
+    pub const fn new() -> Self {
+        const { assert!(N > 0, "queue capacity must not be zero") };
+        Queue {
+            state: UnsafeCell::new(QueueState {
+                buffer: [const { MaybeUninit::uninit() }; N],
+                head: 0,
+                len: 0,
+                receivers: ThreadList::new(),
+                senders: ThreadList::new(),
+                rx_wakers: MultiWakerRegistration::new(),
+                tx_wakers: MultiWakerRegistration::new(),
+            }),
+        }
+    }
+
+    /// Send on the queue (blocking).
+    ///
+    /// If the queue is full, the current thread is suspended until a receiver has made room.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send(&self, mut value: T) {
+        loop {
+            match with(|cs| {
+                self.try_send_cs(cs, value).inspect_err(|_| {
+                    let state = unsafe { &mut *self.state.get() };
+                    state.senders.put_current(cs, self.blocked_tx());
+                })
+            }) {
+                Ok(()) => return,
+                // The value is handed back; retry once woken up.
+                Err(returned) => value = returned,
+            }
+        }
+    }
+
+    /// Send on the queue, with deadline (blocking).
+    ///
+    /// If the queue is full, the current thread is suspended until a receiver has made room,
+    /// or until `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SendTimeoutError`] with the value if `deadline` has been reached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send_until(&self, value: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        let value = Cell::new(Some(value));
+
+        let sent = self.wait_until(
+            deadline,
+            |cs| {
+                let Some(v) = value.take() else {
+                    return true;
+                };
+                match self.try_send_cs(cs, v) {
+                    Ok(()) => true,
+                    Err(v) => {
+                        value.set(Some(v));
+                        false
+                    }
+                }
+            },
+            |state| &mut state.senders,
+            self.blocked_tx(),
+        );
+
+        match value.take() {
+            Some(v) if !sent => Err(SendTimeoutError(v)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send on the queue, with timeout (blocking).
+    ///
+    /// See [`Self::send_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SendTimeoutError`] with the value if `timeout` has elapsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(value, Instant::now().saturating_add(timeout))
+    }
+
+    /// Try to send on the queue (non-blocking).
+    ///
+    /// This can be used from interrupt handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value back if the queue is full.
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        with(|cs| self.try_send_cs(cs, value))
+    }
+
+    fn try_send_cs(&self, cs: CriticalSection<'_>, value: T) -> Result<(), T> {
+        let state = unsafe { &mut *self.state.get() };
+        if state.len == N {
+            return Err(value);
+        }
+
+        let tail = (state.head + state.len) % N;
+        // `tail < N` always holds.
+        if let Some(slot) = state.buffer.get_mut(tail) {
+            slot.write(value);
+        }
+        state.len += 1;
+
+        state.receivers.pop(cs);
+        state.rx_wakers.wake();
+        Ok(())
+    }
+
+    /// Send on the queue from an async task.
+    ///
+    /// If the queue is full, the task waits until a receiver has made room.
+    pub async fn send_async(&self, value: T) {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            with(|cs| {
+                let Some(v) = value.take() else {
+                    return Poll::Ready(());
+                };
+                match self.try_send_cs(cs, v) {
+                    Ok(()) => Poll::Ready(()),
+                    Err(v) => {
+                        value = Some(v);
+                        let state = unsafe { &mut *self.state.get() };
+                        state.tx_wakers.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await;
+    }
+
+    /// Receive on the queue (blocking).
+    ///
+    /// If the queue is empty, the current thread is suspended until a sender is ready.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv(&self) -> T {
+        loop {
+            if let Some(value) = with(|cs| {
+                let value = self.try_recv_cs(cs);
+                if value.is_none() {
+                    let state = unsafe { &mut *self.state.get() };
+                    state.receivers.put_current(cs, self.blocked_rx());
+                }
+                value
+            }) {
+                return value;
+            }
+        }
+    }
+
+    /// Receive on the queue, with deadline (blocking).
+    ///
+    /// If the queue is empty, the current thread is suspended until a sender is ready, or
+    /// until `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if `deadline` has been reached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv_until(&self, deadline: Instant) -> Result<T, TimeoutError> {
+        let value = Cell::new(None);
+
+        self.wait_until(
+            deadline,
+            |cs| {
+                // `value` is only set once.
+                if let Some(v) = self.try_recv_cs(cs) {
+                    value.set(Some(v));
+                    true
+                } else {
+                    false
+                }
+            },
+            |state| &mut state.receivers,
+            self.blocked_rx(),
+        );
+
+        value.take().ok_or(TimeoutError)
+    }
+
+    /// Receive on the queue, with timeout (blocking).
+    ///
+    /// See [`Self::recv_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if `timeout` has elapsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TimeoutError> {
+        self.recv_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Try to receive on the queue (non-blocking).
+    ///
+    /// Returns `Some` data if the queue was not empty, `None` otherwise.
+    ///
+    /// This can be used from interrupt handlers.
+    pub fn try_recv(&self) -> Option<T> {
+        with(|cs| self.try_recv_cs(cs))
+    }
+
+    fn try_recv_cs(&self, cs: CriticalSection<'_>) -> Option<T> {
+        let state = unsafe { &mut *self.state.get() };
+        if state.len == 0 {
+            return None;
+        }
+
+        let head = state.head;
+        // Safety: values between `head` and `head + len` are initialized.
+        let value = unsafe { state.buffer.get(head)?.assume_init_read() };
+        state.head = (head + 1) % N;
+        state.len -= 1;
+
+        state.senders.pop(cs);
+        state.tx_wakers.wake();
+        Some(value)
+    }
+
+    /// Receive on the queue from an async task.
+    ///
+    /// If the queue is empty, the task waits until a sender is ready.
+    pub async fn recv_async(&self) -> T {
+        poll_fn(|cx| {
+            with(|cs| {
+                if let Some(value) = self.try_recv_cs(cs) {
+                    return Poll::Ready(value);
+                }
+                let state = unsafe { &mut *self.state.get() };
+                state.rx_wakers.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    /// Returns the number of values currently buffered.
+    pub fn len(&self) -> usize {
+        with(|_| unsafe { &*self.state.get() }.len)
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the capacity of the queue.
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Blocks on one of the [`ThreadList`]s until `check()` succeeds or `deadline` is reached.
+    ///
+    /// Returns the result of the last `check()`.
+    fn wait_until(
+        &self,
+        deadline: Instant,
+        check: impl Fn(CriticalSection<'_>) -> bool,
+        waiters: impl Fn(&mut QueueState<T, N>) -> &mut ThreadList,
+        blocked_state: ThreadState,
+    ) -> bool {
+        loop {
+            // Safety:
+            // `on_timeout` takes care of removing the thread from the threadlist.
+            let done = unsafe {
+                crate::timeout::with_deadline_check(
+                    deadline,
+                    &check,
+                    |cs| {
+                        let state = &mut *self.state.get();
+                        waiters(state).put_current(cs, blocked_state);
+                    },
+                    |cs| {
+                        let state = &mut *self.state.get();
+                        waiters(state).remove_current(cs);
+                    },
+                )
+            };
+            // Another thread may have been faster after we got woken up.
+            if done || Instant::now() >= deadline {
+                return done;
+            }
+        }
+    }
+
+    // Blocked threads are reported like those of a `Channel`.
+    fn blocked_rx(&self) -> ThreadState {
+        ThreadState::ChannelRxBlocked(core::ptr::from_ref(self) as usize)
+    }
+
+    fn blocked_tx(&self) -> ThreadState {
+        ThreadState::ChannelTxBlocked(core::ptr::from_ref(self) as usize)
+    }
+}
+
+impl<T: Send, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        for i in 0..state.len {
+            if let Some(slot) = state.buffer.get_mut((state.head + i) % N) {
+                // Safety: values between `head` and `head + len` are initialized.
+                unsafe { slot.assume_init_drop() };
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! This module provides a reader-writer lock.
+
+#![expect(unsafe_code)]
+#![deny(missing_docs)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use ariel_os_runqueue::{RunqueueId, ThreadId};
+use critical_section::CriticalSection;
+use embassy_time::{Duration, Instant};
+
+use crate::{SCHEDULER, TimeoutError, thread::ThreadState, threadlist::ThreadList};
+
+/// A reader-writer lock with writer preference and priority inheritance.
+///
+/// Any number of threads can hold read access at the same time, while write access is
+/// exclusive.
+/// Once a writer is waiting, new readers are blocked until it got write access, so that writers
+/// are not starved by a continuous stream of readers.
+///
+/// Like with [`Mutex`](super::Mutex), the thread holding write access inherits the priority of
+/// the highest priority waiting thread, and its priority is reset when it releases write
+/// access.
+///
+/// Priority inheritance is **not** implemented for threads holding read access: the lock only
+/// counts them, and does not know which threads they are. A high priority thread waiting for
+/// write access can therefore be delayed for an unbounded time by lower priority readers being
+/// preempted by medium priority threads. Use a [`Mutex`](super::Mutex) where this matters.
+///
+/// Example:
+///
+/// ```ignore
+/// static ROUTES: RwLock<RouteTable> = RwLock::new(RouteTable::new());
+///
+/// let next_hop = ROUTES.read().lookup(destination);
+/// ROUTES.write().insert(destination, next_hop);
+/// ```
+pub struct RwLock<T> {
+    state: UnsafeCell<LockState>,
+    inner: UnsafeCell<T>,
+}
+
+/// State of a [`RwLock`].
+struct LockState {
+    /// Number of threads holding read access.
+    readers: usize,
+    /// The thread holding write access, and its original priority (without priority
+    /// inheritance).
+    writer: Option<(ThreadId, RunqueueId)>,
+    /// Threads waiting for read access.
+    waiting_readers: ThreadList,
+    /// Threads waiting for write access.
+    waiting_writers: ThreadList,
+}
+
+impl LockState {
+    /// Returns whether read access can be granted.
+    fn can_read(&self, cs: CriticalSection<'_>) -> bool {
+        self.writer.is_none() && self.waiting_writers.head_prio(cs).is_none()
+    }
+
+    /// Returns whether write access can be granted.
+    fn can_write(&self) -> bool {
+        self.writer.is_none() && self.readers == 0
+    }
+
+    /// Grants write access to the current thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    fn set_writer_current(&mut self, cs: CriticalSection<'_>) {
+        self.writer = Some(SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let current = scheduler
+                .current()
+                .expect("Function should be called inside a thread context.");
+            (current.tid, current.prio)
+        }));
+    }
+
+    /// Hands access over to waiting threads, if possible.
+    ///
+    /// Writers are preferred: the highest priority waiting writer gets write access if no thread
+    /// holds access anymore. Otherwise, if no writer is waiting, all waiting readers get read
+    /// access.
+    fn hand_over(&mut self, cs: CriticalSection<'_>) {
+        if self.can_write()
+            && let Some((tid, _)) = self.waiting_writers.pop(cs)
+        {
+            let prio = SCHEDULER.with_cs(cs, |scheduler| scheduler.get_unchecked(tid).prio);
+            self.writer = Some((tid, prio));
+            self.inherit_priority(cs);
+            return;
+        }
+        if self.can_read(cs) {
+            while self.waiting_readers.pop(cs).is_some() {
+                self.readers += 1;
+            }
+        }
+    }
+
+    /// Sets the priority of the writer, if any, to the highest priority among itself and all
+    /// waiting threads.
+    fn inherit_priority(&self, cs: CriticalSection<'_>) {
+        let Some((owner_id, owner_prio)) = self.writer else {
+            return;
+        };
+        let prio = [
+            self.waiting_readers.head_prio(cs),
+            self.waiting_writers.head_prio(cs),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(owner_prio, RunqueueId::max);
+        SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            scheduler.set_priority(owner_id, prio);
+        });
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new **unlocked** [`RwLock`].
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: UnsafeCell::new(LockState {
+                readers: 0,
+                writer: None,
+                waiting_readers: ThreadList::new(),
+                waiting_writers: ThreadList::new(),
+            }),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns whether a thread holds write access.
+    pub fn is_write_locked(&self) -> bool {
+        critical_section::with(|_| unsafe { &*self.state.get() }.writer.is_some())
+    }
+
+    /// Acquires read access, blocking the current thread until it is able to do so.
+    ///
+    /// This blocks while a thread holds write access or waits for it.
+    /// If a lower priority thread holds write access, it inherits the priority of the current
+    /// thread until it releases write access.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        critical_section::with(|cs| self.read_cs(cs));
+        // Read access was either granted directly, or handed over to this thread by the thread
+        // releasing access once the current thread runs again.
+        RwLockReadGuard::new(self)
+    }
+
+    fn read_cs(&self, cs: CriticalSection<'_>) {
+        // SAFETY: access to the state only happens in critical sections, so it's always unique.
+        let state = unsafe { &mut *self.state.get() };
+        if state.can_read(cs) {
+            state.readers += 1;
+        } else {
+            // Context switch happens here as soon as we leave the critical section.
+            state
+                .waiting_readers
+                .put_current(cs, ThreadState::LockBlocked);
+            state.inherit_priority(cs);
+        }
+    }
+
+    /// Acquires read access, blocking the current thread until it is able to do so or until
+    /// `deadline` is reached.
+    ///
+    /// See [`Self::read()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if read access could not be acquired before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn read_until(&self, deadline: Instant) -> Result<RwLockReadGuard<'_, T>, TimeoutError> {
+        if let Some(guard) = self.try_read() {
+            return Ok(guard);
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the waitlist.
+        let acquired = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.read_cs(cs),
+                |cs| {
+                    // SAFETY: access to the state only happens in critical sections, so it's always unique.
+                    let state = &mut *self.state.get();
+                    if !state.waiting_readers.remove_current(cs) {
+                        // Read access has been handed over to this thread in the meantime.
+                        return true;
+                    }
+                    // Drop the priority this thread may have lent to the writer.
+                    state.inherit_priority(cs);
+                    false
+                },
+            )
+        };
+        if acquired {
+            Ok(RwLockReadGuard::new(self))
+        } else {
+            Err(TimeoutError)
+        }
+    }
+
+    /// Acquires read access, blocking the current thread until it is able to do so or until
+    /// `timeout` has elapsed.
+    ///
+    /// See [`Self::read_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if read access could not be acquired within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn read_timeout(&self, timeout: Duration) -> Result<RwLockReadGuard<'_, T>, TimeoutError> {
+        self.read_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Attempts to acquire read access, in a non-blocking fashion.
+    ///
+    /// Returns `None` if a thread holds write access or waits for it.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.can_read(cs).then(|| {
+                state.readers += 1;
+                RwLockReadGuard::new(self)
+            })
+        })
+    }
+
+    /// Acquires write access, blocking the current thread until it is able to do so.
+    ///
+    /// If a lower priority thread holds write access, it inherits the priority of the current
+    /// thread until it releases write access.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        critical_section::with(|cs| self.write_cs(cs));
+        // Write access was either granted directly, or handed over to this thread by the thread
+        // releasing access once the current thread runs again.
+        RwLockWriteGuard::new(self)
+    }
+
+    fn write_cs(&self, cs: CriticalSection<'_>) {
+        // SAFETY: access to the state only happens in critical sections, so it's always unique.
+        let state = unsafe { &mut *self.state.get() };
+        if state.can_write() {
+            state.set_writer_current(cs);
+        } else {
+            // Context switch happens here as soon as we leave the critical section.
+            state
+                .waiting_writers
+                .put_current(cs, ThreadState::LockBlocked);
+            state.inherit_priority(cs);
+        }
+    }
+
+    /// Acquires write access, blocking the current thread until it is able to do so or until
+    /// `deadline` is reached.
+    ///
+    /// See [`Self::write()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if write access could not be acquired before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn write_until(&self, deadline: Instant) -> Result<RwLockWriteGuard<'_, T>, TimeoutError> {
+        if let Some(guard) = self.try_write() {
+            return Ok(guard);
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the waitlist.
+        let acquired = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.write_cs(cs),
+                |cs| {
+                    // SAFETY: access to the state only happens in critical sections, so it's always unique.
+                    let state = &mut *self.state.get();
+                    if !state.waiting_writers.remove_current(cs) {
+                        // Write access has been handed over to this thread in the meantime.
+                        return true;
+                    }
+                    // Readers blocked by this thread waiting may proceed now.
+                    state.hand_over(cs);
+                    // Drop the priority this thread may have lent to the writer.
+                    state.inherit_priority(cs);
+                    false
+                },
+            )
+        };
+        if acquired {
+            Ok(RwLockWriteGuard::new(self))
+        } else {
+            Err(TimeoutError)
+        }
+    }
+
+    /// Acquires write access, blocking the current thread until it is able to do so or until
+    /// `timeout` has elapsed.
+    ///
+    /// See [`Self::write_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if write access could not be acquired within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RwLockWriteGuard<'_, T>, TimeoutError> {
+        self.write_until(Instant::now().saturating_add(timeout))
+    }
+
+    /// Attempts to acquire write access, in a non-blocking fashion.
+    ///
+    /// Returns `None` if a thread holds read or write access.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.can_write().then(|| {
+                state.set_writer_current(cs);
+                RwLockWriteGuard::new(self)
+            })
+        })
+    }
+
+    /// Releases read access.
+    ///
+    /// If this was the last reader, access is handed over to waiting threads.
+    fn release_read(&self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.readers -= 1;
+            state.hand_over(cs);
+        });
+    }
+
+    /// Releases write access.
+    ///
+    /// The priority of the writer is reset, and access is handed over to waiting threads.
+    fn release_write(&self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if let Some((owner_id, owner_prio)) = state.writer.take() {
+                // Reset original priority of owner.
+                SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    scheduler.set_priority(owner_id, owner_prio);
+                });
+            }
+            state.hand_over(cs);
+        });
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+/// Grants shared access to the [`RwLock`] inner data.
+///
+/// Dropping the [`RwLockReadGuard`] releases read access.
+#[must_use = "if unused the RwLock will immediately be released"]
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self {
+            lock,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: no thread holds write access while a RwLockReadGuard exists.
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release_read();
+    }
+}
+
+unsafe impl<T: Sync> Sync for RwLockReadGuard<'_, T> {}
+
+/// Grants exclusive access to the [`RwLock`] inner data.
+///
+/// Dropping the [`RwLockWriteGuard`] releases write access.
+#[must_use = "if unused the RwLock will immediately be released"]
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self {
+            lock,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: RwLockWriteGuard always has unique access.
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: RwLockWriteGuard always has unique access.
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release_write();
+    }
+}
+
+unsafe impl<T: Sync> Sync for RwLockWriteGuard<'_, T> {}
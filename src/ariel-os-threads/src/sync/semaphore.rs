@@ -0,0 +1,188 @@
+//! This module provides a counting semaphore.
+
+#![expect(unsafe_code)]
+#![deny(missing_docs)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::cell::UnsafeCell;
+
+use critical_section::CriticalSection;
+use embassy_time::{Duration, Instant};
+
+use crate::{ThreadState, TimeoutError, threadlist::ThreadList};
+
+/// A counting semaphore.
+///
+/// A [`Semaphore`] holds a number of permits, which threads acquire and release, e.g., to manage
+/// a pool of resources.
+/// Threads that cannot acquire the permits they ask for are blocked. Once permits are released,
+/// they are handed over to the waiting threads in priority order: every waiting thread whose
+/// request can be satisfied with the remaining permits gets them and is woken up.
+///
+/// Permits are not owned by the thread that acquired them, and can be released by any thread.
+/// Consequently, there is no priority inheritance.
+///
+/// Example:
+///
+/// ```ignore
+/// static BUFFERS: Semaphore = Semaphore::new(4);
+///
+/// BUFFERS.acquire(1);
+/// // Use one of the buffers.
+/// BUFFERS.release(1);
+/// ```
+pub struct Semaphore {
+    state: UnsafeCell<SemaphoreState>,
+}
+
+unsafe impl Sync for Semaphore {}
+
+struct SemaphoreState {
+    /// Number of available permits.
+    permits: usize,
+    /// Threads waiting for permits.
+    waiters: ThreadList,
+}
+
+impl Semaphore {
+    /// Creates a new [`Semaphore`] with `permits` available permits.
+    #[must_use]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: UnsafeCell::new(SemaphoreState {
+                permits,
+                waiters: ThreadList::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of available permits.
+    pub fn available(&self) -> usize {
+        critical_section::with(|_| unsafe { &*self.state.get() }.permits)
+    }
+
+    /// Acquires `n` permits (blocking).
+    ///
+    /// If fewer than `n` permits are available, the current thread is suspended until enough
+    /// permits have been released.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire(&self, n: usize) {
+        critical_section::with(|cs| self.acquire_cs(cs, n));
+        // The permits were either acquired directly, or handed over to this thread by the thread
+        // releasing them once the current thread runs again.
+    }
+
+    fn acquire_cs(&self, cs: CriticalSection<'_>, n: usize) {
+        if !self.try_acquire_cs(cs, n) {
+            let state = unsafe { &mut *self.state.get() };
+            // Context switch happens here as soon as we leave the critical section.
+            state
+                .waiters
+                .put_current(cs, ThreadState::SemaphoreBlocked(n));
+        }
+    }
+
+    /// Acquires `n` permits, with deadline (blocking).
+    ///
+    /// Like [`Self::acquire()`], but gives up once `deadline` is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the permits could not be acquired before `deadline`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_until(&self, n: usize, deadline: Instant) -> Result<(), TimeoutError> {
+        if self.try_acquire(n) {
+            return Ok(());
+        }
+        // Safety:
+        // `on_timeout` takes care of removing the thread from the threadlist.
+        let acquired = unsafe {
+            crate::timeout::with_deadline(
+                deadline,
+                |cs| self.acquire_cs(cs, n),
+                |cs| {
+                    let state = &mut *self.state.get();
+                    // The permits have been handed over to this thread in the meantime if it is
+                    // not waiting anymore.
+                    !state.waiters.remove_current(cs)
+                },
+            )
+        };
+        if acquired { Ok(()) } else { Err(TimeoutError) }
+    }
+
+    /// Acquires `n` permits, with timeout (blocking).
+    ///
+    /// See [`Self::acquire_until()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the permits could not be acquired within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_timeout(&self, n: usize, timeout: Duration) -> Result<(), TimeoutError> {
+        self.acquire_until(n, Instant::now().saturating_add(timeout))
+    }
+
+    /// Acquires `n` permits (non-blocking).
+    ///
+    /// Returns `true` if the permits have been acquired, `false` if fewer than `n` permits are
+    /// available.
+    ///
+    /// This can be used from interrupt handlers.
+    pub fn try_acquire(&self, n: usize) -> bool {
+        critical_section::with(|cs| self.try_acquire_cs(cs, n))
+    }
+
+    fn try_acquire_cs(&self, _cs: CriticalSection<'_>, n: usize) -> bool {
+        let state = unsafe { &mut *self.state.get() };
+        let Some(permits) = state.permits.checked_sub(n) else {
+            return false;
+        };
+        state.permits = permits;
+        true
+    }
+
+    /// Releases `n` permits.
+    ///
+    /// The permits are handed over to the waiters whose requests can be satisfied, in priority
+    /// order.
+    ///
+    /// This can be used from interrupt handlers.
+    pub fn release(&self, n: usize) {
+        critical_section::with(|cs| {
+            let state = unsafe { &mut *self.state.get() };
+            state.permits = state.permits.saturating_add(n);
+            self.hand_over(cs);
+        });
+    }
+
+    /// Hands the available permits over to all waiters whose requests can be satisfied, in
+    /// priority order, and wakes them up.
+    fn hand_over(&self, cs: CriticalSection<'_>) {
+        let state = unsafe { &mut *self.state.get() };
+        let mut permits = state.permits;
+        state.waiters.pop_matching(cs, |thread_state| {
+            let ThreadState::SemaphoreBlocked(n) = thread_state else {
+                unreachable!("unexpected thread state");
+            };
+            let Some(remaining) = permits.checked_sub(n) else {
+                return false;
+            };
+            permits = remaining;
+            true
+        });
+        state.permits = permits;
+    }
+}
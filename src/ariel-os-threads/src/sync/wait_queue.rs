@@ -36,13 +36,15 @@ impl WaitQueue {
         critical_section::with(|cs| self.wait_cs(cs));
     }
 
-    fn wait_cs(&self, cs: CriticalSection<'_>) {
+    pub(crate) fn wait_cs(&self, cs: CriticalSection<'_>) {
         let waiters = unsafe { &mut *self.waiters.get() };
         waiters.put_current(cs, ThreadState::WaitQueueBlocked);
     }
 
     /// Waits for this [`WaitQueue`] to be notified, with deadline (blocking).
     ///
+    /// Returns true if the queue was notified before `deadline`.
+    ///
     /// # Panics
     ///
     /// Panics if this is called outside of a thread context.
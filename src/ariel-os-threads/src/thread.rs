@@ -1,6 +1,10 @@
 #![expect(unsafe_code)]
 
-use crate::{Arch as _, Cpu, RunqueueId, ThreadData, ThreadId, thread_flags::ThreadFlags};
+use core::ops::Range;
+
+use crate::{
+    Arch as _, Cpu, RunqueueId, ThreadData, ThreadId, thread_flags::ThreadFlags, tls::TLS_SLOTS,
+};
 
 /// Main struct for holding thread data.
 #[derive(Debug)]
@@ -34,6 +38,28 @@ pub struct Thread {
     pub stack_lowest: usize,
     /// Highest stack address.
     pub stack_highest: usize,
+
+    /// Function run by a joinable thread until it is started, then the value returned by it until
+    /// the thread is joined.
+    pub(crate) entry_or_exit: usize,
+    /// Whether a [`crate::JoinHandle`] exists for the thread.
+    pub(crate) joinable: bool,
+
+    /// Values of the thread-local slots.
+    pub(crate) tls: [usize; TLS_SLOTS],
+    /// Bitmask of the thread-local slots that hold a value.
+    pub(crate) tls_used: u8,
+
+    /// Stack allocated by [`crate::spawn()`], freed once the thread slot is released.
+    #[cfg(feature = "alloc")]
+    pub(crate) heap_stack: Option<crate::spawn::HeapStack>,
+
+    /// Number of times the thread has been switched to.
+    #[cfg(feature = "stats")]
+    pub(crate) context_switches: u32,
+    /// Time spent running, in ticks.
+    #[cfg(feature = "stats")]
+    pub(crate) cpu_ticks: u64,
 }
 
 /// Possible states of a thread.
@@ -49,18 +75,40 @@ pub enum ThreadState {
     Running,
     /// Suspended / paused.
     Parked,
-    /// Waiting to acquire a [`super::lock::Lock`].
+    /// Waiting to acquire a [`crate::sync::Lock`], a [`crate::sync::Mutex`] or a
+    /// [`crate::sync::RwLock`].
     LockBlocked,
+    /// Waiting to acquire permits of a [`crate::sync::Semaphore`].
+    ///
+    /// Holds the number of permits to acquire.
+    SemaphoreBlocked(usize),
     /// Waiting for [`ThreadFlags`] to be set.
     FlagBlocked(crate::thread_flags::WaitMode),
-    /// Waiting to receive on a [`crate::sync::Channel`], i.e. waiting for the sender.
+    /// Waiting for bits of a [`crate::sync::EventGroup`] to be set.
+    ///
+    /// Holds the address of the wait condition.
+    EventGroupBlocked(usize),
+    /// Waiting to receive on a [`crate::sync::Channel`], i.e. waiting for a value to be sent.
+    ///
+    /// Holds the address of the channel.
     ChannelRxBlocked(usize),
-    /// Waiting to send on a [`crate::sync::Channel`], i.e. waiting for the receiver.
+    /// Waiting to send on a [`crate::sync::Channel`], i.e. waiting for free space.
+    ///
+    /// Holds the address of the channel.
     ChannelTxBlocked(usize),
-    /// Waiting for a [`super::wait_queue::WaitQueue`].
+    /// Waiting for a [`crate::sync::WaitQueue`].
     WaitQueueBlocked,
+    /// Returned from its function, waiting to be joined through its [`crate::JoinHandle`].
+    Finished,
 }
 
+/// Byte that's used to paint stacks.
+const STACK_PAINT_COLOR: u8 = 0xCC;
+
+/// Value written at the bottom of stacks to detect overflows.
+#[cfg(feature = "stack-canary")]
+const STACK_CANARY: u32 = 0xDEAD_BEEF;
+
 impl Thread {
     /// Creates an empty [`Thread`] object with [`ThreadState::Invalid`].
     pub const fn default() -> Thread {
@@ -75,6 +123,16 @@ impl Thread {
             deadline: None,
             stack_highest: 0,
             stack_lowest: 0,
+            entry_or_exit: 0,
+            joinable: false,
+            tls: [0; TLS_SLOTS],
+            tls_used: 0,
+            #[cfg(feature = "alloc")]
+            heap_stack: None,
+            #[cfg(feature = "stats")]
+            context_switches: 0,
+            #[cfg(feature = "stats")]
+            cpu_ticks: 0,
         }
     }
 
@@ -84,9 +142,6 @@ impl Thread {
     /// - must only be called before the stack is active (within `arch::setup_stack()`).
     #[allow(dead_code, reason = "not used in all configurations")]
     pub(crate) unsafe fn stack_paint_init(&mut self, sp: usize) {
-        // Byte that's used to paint stacks.
-        const STACK_PAINT_COLOR: u8 = 0xCC;
-
         for pos in self.stack_lowest..sp {
             // SAFETY: Writing to the slice that was passed to `setup_stack()` is fine
             unsafe {
@@ -94,6 +149,91 @@ impl Thread {
             }
         }
     }
+
+    /// Returns the range of the stack that is painted when the thread is set up, i.e., the stack
+    /// above the overflow protection, if any.
+    ///
+    /// The range is empty if the thread has no known stack (on native).
+    pub(crate) fn stack_painted_range(&self) -> Range<usize> {
+        if self.stack_lowest == self.stack_highest {
+            return 0..0;
+        }
+        self.stack_usable_lowest()..self.stack_highest
+    }
+
+    /// Returns the lowest stack address the thread may use, above the stack overflow protection,
+    /// if any.
+    fn stack_usable_lowest(&self) -> usize {
+        #[cfg(feature = "stack-canary")]
+        {
+            self.stack_canary_addr() + size_of::<u32>()
+        }
+        #[cfg(not(feature = "stack-canary"))]
+        {
+            crate::arch::stack_guard_end(self.stack_lowest)
+        }
+    }
+
+    /// Returns the address of the stack canary.
+    #[cfg(feature = "stack-canary")]
+    fn stack_canary_addr(&self) -> usize {
+        crate::arch::stack_guard_end(self.stack_lowest).next_multiple_of(align_of::<u32>())
+    }
+
+    /// Writes the canary at the bottom of the stack.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called before the stack is active, after `arch::setup_stack()`.
+    #[cfg(feature = "stack-canary")]
+    pub(crate) unsafe fn stack_canary_init(&mut self) {
+        if self.stack_lowest == self.stack_highest {
+            return;
+        }
+        // SAFETY: writing to the unused bottom of the stack, which is aligned.
+        unsafe { core::ptr::write_volatile(self.stack_canary_addr() as *mut u32, STACK_CANARY) };
+    }
+
+    /// Checks that the canary at the bottom of the stack is intact.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the ID of the thread if its stack has overflowed.
+    ///
+    /// # Safety
+    ///
+    /// The stack of the thread must still be valid.
+    #[cfg(feature = "stack-canary")]
+    pub(crate) unsafe fn stack_canary_check(&self) {
+        if self.stack_lowest == self.stack_highest {
+            return;
+        }
+        // SAFETY: reading from the bottom of the stack of the thread, which is still valid.
+        let canary = unsafe { core::ptr::read_volatile(self.stack_canary_addr() as *const u32) };
+        assert!(
+            canary == STACK_CANARY,
+            "stack overflow in thread {}",
+            usize::from(self.tid)
+        );
+    }
+}
+
+/// Returns the number of bytes at the start of `range` that still have the color the stack was
+/// painted with, i.e., that have never been used.
+///
+/// This runs in `O(n)`.
+///
+/// # Safety
+///
+/// `range` must be part of the stack of a thread that is not [`ThreadState::Invalid`] for the
+/// duration of the call.
+pub(crate) unsafe fn stack_unused_len(range: Range<usize>) -> usize {
+    range
+        // SAFETY: reading from the stack of the thread, which is still valid.
+        .take_while(
+            |&pos| unsafe { core::ptr::read_volatile(pos as *const u8) } == STACK_PAINT_COLOR,
+        )
+        .count()
 }
 
 #[cfg(test)]
@@ -105,6 +245,13 @@ mod tests {
         // `ThreadData` is arch-specific, and is replaced with a dummy value in tests; its size is
         // non-zero otherwise.
         assert_eq!(size_of::<ThreadData>(), 0);
-        assert_eq!(size_of::<Thread>(), size_of::<ThreadData>() + 56);
+        // The heap-allocated stack takes a pointer and its size.
+        let heap_stack = if cfg!(feature = "alloc") { 16 } else { 0 };
+        // Context switch count and CPU time.
+        let stats = if cfg!(feature = "stats") { 16 } else { 0 };
+        assert_eq!(
+            size_of::<Thread>(),
+            size_of::<ThreadData>() + 64 + heap_stack + stats
+        );
     }
 }
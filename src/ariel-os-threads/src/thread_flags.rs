@@ -1,5 +1,9 @@
 //! Thread flags.
-use crate::{SCHEDULER, Scheduler, ThreadId, ThreadState};
+use core::cell::Cell;
+
+use embassy_time::{Duration, Instant};
+
+use crate::{SCHEDULER, Scheduler, ThreadId, ThreadState, TimeoutError};
 
 /// Bitmask that represent the flags that are set for a thread.
 pub type ThreadFlags = u16;
@@ -34,11 +38,39 @@ pub fn set(thread_id: ThreadId, mask: ThreadFlags) {
 ///
 /// Panics if this is called outside of a thread context.
 pub fn wait_all(mask: ThreadFlags) -> ThreadFlags {
-    loop {
-        if let Some(flags) = SCHEDULER.with_mut(|mut scheduler| scheduler.flag_wait_all(mask)) {
-            return flags;
-        }
-    }
+    wait(WaitMode::All(mask), take_all)
+}
+
+/// Waits until all flags in `mask` are set for the current thread, or until `deadline` is
+/// reached.
+///
+/// See [`wait_all`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if the flags were not set before `deadline`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_all_until(mask: ThreadFlags, deadline: Instant) -> Result<ThreadFlags, TimeoutError> {
+    wait_until(WaitMode::All(mask), take_all, deadline)
+}
+
+/// Waits until all flags in `mask` are set for the current thread, or until `timeout` has
+/// elapsed.
+///
+/// See [`wait_all`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if the flags were not set within `timeout`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_all_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_all_until(mask, Instant::now().saturating_add(timeout))
 }
 
 /// Waits until any flag in `mask` is set for the current thread.
@@ -49,11 +81,39 @@ pub fn wait_all(mask: ThreadFlags) -> ThreadFlags {
 ///
 /// Panics if this is called outside of a thread context.
 pub fn wait_any(mask: ThreadFlags) -> ThreadFlags {
-    loop {
-        if let Some(flags) = SCHEDULER.with_mut(|mut scheduler| scheduler.flag_wait_any(mask)) {
-            return flags;
-        }
-    }
+    wait(WaitMode::Any(mask), take_any)
+}
+
+/// Waits until any flag in `mask` is set for the current thread, or until `deadline` is
+/// reached.
+///
+/// See [`wait_any`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if no flag was set before `deadline`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_any_until(mask: ThreadFlags, deadline: Instant) -> Result<ThreadFlags, TimeoutError> {
+    wait_until(WaitMode::Any(mask), take_any, deadline)
+}
+
+/// Waits until any flag in `mask` is set for the current thread, or until `timeout` has
+/// elapsed.
+///
+/// See [`wait_any`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if no flag was set within `timeout`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_any_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_any_until(mask, Instant::now().saturating_add(timeout))
 }
 
 /// Waits until any flag in `mask` is set for the current thread.
@@ -65,13 +125,122 @@ pub fn wait_any(mask: ThreadFlags) -> ThreadFlags {
 ///
 /// Panics if this is called outside of a thread context.
 pub fn wait_one(mask: ThreadFlags) -> ThreadFlags {
+    wait(WaitMode::Any(mask), take_one)
+}
+
+/// Waits until any flag in `mask` is set for the current thread, or until `deadline` is
+/// reached.
+///
+/// See [`wait_one`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if no flag was set before `deadline`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_one_until(mask: ThreadFlags, deadline: Instant) -> Result<ThreadFlags, TimeoutError> {
+    wait_until(WaitMode::Any(mask), take_one, deadline)
+}
+
+/// Waits until any flag in `mask` is set for the current thread, or until `timeout` has
+/// elapsed.
+///
+/// See [`wait_one`].
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if no flag was set within `timeout`.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_one_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_one_until(mask, Instant::now().saturating_add(timeout))
+}
+
+/// Takes flags out of a thread's flags, returning `None` if the wait condition is not met.
+type TakeFn = fn(&mut ThreadFlags, ThreadFlags) -> Option<ThreadFlags>;
+
+fn take_all(flags: &mut ThreadFlags, mask: ThreadFlags) -> Option<ThreadFlags> {
+    if *flags & mask == mask {
+        *flags &= !mask;
+        Some(mask)
+    } else {
+        None
+    }
+}
+
+fn take_any(flags: &mut ThreadFlags, mask: ThreadFlags) -> Option<ThreadFlags> {
+    let res = *flags & mask;
+    if res != 0 {
+        *flags &= !res;
+        Some(res)
+    } else {
+        None
+    }
+}
+
+fn take_one(flags: &mut ThreadFlags, mask: ThreadFlags) -> Option<ThreadFlags> {
+    let mut res = *flags & mask;
+    if res != 0 {
+        // clear all but least significant bit
+        res &= !res + 1;
+        *flags &= !res;
+        Some(res)
+    } else {
+        None
+    }
+}
+
+/// # Panics
+///
+/// Panics if called outside a thread context.
+fn wait(mode: WaitMode, take: TakeFn) -> ThreadFlags {
     loop {
-        if let Some(flags) = SCHEDULER.with_mut(|mut scheduler| scheduler.flag_wait_one(mask)) {
+        if let Some(flags) = SCHEDULER.with_mut(|mut scheduler| scheduler.flag_wait(mode, take)) {
             return flags;
         }
     }
 }
 
+/// # Panics
+///
+/// Panics if called outside a thread context.
+fn wait_until(
+    mode: WaitMode,
+    take: TakeFn,
+    deadline: Instant,
+) -> Result<ThreadFlags, TimeoutError> {
+    let flags = Cell::new(None);
+    loop {
+        // Safety:
+        // The thread only waits on its own flags, so it is not part of any waitlist.
+        let done = unsafe {
+            crate::timeout::with_deadline_check(
+                deadline,
+                |cs| {
+                    if flags.get().is_none() {
+                        flags.set(
+                            SCHEDULER
+                                .with_mut_cs(cs, |mut scheduler| scheduler.flag_take(mode, take)),
+                        );
+                    }
+                    flags.get().is_some()
+                },
+                |cs| {
+                    SCHEDULER.with_mut_cs(cs, |mut scheduler| scheduler.flag_block(mode));
+                },
+                |_cs| {},
+            )
+        };
+        if done || Instant::now() >= deadline {
+            return flags.get().ok_or(TimeoutError);
+        }
+    }
+}
+
 /// Clears flags for the current thread.
 ///
 /// # Panics
@@ -109,52 +278,37 @@ impl Scheduler {
         self.set_state(thread_id, ThreadState::Running);
     }
 
+    /// Takes the flags `mode` waits for, or blocks the current thread if they are not set.
+    ///
     /// # Panics
     ///
     /// Panics if called outside a thread context.
-    fn flag_wait_all(&mut self, mask: ThreadFlags) -> Option<ThreadFlags> {
-        let thread = self.current().unwrap();
-        if thread.flags & mask == mask {
-            thread.flags &= !mask;
-            Some(mask)
-        } else {
-            let thread_id = thread.tid;
-            self.set_state(thread_id, ThreadState::FlagBlocked(WaitMode::All(mask)));
-            None
+    fn flag_wait(&mut self, mode: WaitMode, take: TakeFn) -> Option<ThreadFlags> {
+        let res = self.flag_take(mode, take);
+        if res.is_none() {
+            self.flag_block(mode);
         }
+        res
     }
 
+    /// Takes the flags `mode` waits for from the current thread, if they are set.
+    ///
     /// # Panics
     ///
     /// Panics if called outside a thread context.
-    fn flag_wait_any(&mut self, mask: ThreadFlags) -> Option<ThreadFlags> {
+    fn flag_take(&mut self, mode: WaitMode, take: TakeFn) -> Option<ThreadFlags> {
+        let (WaitMode::Any(mask) | WaitMode::All(mask)) = mode;
         let thread = self.current().unwrap();
-        if thread.flags & mask != 0 {
-            let res = thread.flags & mask;
-            thread.flags &= !res;
-            Some(res)
-        } else {
-            let thread_id = thread.tid;
-            self.set_state(thread_id, ThreadState::FlagBlocked(WaitMode::Any(mask)));
-            None
-        }
+        take(&mut thread.flags, mask)
     }
 
+    /// Blocks the current thread until the flags `mode` waits for are set.
+    ///
     /// # Panics
     ///
     /// Panics if called outside a thread context.
-    fn flag_wait_one(&mut self, mask: ThreadFlags) -> Option<ThreadFlags> {
-        let thread = self.current().unwrap();
-        if thread.flags & mask != 0 {
-            let mut res = thread.flags & mask;
-            // clear all but least significant bit
-            res &= !res + 1;
-            thread.flags &= !res;
-            Some(res)
-        } else {
-            let thread_id = thread.tid;
-            self.set_state(thread_id, ThreadState::FlagBlocked(WaitMode::Any(mask)));
-            None
-        }
+    fn flag_block(&mut self, mode: WaitMode) {
+        let thread_id = self.current().unwrap().tid;
+        self.set_state(thread_id, ThreadState::FlagBlocked(mode));
     }
 }
@@ -68,6 +68,40 @@ impl ThreadList {
         })
     }
 
+    /// Returns the priority of the head of this [`ThreadList`], i.e., the highest priority among
+    /// its threads.
+    pub fn head_prio(&self, cs: CriticalSection<'_>) -> Option<RunqueueId> {
+        let head = self.head?;
+        SCHEDULER.with_mut_cs(cs, |scheduler| Some(scheduler.get_unchecked(head).prio))
+    }
+
+    /// Removes all threads whose [`ThreadState`] matches `predicate` from this [`ThreadList`].
+    ///
+    /// Sets their [`ThreadState`] to [`ThreadState::Running`] and triggers the scheduler.
+    pub(crate) fn pop_matching(
+        &mut self,
+        cs: CriticalSection<'_>,
+        mut predicate: impl FnMut(ThreadState) -> bool,
+    ) {
+        SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let mut prev: Option<ThreadId> = None;
+            let mut next = self.head;
+            while let Some(thread_id) = next {
+                next = scheduler.thread_blocklist[usize::from(thread_id)];
+                if predicate(scheduler.get_unchecked(thread_id).state) {
+                    match prev {
+                        Some(prev) => scheduler.thread_blocklist[usize::from(prev)] = next,
+                        None => self.head = next,
+                    }
+                    scheduler.thread_blocklist[usize::from(thread_id)] = None;
+                    scheduler.set_state(thread_id, ThreadState::Running);
+                } else {
+                    prev = Some(thread_id);
+                }
+            }
+        });
+    }
+
     fn remove_inner(&mut self, scheduler: &mut Scheduler, thread_id: ThreadId) -> bool {
         ariel_os_log::trace!("remove_current() {:?}", thread_id);
         if let Some(head) = self.head {
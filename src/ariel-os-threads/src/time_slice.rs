@@ -0,0 +1,168 @@
+//! Time-sliced round-robin scheduling among threads of the same priority.
+//!
+//! While a thread has ready peers of the same priority, a tick is scheduled on the
+//! `embassy-time` driver for the end of its time slice. The tick triggers the scheduler, which
+//! moves the thread to the tail of its runqueue if its time slice has expired.
+
+use core::task::{RawWaker, RawWakerVTable, Waker};
+
+use embassy_time::Duration;
+
+use crate::{RunqueueId, Scheduler, TIME_SLICE_US, ThreadId, ThreadState};
+
+#[cfg(not(feature = "multi-core"))]
+use crate::schedule;
+#[cfg(feature = "multi-core")]
+use crate::{CORE_COUNT, CoreId, schedule_on_core};
+
+const TIME_SLICE_TICKS: u64 = Duration::from_micros(TIME_SLICE_US as u64).as_ticks();
+
+/// Returns whether `prio` is the priority of the idle threads, if there are any.
+///
+/// Without idle threads, threads of priority 0 are time-sliced like any others.
+fn is_idle_prio(prio: RunqueueId) -> bool {
+    cfg!(any(feature = "multi-core", feature = "idle-threads")) && prio == RunqueueId::new(0)
+}
+
+/// Time slice state of the scheduler.
+pub(crate) struct TimeSlice {
+    /// Time at which the time slice of the current thread of each core has started, in ticks.
+    started_at: [u64; crate::CORE_COUNT],
+    /// Time of the scheduled tick, if any, in ticks.
+    tick_at: Option<u64>,
+}
+
+impl TimeSlice {
+    pub(crate) const fn new() -> Self {
+        Self {
+            started_at: [0; crate::CORE_COUNT],
+            tick_at: None,
+        }
+    }
+}
+
+/// Triggers the scheduler on all cores.
+///
+/// This must not access the scheduler, as [`schedule_tick()`] is called from within it and may
+/// call this right away.
+fn tick(_ptr: *const ()) {
+    #[cfg(not(feature = "multi-core"))]
+    schedule();
+
+    #[cfg(feature = "multi-core")]
+    for core in 0..CORE_COUNT {
+        schedule_on_core(CoreId(core as u8));
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    // clone
+    |ptr| RawWaker::new(ptr, &VTABLE),
+    tick,
+    tick,
+    |_ptr| {},
+);
+
+fn schedule_tick(at: u64) {
+    let raw_waker = RawWaker::new(core::ptr::null(), &VTABLE);
+    let waker = unsafe { Waker::from_raw(raw_waker) };
+    embassy_time_driver::schedule_wake(at, &waker);
+}
+
+impl Scheduler {
+    /// Moves the current thread to the tail of its runqueue if its time slice has expired.
+    ///
+    /// Must be called by the scheduler before picking the next thread.
+    /// On multi-core, this is not needed, as the current thread is re-added to the tail of its
+    /// runqueue on every scheduler invocation anyway.
+    #[cfg(feature = "single-core")]
+    pub(crate) fn time_slice_expire(&mut self, now: u64) {
+        let started_at = self.time_slice.started_at[usize::from(crate::core_id())];
+        if let Some(&mut crate::Thread {
+            prio,
+            state: ThreadState::Running,
+            ..
+        }) = self.current()
+            && now >= started_at.saturating_add(TIME_SLICE_TICKS)
+        {
+            self.runqueue.advance(prio);
+        }
+    }
+
+    /// Starts a new time slice if `next` is not the current thread, and schedules a tick for
+    /// its end if `next` has ready peers of the same priority.
+    ///
+    /// Must be called by the scheduler after picking the next thread.
+    pub(crate) fn time_slice_start(&mut self, next: ThreadId, now: u64) {
+        let continued = self
+            .current()
+            .is_some_and(|thread| thread.tid == next && thread.state == ThreadState::Running);
+
+        let core = usize::from(crate::core_id());
+        if !continued {
+            self.time_slice.started_at[core] = now;
+        }
+        if self
+            .time_slice
+            .tick_at
+            .is_some_and(|tick_at| tick_at <= now)
+        {
+            self.time_slice.tick_at = None;
+        }
+
+        let end = self.time_slice.started_at[core].saturating_add(TIME_SLICE_TICKS);
+        if self.has_peers(next) && self.time_slice.tick_at.is_none_or(|tick_at| end < tick_at) {
+            self.time_slice.tick_at = Some(end);
+            schedule_tick(end);
+        }
+    }
+
+    /// Triggers the scheduler if a thread of priority `prio` is running and no tick is
+    /// scheduled, so that a tick is scheduled for its time slice.
+    ///
+    /// Must be called when a thread of priority `prio` becomes ready.
+    pub(crate) fn time_slice_on_ready(&mut self, prio: RunqueueId) {
+        if self.time_slice.tick_at.is_some() || is_idle_prio(prio) {
+            return;
+        }
+
+        #[cfg(not(feature = "multi-core"))]
+        if self.current().is_some_and(|thread| thread.prio == prio) {
+            schedule();
+        }
+
+        #[cfg(feature = "multi-core")]
+        if let Some(core) = self
+            .current_threads
+            .iter()
+            .position(|tid| tid.is_some_and(|tid| self.get_unchecked(tid).prio == prio))
+        {
+            schedule_on_core(CoreId(core as u8));
+        }
+    }
+
+    /// Returns whether other threads with the same priority as `thread_id` are ready.
+    ///
+    /// Idle threads are never time-sliced.
+    fn has_peers(&mut self, thread_id: ThreadId) -> bool {
+        let prio = self.get_unchecked(thread_id).prio;
+        if is_idle_prio(prio) {
+            return false;
+        }
+
+        // On single-core, `thread_id` is still in the runqueue.
+        #[cfg(feature = "single-core")]
+        {
+            self.runqueue
+                .iter_from(thread_id, prio)
+                .next()
+                .is_some_and(|tid| tid != thread_id && self.get_unchecked(tid).prio == prio)
+        }
+
+        // On multi-core, `thread_id` has been removed from the runqueue.
+        #[cfg(feature = "multi-core")]
+        {
+            !self.runqueue.is_empty(prio)
+        }
+    }
+}
@@ -3,11 +3,22 @@ use core::task::{RawWaker, RawWakerVTable, Waker};
 use critical_section::CriticalSection;
 use embassy_time::Duration;
 
-use crate::{SCHEDULER, ThreadId, ThreadState, thread_flags::ThreadFlags};
+use crate::{SCHEDULER, ThreadId, ThreadState};
 
 use ariel_os_log::trace;
 
-const THREAD_FLAG_TIMEOUT: ThreadFlags = 2; // TODO: find more appropriate value
+/// Error indicating that a blocking operation did not complete before its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeoutError;
+
+impl core::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("deadline reached")
+    }
+}
+
+impl core::error::Error for TimeoutError {}
 
 fn wake(ptr: *const ()) {
     #[expect(clippy::cast_possible_truncation)]
@@ -20,8 +31,9 @@ fn wake(ptr: *const ()) {
                     "timer for {:?} expired, triggering thread (deadline={:?}, now={:?})",
                     thread_id, deadline, now
                 );
+                // The cleared deadline tells the woken thread that it timed out; its thread flags
+                // are left alone.
                 scheduler.threads[usize::from(thread_id)].deadline = None;
-                scheduler.flag_set(thread_id, THREAD_FLAG_TIMEOUT);
                 match scheduler.get_state(thread_id) {
                     Some(ThreadState::Running) => {}
                     _ => {
@@ -69,7 +81,6 @@ fn set_deadline(cs: CriticalSection<'_>, deadline: u64) -> bool {
     let thread_id = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
         let thread = scheduler.current().expect("must be called from a thread");
         thread.deadline = Some(deadline);
-        thread.flags &= !THREAD_FLAG_TIMEOUT;
         thread.tid
     });
 
@@ -111,7 +122,9 @@ fn clear_deadline(cs: CriticalSection<'_>) -> bool {
 /// If the deadline is reached before the function returns, the thread will be set to runnable
 /// state, and `on_timeout()` will be called after the function returns.
 ///
-/// Returns false if the deadline was reached, and `on_timeout()` was called.
+/// Returns true if the function returned before the deadline was reached (ie. the thread was
+/// woken up by whatever it waited for), false if the deadline was in the past, and otherwise the
+/// result of `on_timeout()`.
 ///
 /// # Safety
 /// This will set the calling thread to `Runnable` after the timeout expires. Caller must ensure safety implications of that.
@@ -135,7 +148,7 @@ pub(crate) unsafe fn with_deadline(
     }) && critical_section::with(|cs| {
         if clear_deadline(cs) {
             trace!("with_deadline: cleared deadline {}", deadline);
-            false
+            true
         } else {
             trace!("with_deadline: timeout deadline {}", deadline);
             on_timeout(cs)
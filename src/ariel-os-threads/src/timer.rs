@@ -0,0 +1,291 @@
+//! Software timers.
+//!
+//! One-shot and periodic timers are all served by a single timer-service thread, which is
+//! started along with the first timer. When a timer expires, it sets thread flags, signals an
+//! [`Event`], or runs a callback in the timer-service thread, depending on its [`Action`].
+//!
+//! At most [`TIMER_COUNT`] timers can be active at the same time, which can be configured using
+//! `CONFIG_THREAD_TIMER_COUNT`.
+//! The timer-service thread has the highest priority by default; its priority and stack size can
+//! be configured using `CONFIG_THREAD_TIMER_PRIORITY` and `CONFIG_THREAD_TIMER_STACKSIZE`.
+//! As callbacks run in that thread, they should return quickly and must not block.
+//!
+//! Example:
+//!
+//! ```ignore
+//! let timer = timer::start_periodic(Duration::from_millis(500), Action::Callback(toggle_led))?;
+//! // ...
+//! timer::cancel(timer);
+//! ```
+
+use core::cell::{Cell, RefCell};
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+use static_cell::ConstStaticCell;
+
+use crate::{
+    SCHED_PRIO_LEVELS, SCHEDULER, ThreadId, create_noarg,
+    sync::Event,
+    thread_flags::{self, ThreadFlags},
+};
+
+/// Maximum number of active timers.
+pub const TIMER_COUNT: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_TIMER_COUNT",
+    8,
+    "maximum number of active software timers"
+);
+
+/// Stack size of the timer-service thread.
+pub const STACKSIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_TIMER_STACKSIZE",
+    2048,
+    "timer-service thread stack size"
+);
+
+/// Priority of the timer-service thread.
+pub const PRIORITY: u8 = ariel_os_utils::u8_from_env_or!(
+    "CONFIG_THREAD_TIMER_PRIORITY",
+    (SCHED_PRIO_LEVELS - 1) as u8,
+    "timer-service thread priority"
+);
+
+// Generations are stored as `u16`, and slot indices as `u8`.
+const _: () = assert!(
+    TIMER_COUNT > 0 && TIMER_COUNT <= u8::MAX as usize,
+    "`CONFIG_THREAD_TIMER_COUNT` must be between 1 and 255"
+);
+
+/// Flag set on the timer-service thread when a timer has been started.
+const FLAG_STARTED: ThreadFlags = 0b1;
+
+/// Action performed when a timer expires.
+#[derive(Clone, Copy)]
+pub enum Action {
+    /// Sets the given flags of the given thread.
+    Flags(ThreadId, ThreadFlags),
+    /// Sets the given [`Event`].
+    Event(&'static Event),
+    /// Runs the given function in the timer-service thread.
+    Callback(fn()),
+}
+
+impl Action {
+    fn run(self) {
+        match self {
+            Self::Flags(thread_id, flags) => thread_flags::set(thread_id, flags),
+            Self::Event(event) => event.set(),
+            Self::Callback(callback) => callback(),
+        }
+    }
+}
+
+/// Identifies a started timer.
+///
+/// Stays valid after the timer has expired or has been cancelled, but then does not refer to
+/// any active timer anymore, even if its slot is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimerId {
+    index: u8,
+    generation: u16,
+}
+
+/// Error returned when a timer could not be started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimerError {
+    /// [`TIMER_COUNT`] timers are active already.
+    NoFreeSlot,
+    /// The timer-service thread could not be started because all
+    /// [`THREAD_COUNT`](crate::THREAD_COUNT) thread slots are in use.
+    NoFreeThread,
+}
+
+impl core::fmt::Display for TimerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoFreeSlot => write!(f, "no free timer slot"),
+            Self::NoFreeThread => write!(f, "no free thread slot for the timer-service thread"),
+        }
+    }
+}
+
+impl core::error::Error for TimerError {}
+
+#[derive(Clone, Copy)]
+struct ActiveTimer {
+    deadline: Instant,
+    /// `None` for one-shot timers.
+    period: Option<Duration>,
+    action: Action,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    /// Incremented whenever the slot is assigned, to detect stale [`TimerId`]s.
+    generation: u16,
+    timer: Option<ActiveTimer>,
+}
+
+static TIMERS: Mutex<RefCell<[Slot; TIMER_COUNT]>> = Mutex::new(RefCell::new(
+    [Slot {
+        generation: 0,
+        timer: None,
+    }; TIMER_COUNT],
+));
+
+/// The timer-service thread, once started.
+static SERVICE: Mutex<Cell<Option<ThreadId>>> = Mutex::new(Cell::new(None));
+
+/// Starts a one-shot timer, performing `action` once `delay` has elapsed.
+///
+/// # Errors
+///
+/// Returns [`TimerError::NoFreeSlot`] if [`TIMER_COUNT`] timers are active already, or
+/// [`TimerError::NoFreeThread`] if the timer-service thread could not be started.
+pub fn start_once(delay: Duration, action: Action) -> Result<TimerId, TimerError> {
+    start(delay, None, action)
+}
+
+/// Starts a periodic timer, performing `action` every `period`, until it is cancelled.
+///
+/// If the timer-service thread falls behind, missed expirations are skipped.
+///
+/// # Errors
+///
+/// Returns [`TimerError::NoFreeSlot`] if [`TIMER_COUNT`] timers are active already, or
+/// [`TimerError::NoFreeThread`] if the timer-service thread could not be started.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn start_periodic(period: Duration, action: Action) -> Result<TimerId, TimerError> {
+    assert!(period.as_ticks() > 0, "timer period must not be zero");
+    start(period, Some(period), action)
+}
+
+/// Cancels a timer.
+///
+/// Returns `false` if the timer was not active anymore, i.e., if it was a one-shot timer that
+/// has expired already, or if it has been cancelled already.
+#[expect(
+    clippy::must_use_candidate,
+    reason = "timers are usually cancelled regardless of whether they are still active"
+)]
+pub fn cancel(timer: TimerId) -> bool {
+    critical_section::with(|cs| {
+        let mut timers = TIMERS.borrow_ref_mut(cs);
+        let slot = &mut timers[usize::from(timer.index)];
+        slot.generation == timer.generation && slot.timer.take().is_some()
+    })
+}
+
+/// Returns whether a timer is active, i.e., whether it will expire (again).
+#[must_use]
+pub fn is_active(timer: TimerId) -> bool {
+    critical_section::with(|cs| {
+        let timers = TIMERS.borrow_ref(cs);
+        let slot = &timers[usize::from(timer.index)];
+        slot.generation == timer.generation && slot.timer.is_some()
+    })
+}
+
+fn start(delay: Duration, period: Option<Duration>, action: Action) -> Result<TimerId, TimerError> {
+    let deadline = Instant::now().saturating_add(delay);
+    let (timer, service) = critical_section::with(|cs| {
+        let service = start_service(cs)?;
+        let mut timers = TIMERS.borrow_ref_mut(cs);
+        let (index, slot) = timers
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.timer.is_none())
+            .ok_or(TimerError::NoFreeSlot)?;
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.timer = Some(ActiveTimer {
+            deadline,
+            period,
+            action,
+        });
+        let timer = TimerId {
+            index: index as u8,
+            generation: slot.generation,
+        };
+        Ok((timer, service))
+    })?;
+    // Let the timer-service thread take the new deadline into account.
+    thread_flags::set(service, FLAG_STARTED);
+    Ok(timer)
+}
+
+/// Starts the timer-service thread if it is not running yet.
+fn start_service(cs: critical_section::CriticalSection<'_>) -> Result<ThreadId, TimerError> {
+    static STACK: ConstStaticCell<[u8; STACKSIZE]> = ConstStaticCell::new([0u8; STACKSIZE]);
+
+    let service = SERVICE.borrow(cs);
+    if let Some(thread_id) = service.get() {
+        return Ok(thread_id);
+    }
+    // Check for a free thread slot first, so that the stack is only taken once the thread can
+    // be created.
+    if !SCHEDULER.with_mut_cs(cs, |mut scheduler| scheduler.get_unused().is_some()) {
+        return Err(TimerError::NoFreeThread);
+    }
+    let thread_id = create_noarg(run_service, STACK.take(), PRIORITY, None);
+    service.set(Some(thread_id));
+    Ok(thread_id)
+}
+
+/// Function of the timer-service thread.
+fn run_service() {
+    loop {
+        let now = Instant::now();
+        while let Some(action) = take_expired(now) {
+            // Actions run outside of the critical section, so that callbacks can use timers.
+            action.run();
+        }
+
+        match next_deadline() {
+            Some(deadline) => {
+                let _ = thread_flags::wait_any_until(FLAG_STARTED, deadline);
+            }
+            None => {
+                thread_flags::wait_any(FLAG_STARTED);
+            }
+        }
+    }
+}
+
+/// Returns the action of the earliest timer expired at `now`, and rearms or removes it.
+fn take_expired(now: Instant) -> Option<Action> {
+    critical_section::with(|cs| {
+        let mut timers = TIMERS.borrow_ref_mut(cs);
+        let slot = timers
+            .iter_mut()
+            .filter(|slot| slot.timer.is_some_and(|timer| timer.deadline <= now))
+            .min_by_key(|slot| slot.timer.map(|timer| timer.deadline))?;
+        let timer = slot.timer.as_mut()?;
+        let action = timer.action;
+        if let Some(period) = timer.period {
+            // Skip expirations that have been missed.
+            let period = period.as_ticks();
+            let missed = (now.as_ticks() - timer.deadline.as_ticks()) / period;
+            timer.deadline = Instant::from_ticks(timer.deadline.as_ticks() + (missed + 1) * period);
+        } else {
+            slot.timer = None;
+        }
+        Some(action)
+    })
+}
+
+/// Returns the earliest deadline of all active timers.
+fn next_deadline() -> Option<Instant> {
+    critical_section::with(|cs| {
+        TIMERS
+            .borrow_ref(cs)
+            .iter()
+            .filter_map(|slot| slot.timer.map(|timer| timer.deadline))
+            .min()
+    })
+}
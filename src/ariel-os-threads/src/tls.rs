@@ -0,0 +1,148 @@
+//! Thread-local storage.
+//!
+//! Each thread has [`TLS_SLOTS`] slots, which can be configured using
+//! `CONFIG_THREAD_TLS_SLOTS`. There are none by default, as each slot takes one word in every
+//! thread slot. A [`LocalSlot`] gets one of them assigned on first use, and gives typed access to
+//! it; slots are empty when a thread is created.
+//!
+//! Slot indices are assigned for the lifetime of the program and are never reclaimed: each
+//! [`LocalSlot`] that has been used once occupies its index for good, in all threads.
+//! [`LocalSlot`]s are meant to be `static`s, so `CONFIG_THREAD_TLS_SLOTS` must be at least the
+//! number of those used by the application.
+//!
+//! Example:
+//!
+//! ```ignore
+//! static REQUEST_ID: LocalSlot<u32> = LocalSlot::new();
+//!
+//! REQUEST_ID.set(42);
+//! assert_eq!(REQUEST_ID.get(), Some(42));
+//! ```
+
+use core::{cell::Cell, marker::PhantomData};
+
+use critical_section::{CriticalSection, Mutex};
+
+use crate::{SCHEDULER, Word, thread::Thread};
+
+/// Number of thread-local slots of each thread.
+pub const TLS_SLOTS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_TLS_SLOTS",
+    0,
+    "number of thread-local storage slots of each thread"
+);
+
+const _: () = assert!(
+    TLS_SLOTS <= u8::BITS as usize,
+    "at most 8 TLS slots are supported"
+);
+
+/// Index of the next slot to assign.
+///
+/// Only ever increases, as indices are never reclaimed.
+static NEXT_INDEX: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+/// Thread-local value of type `T`, stored in one of the thread-local slots.
+///
+/// Each thread sees its own value, which is only accessible from that thread.
+pub struct LocalSlot<T: Word> {
+    index: Mutex<Cell<Option<u8>>>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: Word> LocalSlot<T> {
+    /// Creates a new [`LocalSlot`].
+    ///
+    /// A slot index is only assigned on first use.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            index: Mutex::new(Cell::new(None)),
+            _value: PhantomData,
+        }
+    }
+
+    /// Stores a value in the slot of the current thread.
+    ///
+    /// Returns the previous value, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context, or if no slot is left to assign.
+    pub fn set(&self, value: T) -> Option<T> {
+        self.with_current(|thread, index| {
+            let previous = Self::read(thread, index);
+            thread.tls[index] = value.into_word();
+            thread.tls_used |= 1 << index;
+            previous
+        })
+    }
+
+    /// Returns the value in the slot of the current thread, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context, or if no slot is left to assign.
+    #[must_use]
+    pub fn get(&self) -> Option<T> {
+        self.with_current(|thread, index| Self::read(thread, index))
+    }
+
+    /// Removes the value from the slot of the current thread and returns it, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context, or if no slot is left to assign.
+    pub fn take(&self) -> Option<T> {
+        self.with_current(|thread, index| {
+            let previous = Self::read(thread, index);
+            thread.tls_used &= !(1 << index);
+            previous
+        })
+    }
+
+    fn read(thread: &Thread, index: usize) -> Option<T> {
+        // SAFETY: the value has been stored by `set()` of this `LocalSlot`, as slot indices are
+        // not shared.
+        (thread.tls_used & (1 << index) != 0).then(|| unsafe { T::from_word(thread.tls[index]) })
+    }
+
+    /// Returns the slot index, assigning one if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all [`TLS_SLOTS`] slots have been assigned already.
+    fn index(&self, cs: CriticalSection<'_>) -> usize {
+        let index = self.index.borrow(cs);
+        if let Some(index) = index.get() {
+            return usize::from(index);
+        }
+        let next_index = NEXT_INDEX.borrow(cs);
+        let assigned = next_index.get();
+        assert!(
+            usize::from(assigned) < TLS_SLOTS,
+            "all TLS slots are in use, increase `CONFIG_THREAD_TLS_SLOTS`"
+        );
+        next_index.set(assigned + 1);
+        index.set(Some(assigned));
+        usize::from(assigned)
+    }
+
+    fn with_current<U>(&self, f: impl FnOnce(&mut Thread, usize) -> U) -> U {
+        critical_section::with(|cs| {
+            let index = self.index(cs);
+            SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                let thread = scheduler
+                    .current()
+                    .expect("Function should be called inside a thread context.");
+                f(thread, index)
+            })
+        })
+    }
+}
+
+impl<T: Word> Default for LocalSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,806 @@
+//! Block-wise transfers ([RFC 7959]) on the server side, see [`BlockwiseHandler`].
+//!
+//! [RFC 7959]: https://www.rfc-editor.org/rfc/rfc7959
+
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage, error::RenderableOnMinimal,
+};
+use coap_message_implementations::{inmemory, inmemory_write};
+use coap_numbers::option::{BLOCK1, BLOCK2, ETAG, SIZE1, SIZE2};
+use defmt_or_log::{debug, error};
+
+/// Largest block size exponent used in responses (for blocks of 512 bytes).
+///
+/// Blocks of that size and the options of a typical response fit into the 1152 bytes that CoAP
+/// stacks commonly limit messages to, even after protection with OSCORE.
+const MAX_SZX: u8 = 5;
+
+/// Upper bound on the encoded length of the options added to a response (ETag, Block2, Block1 and
+/// Size2, each with up to 2 bytes of header and 4 bytes of value).
+const MAX_ADDED_OPTIONS_LEN: usize = 24;
+
+/// The value of a Block1 or Block2 option ([RFC 7959 Section 2.2]).
+///
+/// [RFC 7959 Section 2.2]: https://www.rfc-editor.org/rfc/rfc7959#section-2.2
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockOption {
+    /// Number of the block, counted in blocks of the size indicated by `szx`.
+    pub num: u32,
+    /// Whether further blocks follow.
+    pub more: bool,
+    /// Size exponent: the block size is `2**(szx + 4)` bytes.
+    pub szx: u8,
+}
+
+impl BlockOption {
+    /// Parses an option value; returns `None` if it is too long or uses the reserved size
+    /// exponent.
+    #[must_use]
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        if value.len() > 3 {
+            return None;
+        }
+        let value = value
+            .iter()
+            .fold(0u32, |acc, byte| (acc << 8) | u32::from(*byte));
+        #[expect(clippy::cast_possible_truncation, reason = "masked to 3 bits")]
+        let szx = (value & 0x07) as u8;
+        if szx == 7 {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    /// Encodes the block as a minimal option value.
+    #[must_use]
+    pub fn encode(self) -> heapless::Vec<u8, 4> {
+        encode_uint((self.num << 4) | (u32::from(self.more) << 3) | u32::from(self.szx))
+    }
+
+    /// Size of the block in bytes.
+    #[must_use]
+    pub fn size(self) -> usize {
+        16 << self.szx
+    }
+
+    /// Position of the block's first byte in the complete payload.
+    #[must_use]
+    pub fn offset(self) -> usize {
+        usize::try_from(self.num)
+            .unwrap_or(usize::MAX)
+            .saturating_mul(self.size())
+    }
+}
+
+/// Encodes a number as a minimal CoAP unsigned integer option value.
+pub(crate) fn encode_uint(value: u32) -> heapless::Vec<u8, 4> {
+    let bytes = value.to_be_bytes();
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    let (_, bytes) = bytes.split_at(leading_zeros);
+    bytes.iter().copied().collect()
+}
+
+/// Error type returned when a request can not be processed by the [`BlockwiseHandler`].
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockwiseError {
+    /// A block option was malformed, or requested a block beyond the response.
+    BadOption,
+    /// A block of a request arrived without its preceding blocks.
+    Incomplete,
+    /// The request does not fit into the handler's buffer.
+    TooLarge {
+        /// Size of the largest request that is accepted.
+        max_size: usize,
+    },
+}
+
+impl RenderableOnMinimal for BlockwiseError {
+    type Error<IE>
+        = IE
+    where
+        IE: RenderableOnMinimal,
+        IE: core::fmt::Debug;
+
+    fn render<M: MinimalWritableMessage>(
+        self,
+        msg: &mut M,
+    ) -> Result<(), Self::Error<M::UnionError>> {
+        let code = match self {
+            Self::BadOption => coap_numbers::code::BAD_OPTION,
+            Self::Incomplete => coap_numbers::code::REQUEST_ENTITY_INCOMPLETE,
+            Self::TooLarge { .. } => coap_numbers::code::REQUEST_ENTITY_TOO_LARGE,
+        };
+        msg.set_code(M::Code::new(code)?);
+        if let Self::TooLarge { max_size } = self {
+            // Tells the client how large a request may be (RFC 7959 Section 2.9.3).
+            let max_size = encode_uint(u32::try_from(max_size).unwrap_or(u32::MAX));
+            msg.add_option(M::OptionNumber::new(SIZE1)?, &max_size)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the [`BlockwiseHandler`] responds with, as decided when the request was extracted.
+///
+/// This is only public because it is the handler's request data type.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct BlockwiseResponse(Prepared);
+
+#[derive(Debug)]
+enum Prepared {
+    /// Confirms a block of a request whose further blocks are expected.
+    Continue(BlockOption),
+    /// Sends (a block of) the stored response.
+    Stored {
+        /// The final block of the request, if it was sent block-wise.
+        block1: Option<BlockOption>,
+        /// The block the client asked for, if any.
+        block2: Option<BlockOption>,
+    },
+}
+
+/// A [`coap_handler::Handler`] wrapper that serves block-wise transfers ([RFC 7959]).
+///
+/// Requests sent in multiple blocks (using the Block1 option) are reassembled before they are
+/// passed on to the inner handler; responses that exceed a block size (512 bytes, or smaller if
+/// the client asks for it) are sent in blocks (using the Block2 option). The inner handler never
+/// sees block options, and may produce responses of up to `N` bytes of options and payload.
+///
+/// The handler keeps a single request and a single response in buffers of `N` bytes each, so only
+/// one block-wise transfer can be in progress at any time: A request that is not part of an
+/// ongoing Block1 transfer ends that transfer, and the blocks of a response are re-rendered if
+/// other requests were processed between them.
+///
+/// A request only continues a transfer if it has the same code and options (apart from the block
+/// options) as the request that started it, and comes from the same requester. As a handler does
+/// not learn who sent a request, the requester is reported by the function passed to
+/// [`with_requester()`][Self::with_requester]; with [`new()`][Self::new], all requests are assumed
+/// to come from the same requester.
+///
+/// When used with an [`OscoreEdhocHandler`][crate::OscoreEdhocHandler], this handler is wrapped
+/// *by* it (ie. it is passed in as its inner handler). That way, each block is protected
+/// individually (which RFC 8613 Section 4.1.3.4.1 calls inner block-wise transfer), and
+/// authorization is checked for each block.
+///
+/// [RFC 7959]: https://www.rfc-editor.org/rfc/rfc7959
+pub struct BlockwiseHandler<H, const N: usize, K = ()> {
+    inner: H,
+    /// Reports who sent the request that is being processed.
+    requester: fn() -> K,
+    /// Copy of the request without block options, or the part of it that has been received.
+    request: [u8; N],
+    request_code: u8,
+    request_length: usize,
+    request_payload_length: usize,
+    /// Requester whose blocks are being assembled in `request`, if any.
+    request_owner: Option<K>,
+    /// Response produced by the inner handler for the request in `request`.
+    response: [u8; N],
+    response_code: u8,
+    response_length: usize,
+    /// ETag derived from the response in `response`, sent along with its blocks.
+    response_etag: [u8; 4],
+    /// Requester the response in `response` was rendered for, if it is still valid.
+    response_owner: Option<K>,
+}
+
+impl<H: coap_handler::Handler, const N: usize> BlockwiseHandler<H, N> {
+    /// Wraps a handler to serve block-wise transfers.
+    ///
+    /// All requests are considered to come from the same requester; use
+    /// [`with_requester()`][Self::with_requester] where requests from different peers or security
+    /// contexts are served.
+    #[must_use]
+    pub fn new(inner: H) -> Self {
+        Self::with_requester(inner, || ())
+    }
+}
+
+impl<H: coap_handler::Handler, const N: usize, K: Clone + PartialEq> BlockwiseHandler<H, N, K> {
+    /// Wraps a handler to serve block-wise transfers, where a transfer is only continued by
+    /// requests for which `requester` returns the same value as for the request that started it.
+    ///
+    /// `requester` is called while a request is extracted; it typically reports the peer address
+    /// and the security context of the request the CoAP stack is processing.
+    #[must_use]
+    pub fn with_requester(inner: H, requester: fn() -> K) -> Self {
+        Self {
+            inner,
+            requester,
+            request: [0; N],
+            request_code: 0,
+            request_length: 0,
+            request_payload_length: 0,
+            request_owner: None,
+            response: [0; N],
+            response_code: 0,
+            response_length: 0,
+            response_etag: [0; 4],
+            response_owner: None,
+        }
+    }
+
+    /// Copies the request without its block options into `self.request`.
+    ///
+    /// This ends any transfer in progress, as the response was rendered for the request that is
+    /// replaced.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the request does not fit into the buffer.
+    fn copy_request<M: ReadableMessage>(&mut self, request: &M) -> Result<(), BlockwiseError> {
+        self.request_owner = None;
+        self.response_owner = None;
+        let mut copy = inmemory_write::Message::new(&mut self.request_code, &mut self.request);
+        copy.set_code(request.code().into());
+        for option in request.options() {
+            if !is_block_option(option.number()) {
+                copy.add_option(option.number(), option.value())
+                    .map_err(|_| BlockwiseError::TooLarge { max_size: N })?;
+            }
+        }
+        copy.set_payload(request.payload())
+            .map_err(|_| BlockwiseError::TooLarge { max_size: N })?;
+        self.request_length = copy.finish();
+        self.request_payload_length = request.payload().len();
+        Ok(())
+    }
+
+    /// Returns true if `request` has the code and, apart from the block options, the options of
+    /// the request in `self.request`.
+    fn is_stored_request<M: ReadableMessage>(&self, request: &M) -> bool {
+        let (stored, _) = self.request.split_at(self.request_length);
+        let stored = inmemory::Message::new(self.request_code, stored);
+        let code: u8 = request.code().into();
+        if code != self.request_code {
+            return false;
+        }
+        let mut stored_options = stored.options();
+        for option in request
+            .options()
+            .filter(|option| !is_block_option(option.number()))
+        {
+            match stored_options.next() {
+                Some(stored_option)
+                    if stored_option.number() == option.number()
+                        && stored_option.value() == option.value() => {}
+                _ => return false,
+            }
+        }
+        stored_options.next().is_none()
+    }
+
+    /// Appends a block's payload to the request in `self.request`.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the request does not fit into the buffer.
+    fn append_request(&mut self, payload: &[u8]) -> Result<(), BlockwiseError> {
+        let end = self.request_length + payload.len();
+        self.request
+            .get_mut(self.request_length..end)
+            .ok_or(BlockwiseError::TooLarge { max_size: N })?
+            .copy_from_slice(payload);
+        self.request_length = end;
+        self.request_payload_length += payload.len();
+        Ok(())
+    }
+
+    /// Runs the request in `self.request` through the inner handler, and stores the response in
+    /// `self.response`.
+    fn render_response(&mut self) {
+        let (request, _) = self.request.split_at(self.request_length);
+        let request = inmemory::Message::new(self.request_code, request);
+
+        let mut response =
+            inmemory_write::Message::new(&mut self.response_code, &mut self.response);
+        // FIXME rewind message: when building the response fails, the error is rendered into what
+        // was already written.
+        let rendered = match self.inner.extract_request_data(&request) {
+            Ok(extracted) => match self.inner.build_response(&mut response, extracted) {
+                Ok(()) => Ok(()),
+                Err(e) => e.render(&mut response).map_err(|_| ()),
+            },
+            Err(e) => e.render(&mut response).map_err(|_| ()),
+        };
+        if rendered.is_err() {
+            error!("Error could not be rendered into the response.");
+            response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+        }
+        self.response_length = response.finish();
+        let (response, _) = self.response.split_at(self.response_length);
+        self.response_etag = etag(self.response_code, response);
+    }
+
+    /// Returns the response stored in `self.response`.
+    fn stored_response(&self) -> inmemory::Message<'_> {
+        let (response, _) = self.response.split_at(self.response_length);
+        inmemory::Message::new(self.response_code, response)
+    }
+}
+
+impl<H: coap_handler::Handler, const N: usize, K: Clone + PartialEq> coap_handler::Handler
+    for BlockwiseHandler<H, N, K>
+{
+    type RequestData = BlockwiseResponse;
+    type ExtractRequestError = BlockwiseError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        let requester = (self.requester)();
+        let mut block1 = None;
+        let mut block2 = None;
+        for option in request.options() {
+            match option.number() {
+                BLOCK1 => {
+                    block1 =
+                        Some(BlockOption::parse(option.value()).ok_or(BlockwiseError::BadOption)?)
+                }
+                BLOCK2 => {
+                    block2 =
+                        Some(BlockOption::parse(option.value()).ok_or(BlockwiseError::BadOption)?)
+                }
+                _ => (),
+            }
+        }
+
+        match block1 {
+            Some(block1) if block1.num == 0 => {
+                self.copy_request(request)?;
+                if block1.more && request.payload().is_empty() {
+                    return Err(BlockwiseError::Incomplete);
+                }
+                self.request_owner = Some(requester.clone());
+            }
+            Some(block1) => {
+                if self.request_owner.as_ref() != Some(&requester)
+                    || !self.is_stored_request(request)
+                    || block1.offset() != self.request_payload_length
+                {
+                    debug!("Block1 request does not continue the current transfer.");
+                    return Err(BlockwiseError::Incomplete);
+                }
+                if let Err(e) = self.append_request(request.payload()) {
+                    self.request_owner = None;
+                    return Err(e);
+                }
+            }
+            None => {
+                if let Some(block2) = block2
+                    && block2.num != 0
+                    && self.response_owner.as_ref() == Some(&requester)
+                    && self.is_stored_request(request)
+                {
+                    if block2.offset() >= self.stored_response().payload().len() {
+                        return Err(BlockwiseError::BadOption);
+                    }
+                    return Ok(BlockwiseResponse(Prepared::Stored {
+                        block1: None,
+                        block2: Some(block2),
+                    }));
+                }
+                self.copy_request(request)?;
+            }
+        }
+
+        if let Some(block1) = block1 {
+            if block1.more {
+                return Ok(BlockwiseResponse(Prepared::Continue(block1)));
+            }
+            self.request_owner = None;
+        }
+
+        self.render_response();
+        self.response_owner = Some(requester);
+
+        if let Some(block2) = block2
+            && block2.num != 0
+            && block2.offset() >= self.stored_response().payload().len()
+        {
+            return Err(BlockwiseError::BadOption);
+        }
+
+        Ok(BlockwiseResponse(Prepared::Stored { block1, block2 }))
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        match request.0 {
+            Prepared::Continue(_) => MAX_ADDED_OPTIONS_LEN,
+            Prepared::Stored { .. } => self.response_length + MAX_ADDED_OPTIONS_LEN,
+        }
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        let (block1, block2) = match request.0 {
+            Prepared::Continue(block1) => {
+                response.set_code(M::Code::new(coap_numbers::code::CONTINUE)?);
+                response.add_option(M::OptionNumber::new(BLOCK1)?, &block1.encode())?;
+                return Ok(());
+            }
+            Prepared::Stored { block1, block2 } => (block1, block2),
+        };
+
+        let stored = self.stored_response();
+        let payload = stored.payload();
+
+        // Options to be added, in ascending order of their numbers.
+        let mut added = heapless::Vec::<(u16, heapless::Vec<u8, 4>), 4>::new();
+        let mut block_payload = payload;
+        let largest = BlockOption {
+            num: 0,
+            more: false,
+            szx: MAX_SZX,
+        };
+        if block2.is_some() || payload.len() > largest.size() {
+            // Lets the client tell apart the blocks of different responses, eg. when the resource
+            // changed between blocks (RFC 7959 Section 2.4).
+            if !stored.options().any(|option| option.number() == ETAG) {
+                let _ = added.push((ETAG, self.response_etag.iter().copied().collect()));
+            }
+
+            let requested = block2.unwrap_or(largest);
+            let szx = requested.szx.min(MAX_SZX);
+            // If the client asked for larger blocks than we send, its block number is scaled
+            // to our block size.
+            let num = u32::try_from(requested.offset() >> (4 + szx)).unwrap_or(u32::MAX);
+            let mut block = BlockOption {
+                num,
+                more: false,
+                szx,
+            };
+            let (_, tail) = payload.split_at(block.offset().min(payload.len()));
+            let (data, rest) = tail.split_at(block.size().min(tail.len()));
+            block.more = !rest.is_empty();
+            block_payload = data;
+
+            let _ = added.push((BLOCK2, block.encode()));
+            if let Some(block1) = block1 {
+                let _ = added.push((BLOCK1, block1.encode()));
+            }
+            if block.num == 0 {
+                // Tells the client the total size (RFC 7959 Section 4).
+                let size = encode_uint(u32::try_from(payload.len()).unwrap_or(u32::MAX));
+                let _ = added.push((SIZE2, size));
+            }
+        } else if let Some(block1) = block1 {
+            let _ = added.push((BLOCK1, block1.encode()));
+        }
+
+        response.set_code(M::Code::new(stored.code())?);
+        let mut added = added.iter().peekable();
+        for option in stored.options() {
+            while let Some((number, value)) =
+                added.next_if(|(number, _)| *number <= option.number())
+            {
+                response.add_option(M::OptionNumber::new(*number)?, value)?;
+            }
+            response.add_option(M::OptionNumber::new(option.number())?, option.value())?;
+        }
+        for (number, value) in added {
+            response.add_option(M::OptionNumber::new(*number)?, value)?;
+        }
+        response.set_payload(block_payload)?;
+        Ok(())
+    }
+}
+
+/// Derives an ETag from a rendered response, using the 32-bit FNV-1a hash of its code and bytes.
+///
+/// Unlike a counter, this gives the same ETag to blocks of a response that is rendered again for
+/// the same request, as long as the response did not change.
+fn etag(code: u8, response: &[u8]) -> [u8; 4] {
+    core::iter::once(&code)
+        .chain(response)
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+        })
+        .to_be_bytes()
+}
+
+/// Returns true for the options that are processed by the [`BlockwiseHandler`].
+fn is_block_option(number: u16) -> bool {
+    matches!(number, BLOCK1 | BLOCK2 | SIZE1 | SIZE2)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use std::vec::Vec;
+
+    use coap_handler::Handler as _;
+    use coap_message::{
+        Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+        ReadableMessage, error::RenderableOnMinimal as _,
+    };
+    use coap_message_implementations::{inmemory, inmemory_write};
+    use coap_numbers::{
+        code::{BAD_OPTION, CONTENT, CONTINUE, POST, REQUEST_ENTITY_INCOMPLETE},
+        option::{BLOCK1, BLOCK2, ETAG, SIZE2, URI_PATH},
+    };
+
+    use super::{BlockOption, BlockwiseError, BlockwiseHandler};
+
+    const N: usize = 2048;
+
+    std::thread_local! {
+        static REQUESTER: Cell<u8> = const { Cell::new(0) };
+    }
+
+    /// Records the payload of its requests, and responds with `response_len` bytes.
+    #[derive(Default)]
+    struct Inner {
+        response_len: usize,
+        request: Vec<u8>,
+    }
+
+    impl coap_handler::Handler for Inner {
+        type RequestData = ();
+        type ExtractRequestError = BlockwiseError;
+        type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+        fn extract_request_data<M: ReadableMessage>(
+            &mut self,
+            request: &M,
+        ) -> Result<(), BlockwiseError> {
+            self.request = request.payload().to_vec();
+            Ok(())
+        }
+
+        fn estimate_length(&mut self, (): &()) -> usize {
+            self.response_len + 16
+        }
+
+        fn build_response<M: MutableWritableMessage>(
+            &mut self,
+            response: &mut M,
+            (): (),
+        ) -> Result<(), M::UnionError> {
+            response.set_code(M::Code::new(CONTENT)?);
+            response.set_payload(&payload(self.response_len))?;
+            Ok(())
+        }
+    }
+
+    /// A response, as read back from the message built by the handler.
+    struct Response {
+        code: u8,
+        options: Vec<(u16, Vec<u8>)>,
+        payload: Vec<u8>,
+    }
+
+    impl Response {
+        fn option(&self, number: u16) -> Option<&[u8]> {
+            self.options
+                .iter()
+                .find(|(n, _)| *n == number)
+                .map(|(_, value)| value.as_slice())
+        }
+
+        fn block(&self, number: u16) -> Option<BlockOption> {
+            BlockOption::parse(self.option(number)?)
+        }
+    }
+
+    /// Returns a payload of `len` bytes, each depending on its position.
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| u8::try_from(i % 251).unwrap()).collect()
+    }
+
+    fn block(num: u32, more: bool, szx: u8) -> Vec<u8> {
+        BlockOption { num, more, szx }.encode().to_vec()
+    }
+
+    /// Sends a POST request with the given options (in ascending order) and payload through
+    /// `handler`.
+    fn exchange<K: Clone + PartialEq>(
+        handler: &mut BlockwiseHandler<Inner, N, K>,
+        options: &[(u16, &[u8])],
+        payload: &[u8],
+    ) -> Response {
+        let mut request_code = POST;
+        let mut request = [0; N];
+        let mut message = inmemory_write::Message::new(&mut request_code, &mut request);
+        for (number, value) in options {
+            message.add_option(*number, value).unwrap();
+        }
+        message.set_payload(payload).unwrap();
+        let len = message.finish();
+        let request = inmemory::Message::new(request_code, request.get(..len).unwrap());
+
+        let mut response_code = 0;
+        let mut response = [0; N];
+        let mut message = inmemory_write::Message::new(&mut response_code, &mut response);
+        match handler.extract_request_data(&request) {
+            Ok(extracted) => handler.build_response(&mut message, extracted).unwrap(),
+            Err(e) => e.render(&mut message).unwrap(),
+        }
+        let len = message.finish();
+        let response = inmemory::Message::new(response_code, response.get(..len).unwrap());
+
+        Response {
+            code: response_code,
+            options: response
+                .options()
+                .map(|option| (option.number(), option.value().to_vec()))
+                .collect(),
+            payload: response.payload().to_vec(),
+        }
+    }
+
+    #[test]
+    fn block_option() {
+        let block = BlockOption::parse(&[]).unwrap();
+        assert_eq!(
+            block,
+            BlockOption {
+                num: 0,
+                more: false,
+                szx: 0
+            }
+        );
+        assert!(block.encode().is_empty());
+
+        let block = BlockOption::parse(&[0x12, 0x34, 0x5d]).unwrap();
+        assert_eq!(
+            block,
+            BlockOption {
+                num: 0x12345,
+                more: true,
+                szx: 5
+            }
+        );
+        assert_eq!(block.encode().as_slice(), &[0x12, 0x34, 0x5d]);
+        assert_eq!(block.size(), 512);
+        assert_eq!(block.offset(), 0x12345 * 512);
+
+        let block = BlockOption::parse(&[0x3a]).unwrap();
+        assert_eq!(
+            block,
+            BlockOption {
+                num: 3,
+                more: true,
+                szx: 2
+            }
+        );
+        assert_eq!(block.encode().as_slice(), &[0x3a]);
+        assert_eq!(block.offset(), 192);
+
+        // Reserved size exponent, and values longer than 3 bytes.
+        assert_eq!(BlockOption::parse(&[0x07]), None);
+        assert_eq!(BlockOption::parse(&[0, 0, 0, 0x10]), None);
+    }
+
+    #[test]
+    fn block1_reassembly() {
+        let mut handler = BlockwiseHandler::<_, N>::new(Inner::default());
+        let body = payload(40);
+        let (first, rest) = body.split_at(16);
+        let (second, third) = rest.split_at(16);
+
+        let response = exchange(&mut handler, &[(BLOCK1, &block(0, true, 0))], first);
+        assert_eq!(response.code, CONTINUE);
+        assert_eq!(
+            response.block(BLOCK1),
+            Some(BlockOption::parse(&block(0, true, 0)).unwrap())
+        );
+
+        // A block that does not follow the received ones is rejected.
+        let response = exchange(&mut handler, &[(BLOCK1, &block(2, false, 0))], third);
+        assert_eq!(response.code, REQUEST_ENTITY_INCOMPLETE);
+
+        let response = exchange(&mut handler, &[(BLOCK1, &block(1, true, 0))], second);
+        assert_eq!(response.code, CONTINUE);
+        let response = exchange(&mut handler, &[(BLOCK1, &block(2, false, 0))], third);
+        assert_eq!(response.code, CONTENT);
+        assert_eq!(response.block(BLOCK1).map(|block| block.num), Some(2));
+        assert_eq!(handler.inner.request, body);
+    }
+
+    #[test]
+    fn block1_other_request() {
+        REQUESTER.set(1);
+        let mut handler =
+            BlockwiseHandler::<_, N, u8>::with_requester(Inner::default(), || REQUESTER.get());
+        let body = payload(32);
+        let (first, second) = body.split_at(16);
+
+        let response = exchange(
+            &mut handler,
+            &[(URI_PATH, b"a"), (BLOCK1, &block(0, true, 0))],
+            first,
+        );
+        assert_eq!(response.code, CONTINUE);
+
+        // Blocks from another requester, or with other options, do not continue the transfer.
+        REQUESTER.set(2);
+        let response = exchange(
+            &mut handler,
+            &[(URI_PATH, b"a"), (BLOCK1, &block(1, false, 0))],
+            second,
+        );
+        assert_eq!(response.code, REQUEST_ENTITY_INCOMPLETE);
+        REQUESTER.set(1);
+        let response = exchange(
+            &mut handler,
+            &[(URI_PATH, b"b"), (BLOCK1, &block(1, false, 0))],
+            second,
+        );
+        assert_eq!(response.code, REQUEST_ENTITY_INCOMPLETE);
+
+        let response = exchange(
+            &mut handler,
+            &[(URI_PATH, b"a"), (BLOCK1, &block(1, false, 0))],
+            second,
+        );
+        assert_eq!(response.code, CONTENT);
+        assert_eq!(handler.inner.request, body);
+    }
+
+    #[test]
+    fn block2_slicing() {
+        let mut handler = BlockwiseHandler::<_, N>::new(Inner {
+            response_len: 1500,
+            ..Inner::default()
+        });
+        let body = payload(1500);
+
+        // Without a Block2 option, the first block of the largest size is sent.
+        let first = exchange(&mut handler, &[], &[]);
+        assert_eq!(first.code, CONTENT);
+        assert_eq!(first.block(BLOCK2), BlockOption::parse(&block(0, true, 5)));
+        assert_eq!(first.option(SIZE2), Some(&[0x05, 0xdc][..]));
+        assert_eq!(first.payload, body.get(..512).unwrap());
+        let etag = first.option(ETAG).unwrap().to_vec();
+
+        // Smaller blocks are sent as requested.
+        let response = exchange(&mut handler, &[(BLOCK2, &block(3, false, 2))], &[]);
+        assert_eq!(
+            response.block(BLOCK2),
+            BlockOption::parse(&block(3, true, 2))
+        );
+        assert_eq!(response.payload, body.get(192..256).unwrap());
+        assert_eq!(response.option(ETAG), Some(etag.as_slice()));
+
+        // Larger blocks are rescaled to the largest size sent.
+        let response = exchange(&mut handler, &[(BLOCK2, &block(1, false, 6))], &[]);
+        assert_eq!(
+            response.block(BLOCK2),
+            BlockOption::parse(&block(2, false, 5))
+        );
+        assert_eq!(response.payload, body.get(1024..).unwrap());
+        assert_eq!(response.option(ETAG), Some(etag.as_slice()));
+
+        // Blocks beyond the end of the response are rejected.
+        let response = exchange(&mut handler, &[(BLOCK2, &block(3, false, 5))], &[]);
+        assert_eq!(response.code, BAD_OPTION);
+
+        // A changed response gets another ETag.
+        handler.inner.response_len = 1400;
+        let response = exchange(&mut handler, &[], &[]);
+        assert_ne!(response.option(ETAG), Some(etag.as_slice()));
+
+        // Responses that fit into a block are sent as they are.
+        handler.inner.response_len = 100;
+        let response = exchange(&mut handler, &[], &[]);
+        assert_eq!(response.block(BLOCK2), None);
+        assert_eq!(response.option(ETAG), None);
+        assert_eq!(response.payload, payload(100));
+    }
+}
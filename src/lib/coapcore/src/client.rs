@@ -0,0 +1,496 @@
+//! The client side of OSCORE/EDHOC.
+//!
+//! This module is independent of any CoAP stack: the [`OscoreEdhocClient`] writes the messages to
+//! send into any writable message, and processes responses from any readable message. Sending
+//! them is left to the caller.
+
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, OptionNumber as _, ReadableMessage,
+};
+use coap_message_implementations::{inmemory, inmemory_write};
+use defmt_or_log::{Debug2Format, debug, error, trace};
+
+use crate::helpers::COwn;
+
+/// Space allocated for the copies of messages that are processed by libOSCORE.
+///
+/// This matches the size used by the server side, see there for why copies are needed.
+const COPY_BUFFER_SIZE: usize = 1152;
+
+/// A copy of the OSCORE option.
+type OscoreOption = heapless::Vec<u8, 16>;
+
+/// Error type returned by the operations of an [`OscoreEdhocClient`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClientError {
+    /// The operation is not possible in the client's current stage, e.g., a request was to be
+    /// protected before the EDHOC exchange completed.
+    InvalidState,
+    /// The server's response is not a usable EDHOC or OSCORE response (e.g., it is an error
+    /// response, or was sent unprotected).
+    UnexpectedResponse,
+    /// The server did not authenticate with the configured peer credential.
+    PeerNotAuthenticated,
+    /// The EDHOC exchange failed.
+    Edhoc,
+    /// A message could not be protected or verified with OSCORE.
+    Oscore,
+    /// A message could not be written, typically because it exceeded the available buffers.
+    MessageTooLarge,
+}
+
+/// Correlation data of a request protected by [`OscoreEdhocClient::protect_request()`], which is
+/// needed to verify the response.
+///
+/// This deliberately is neither [`Clone`] nor [`Copy`]: it is used up by verifying a single
+/// response.
+pub struct RequestCorrelation(liboscore::raw::oscore_requestid_t);
+
+#[expect(
+    clippy::large_enum_variant,
+    reason = "the stages are not stored side by side, and the client is typically long-lived"
+)]
+enum ClientStage<Crypto: lakers::Crypto> {
+    Empty,
+    EdhocInitiatorSentM1 {
+        initiator: lakers::EdhocInitiatorWaitM2<Crypto>,
+        c_i: COwn,
+    },
+    /// Message 3 has been prepared, and is sent along with every OSCORE request until a response
+    /// shows that the server has processed it.
+    EdhocInitiatorSentM3 {
+        message_3: lakers::EdhocMessageBuffer,
+        context: liboscore::PrimitiveContext,
+    },
+    Oscore(liboscore::PrimitiveContext),
+}
+
+/// A CoAP client's security context for a single server, established through EDHOC and used with
+/// OSCORE.
+///
+/// The client takes the EDHOC initiator role, and authenticates itself with its own credential.
+/// It only accepts a server that authenticates with the configured peer credential.
+///
+/// A typical sequence of operations is:
+///
+/// * [`build_edhoc_request()`][Self::build_edhoc_request] writes a request carrying EDHOC message
+///   1, which is POSTed to the server's `/.well-known/edhoc` resource;
+/// * [`process_edhoc_response()`][Self::process_edhoc_response] processes the server's response,
+///   and derives the OSCORE security context;
+/// * [`protect_request()`][Self::protect_request] writes an OSCORE protected version of a request
+///   (which also carries EDHOC message 3 until the server has processed it); and
+/// * [`unprotect_response()`][Self::unprotect_response] verifies the server's response.
+///
+/// The last two steps are repeated for every request. When the server loses the security context
+/// (which typically shows by an unprotected error response), the client can be
+/// [reset][Self::reset] to start over.
+pub struct OscoreEdhocClient<Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto> {
+    own_credential: lakers::Credential,
+    own_key: lakers::BytesP256ElemLen,
+    peer_credential: lakers::Credential,
+    crypto_factory: CryptoFactory,
+    stage: ClientStage<Crypto>,
+}
+
+impl<Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto>
+    OscoreEdhocClient<Crypto, CryptoFactory>
+{
+    /// Creates a new client that authenticates with `own_credential` (for which it holds the
+    /// private key `own_key`), and expects the server to authenticate with `peer_credential`.
+    ///
+    /// The own credential is sent by reference, so the server needs to know it in advance (e.g.,
+    /// by configuring it as a known EDHOC credential).
+    ///
+    /// As in [`OscoreEdhocHandler::new()`][crate::OscoreEdhocHandler::new], `crypto_factory` is
+    /// used to pass in a platform specific implementation of the cryptographic primitives.
+    #[must_use]
+    pub fn new(
+        own_credential: lakers::Credential,
+        own_key: lakers::BytesP256ElemLen,
+        peer_credential: lakers::Credential,
+        crypto_factory: CryptoFactory,
+    ) -> Self {
+        Self {
+            own_credential,
+            own_key,
+            peer_credential,
+            crypto_factory,
+            stage: ClientStage::Empty,
+        }
+    }
+
+    /// Returns true if a security context is available, i.e., requests can be protected.
+    #[must_use]
+    pub fn has_context(&self) -> bool {
+        matches!(
+            self.stage,
+            ClientStage::EdhocInitiatorSentM3 { .. } | ClientStage::Oscore(_)
+        )
+    }
+
+    /// Discards any security context or ongoing EDHOC exchange.
+    pub fn reset(&mut self) {
+        self.stage = ClientStage::Empty;
+    }
+
+    /// Starts an EDHOC exchange, and writes the request carrying message 1 into `request`.
+    ///
+    /// Any previous security context is discarded.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if EDHOC message 1 can not be created, or if the request can not be
+    /// written.
+    pub fn build_edhoc_request<M: MinimalWritableMessage>(
+        &mut self,
+        request: &mut M,
+    ) -> Result<(), ClientError> {
+        self.reset();
+
+        // There is only a single security context, so any identifier will do.
+        let c_i = COwn::not_in_iter(core::iter::empty());
+
+        let (initiator, message_1) = lakers::EdhocInitiator::new(
+            (self.crypto_factory)(),
+            lakers::EDHOCMethod::StatStat,
+            lakers::EDHOCSuite::CipherSuite2,
+        )
+        .prepare_message_1(Some(c_i.into()), &None)
+        .map_err(edhoc_error)?;
+
+        // In the forward message flow, message 1 is prefixed with CBOR true (RFC9528 Appendix A.2).
+        let mut payload = heapless::Vec::<u8, { lakers::MAX_BUFFER_LEN + 1 }>::new();
+        payload
+            .push(0xf5)
+            .map_err(|_| ClientError::MessageTooLarge)?;
+        payload
+            .extend_from_slice(message_1.as_slice())
+            .map_err(|_| ClientError::MessageTooLarge)?;
+
+        request.set_code(
+            M::Code::new(coap_numbers::code::POST).map_err(|_| ClientError::MessageTooLarge)?,
+        );
+        add_option(request, coap_numbers::option::URI_PATH, b".well-known")?;
+        add_option(request, coap_numbers::option::URI_PATH, b"edhoc")?;
+        request
+            .set_payload(&payload)
+            .map_err(|_| ClientError::MessageTooLarge)?;
+
+        trace!("Sending EDHOC message 1");
+        self.stage = ClientStage::EdhocInitiatorSentM1 { initiator, c_i };
+
+        Ok(())
+    }
+
+    /// Processes the server's response to the request built in
+    /// [`Self::build_edhoc_request()`], and derives the OSCORE security context.
+    ///
+    /// EDHOC message 3 is not sent on its own, but along with the next protected request.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if no EDHOC exchange is ongoing, if the server responded with an error
+    /// or a malformed message, or if it did not authenticate with the configured peer credential.
+    /// In all those cases, the ongoing EDHOC exchange is discarded.
+    ///
+    /// # Panics
+    ///
+    /// This panics if cipher suite negotiation passed for a suite whose algorithms are unsupported
+    /// in libOSCORE.
+    pub fn process_edhoc_response<M: ReadableMessage>(
+        &mut self,
+        response: &M,
+    ) -> Result<(), ClientError> {
+        let ClientStage::EdhocInitiatorSentM1 { initiator, c_i } =
+            core::mem::replace(&mut self.stage, ClientStage::Empty)
+        else {
+            error!("Received EDHOC response without an ongoing EDHOC exchange.");
+            return Err(ClientError::InvalidState);
+        };
+
+        let code: u8 = response.code().into();
+        if code != coap_numbers::code::CHANGED {
+            error!("Server responded to EDHOC message 1 with code {}.", code);
+            return Err(ClientError::UnexpectedResponse);
+        }
+
+        let message_2 = lakers::EdhocMessageBuffer::new_from_slice(response.payload())
+            .map_err(|_| ClientError::MessageTooLarge)?;
+
+        let (mut initiator, c_r, id_cred_r, ead_2) =
+            initiator.parse_message_2(&message_2).map_err(edhoc_error)?;
+
+        if let Some(ead_2) = ead_2
+            && ead_2.is_critical
+        {
+            error!("Critical EAD2 item received, aborting");
+            return Err(ClientError::Edhoc);
+        }
+
+        if !is_presented(&self.peer_credential, &id_cred_r) {
+            error!("Server did not present the configured credential.");
+            return Err(ClientError::PeerNotAuthenticated);
+        }
+
+        #[expect(clippy::clone_on_copy, reason = "Lakers items are overly copy happy")]
+        initiator
+            .set_identity(self.own_key, self.own_credential.clone())
+            .map_err(edhoc_error)?;
+        #[expect(clippy::clone_on_copy, reason = "Lakers items are overly copy happy")]
+        let initiator = initiator
+            .verify_message_2(self.peer_credential.clone())
+            .map_err(edhoc_error)?;
+
+        // Sending our credential by reference: the server is expected to know us (and is not
+        // obliged to accept any other credential anyway).
+        let (initiator, message_3, _prk_out) = initiator
+            .prepare_message_3(lakers::CredentialTransfer::ByReference, &None)
+            .map_err(edhoc_error)?;
+        let mut initiator = initiator
+            .completed_without_message_4()
+            .map_err(edhoc_error)?;
+
+        let oscore_secret = initiator.edhoc_exporter(0u8, &[], 16); // label is 0
+        let oscore_salt = initiator.edhoc_exporter(1u8, &[], 8); // label is 1
+        let oscore_secret = &oscore_secret[..16];
+        let oscore_salt = &oscore_salt[..8];
+
+        // As the initiator, we send with the responder's identifier.
+        let sender_id = c_r.as_slice();
+        let recipient_id = c_i.as_slice();
+
+        // FIXME probe cipher suite
+        let hkdf = liboscore::HkdfAlg::from_number(crate::iana::cose_alg::HKDF_HMAC256256)
+            .expect("algorithm of the selected cipher suite is supported by libOSCORE");
+        let aead = liboscore::AeadAlg::from_number(crate::iana::cose_alg::AES_CCM_16_64_128)
+            .expect("algorithm of the selected cipher suite is supported by libOSCORE");
+
+        let immutables = liboscore::PrimitiveImmutables::derive(
+            hkdf,
+            oscore_secret,
+            oscore_salt,
+            None,
+            aead,
+            sender_id,
+            recipient_id,
+        )
+        .map_err(|_| {
+            error!("OSCORE context could not be derived.");
+            ClientError::Oscore
+        })?;
+
+        debug!(
+            "Established OSCORE context with recipient ID {:?} through EDHOC",
+            recipient_id
+        );
+        self.stage = ClientStage::EdhocInitiatorSentM3 {
+            message_3,
+            context: liboscore::PrimitiveContext::new_from_fresh_material(immutables),
+        };
+
+        Ok(())
+    }
+
+    /// Protects the `plaintext` request with OSCORE, and writes the result into `request`.
+    ///
+    /// The returned correlation data is needed to verify the response in
+    /// [`Self::unprotect_response()`].
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if no security context is available, or if the request does not fit
+    /// into the available buffers.
+    pub fn protect_request<P: ReadableMessage, M: MinimalWritableMessage>(
+        &mut self,
+        plaintext: &P,
+        request: &mut M,
+    ) -> Result<RequestCorrelation, ClientError> {
+        let (context, message_3) = match &mut self.stage {
+            ClientStage::EdhocInitiatorSentM3 { message_3, context } => {
+                (context, Some(&*message_3))
+            }
+            ClientStage::Oscore(context) => (context, None),
+            ClientStage::Empty | ClientStage::EdhocInitiatorSentM1 { .. } => {
+                error!("No security context to protect the request with.");
+                return Err(ClientError::InvalidState);
+            }
+        };
+
+        // See comment on COPY_BUFFER_SIZE
+        let mut buffer = [0u8; COPY_BUFFER_SIZE];
+        let mut code = 0;
+        let mut protected = inmemory_write::Message::new(&mut code, &mut buffer[..]);
+        let (correlation, copied) = liboscore::protect_request(&mut protected, context, |inner| {
+            inner.set_from_message(plaintext).is_ok()
+        })
+        .map_err(|_| {
+            error!("Request could not be protected.");
+            ClientError::Oscore
+        })?;
+        if !copied {
+            error!("Plaintext request does not fit into the protected message.");
+            return Err(ClientError::MessageTooLarge);
+        }
+        let length = protected.finish();
+        let (written, _) = buffer.split_at(length);
+        let protected = inmemory::Message::new(code, written);
+
+        request.set_code(M::Code::new(code).map_err(|_| ClientError::MessageTooLarge)?);
+        let mut edhoc_pending = message_3.is_some();
+        for opt in protected.options() {
+            if edhoc_pending && opt.number() > coap_numbers::option::EDHOC {
+                add_option(request, coap_numbers::option::EDHOC, b"")?;
+                edhoc_pending = false;
+            }
+            add_option(request, opt.number(), opt.value())?;
+        }
+        if edhoc_pending {
+            add_option(request, coap_numbers::option::EDHOC, b"")?;
+        }
+
+        let written = if let Some(message_3) = message_3 {
+            trace!("Sending EDHOC message 3 along with the OSCORE request");
+            // The EDHOC option indicates that the payload starts with message 3 (RFC9668).
+            let mut payload = heapless::Vec::<u8, COPY_BUFFER_SIZE>::new();
+            payload
+                .extend_from_slice(message_3.as_slice())
+                .and_then(|()| payload.extend_from_slice(protected.payload()))
+                .map_err(|_| ClientError::MessageTooLarge)?;
+            request.set_payload(&payload)
+        } else {
+            request.set_payload(protected.payload())
+        };
+        written.map_err(|_| ClientError::MessageTooLarge)?;
+
+        Ok(RequestCorrelation(correlation))
+    }
+
+    /// Verifies the `response` to a request protected in [`Self::protect_request()`], and writes
+    /// the decrypted response into `plaintext`.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if no security context is available, if the response is not protected
+    /// with OSCORE (e.g., because the server lost the security context), if verification fails, or
+    /// if the plaintext can not be written.
+    pub fn unprotect_response<R: ReadableMessage, M: MinimalWritableMessage>(
+        &mut self,
+        correlation: RequestCorrelation,
+        response: &R,
+        plaintext: &mut M,
+    ) -> Result<(), ClientError> {
+        let context = match &mut self.stage {
+            ClientStage::EdhocInitiatorSentM3 { context, .. } | ClientStage::Oscore(context) => {
+                context
+            }
+            ClientStage::Empty | ClientStage::EdhocInitiatorSentM1 { .. } => {
+                error!("No security context to verify the response with.");
+                return Err(ClientError::InvalidState);
+            }
+        };
+
+        // See comment on COPY_BUFFER_SIZE
+        let mut read_copy = [0u8; COPY_BUFFER_SIZE];
+        let mut code_copy = 0;
+        let mut copied_message = inmemory_write::Message::new(&mut code_copy, &mut read_copy[..]);
+        copied_message.set_code(response.code().into());
+        let mut oscore_option: Option<OscoreOption> = None;
+        for opt in response.options() {
+            if opt.number() == coap_numbers::option::OSCORE {
+                oscore_option = Some(opt.value().try_into().map_err(|_| {
+                    error!("OSCORE option is too long.");
+                    ClientError::UnexpectedResponse
+                })?);
+            }
+            copied_message
+                .add_option(opt.number(), opt.value())
+                .map_err(|_| ClientError::MessageTooLarge)?;
+        }
+        copied_message
+            .set_payload(response.payload())
+            .map_err(|_| ClientError::MessageTooLarge)?;
+
+        let Some(oscore_option) = oscore_option else {
+            let code: u8 = response.code().into();
+            error!("Response with code {} is not protected by OSCORE.", code);
+            return Err(ClientError::UnexpectedResponse);
+        };
+        let oscore_option = liboscore::OscoreOption::parse(&oscore_option).map_err(|_| {
+            error!("OSCORE option could not be parsed");
+            ClientError::UnexpectedResponse
+        })?;
+
+        let mut correlation = correlation.0;
+        let copied = liboscore::unprotect_response(
+            &mut copied_message,
+            context,
+            oscore_option,
+            &mut correlation,
+            |response| {
+                plaintext
+                    .set_from_message(response)
+                    .map_err(|e| error!("Response could not be copied: {:?}", Debug2Format(&e)))
+                    .is_ok()
+            },
+        )
+        .map_err(|_| {
+            error!("Response could not be verified.");
+            ClientError::Oscore
+        })?;
+
+        // A verified response shows that the server has processed message 3.
+        if matches!(self.stage, ClientStage::EdhocInitiatorSentM3 { .. })
+            && let ClientStage::EdhocInitiatorSentM3 { context, .. } =
+                core::mem::replace(&mut self.stage, ClientStage::Empty)
+        {
+            debug!("EDHOC exchange completed.");
+            self.stage = ClientStage::Oscore(context);
+        }
+
+        if copied {
+            Ok(())
+        } else {
+            Err(ClientError::MessageTooLarge)
+        }
+    }
+}
+
+/// Returns true if `id_cred` (as received from the peer) refers to `credential`.
+fn is_presented(credential: &lakers::Credential, id_cred: &lakers::IdCred) -> bool {
+    // ad Ok: If the credential has no KID (or can not be sent by value), it can't be recognized.
+    if id_cred.reference_only() {
+        credential.by_kid().as_ref() == Ok(id_cred)
+    } else {
+        credential.by_value().as_ref() == Ok(id_cred)
+    }
+}
+
+/// Adds an option to a message whose option number type is not known to be `u16`.
+///
+/// # Errors
+///
+/// This produces errors if the message can not represent the option number, or the option does
+/// not fit into the message.
+fn add_option<M: MinimalWritableMessage>(
+    message: &mut M,
+    number: u16,
+    value: &[u8],
+) -> Result<(), ClientError> {
+    let number = M::OptionNumber::new(number).map_err(|_| ClientError::MessageTooLarge)?;
+    message
+        .add_option(number, value)
+        .map_err(|_| ClientError::MessageTooLarge)
+}
+
+/// Logs a [`lakers::EDHOCError`] and converts it into a [`ClientError`].
+#[track_caller]
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "ergonomics at the call sites need this"
+)]
+fn edhoc_error(e: lakers::EDHOCError) -> ClientError {
+    error!("Lakers error: {:?}", Debug2Format(&e));
+    ClientError::Edhoc
+}
@@ -1,9 +1,10 @@
 //! A CoAP security tool for embedded devices, supporting OSCORE/EDHOC and managing credentials.
 //!
-//! This crate is under active development; breaking changes will be made as necessary. It
-//! currently only handles the server side of CoAP exchanges. At runtime, there is more copying of
-//! messages than is generally preferred; those result from limitations of underlying tools and are
-//! being addressed there.
+//! This crate is under active development; breaking changes will be made as necessary. It mainly
+//! handles the server side of CoAP exchanges; on the client side, it can establish and use an
+//! OSCORE context with a single server through EDHOC. At runtime, there is more copying of messages
+//! than is generally preferred; those result from limitations of underlying tools and are being
+//! addressed there.
 //!
 //! This crate builds on several components technically and logically:
 //!
@@ -27,6 +28,19 @@
 //!
 //! The arguments passed to the [`OscoreEdhocHandler`] at construction guide its behavior.
 //!
+//! Applications that exchange representations larger than a single message wrap their handler in
+//! a [`BlockwiseHandler`] before passing it to the [`OscoreEdhocHandler`], which then protects
+//! every block on its own.
+//!
+//! CoAP stacks that support observations ([RFC 7641](https://www.rfc-editor.org/rfc/rfc7641)) take
+//! an [`OscoreObservation`] from the [`OscoreEdhocHandler`] after every response to a protected
+//! registration, and build the notifications through it.
+//!
+//! On the client side, an [`OscoreEdhocClient`] is created with the credential the server is
+//! expected to present. It does not send messages on its own, but builds the requests to be sent
+//! through a CoAP stack, and processes the responses: First, it runs EDHOC as the initiator, then
+//! it protects requests and verifies their responses with the resulting OSCORE context.
+//!
 //! # Logging
 //!
 //! Extensive logging is available in this crate through [`defmt_or_log`], depending on features
@@ -79,5 +93,11 @@ pub mod oluru;
 mod seccontext;
 pub use seccontext::*;
 
+mod client;
+pub use client::{ClientError, OscoreEdhocClient, RequestCorrelation};
+
+mod blockwise;
+pub use blockwise::{BlockOption, BlockwiseError, BlockwiseHandler};
+
 mod error;
 pub use error::{CredentialError, CredentialErrorDetail as CredentialErrorKind};
@@ -52,6 +52,44 @@ type SecContextPool<Crypto, Claims> =
 /// A copy of the OSCORE option.
 type OscoreOption = heapless::Vec<u8, 16>;
 
+/// Space allocated for the decrypted request of an observation.
+///
+/// Registrations through larger requests are served, but not registered.
+const MAX_OBSERVED_REQUEST_LEN: usize = 128;
+
+/// Code, and options and payload of a decrypted request that registers an observation.
+type ObservedRequest = (u8, heapless::Vec<u8, MAX_OBSERVED_REQUEST_LEN>);
+
+/// An observation ([RFC 7641]) registered through an OSCORE protected request.
+///
+/// This is created by the [`OscoreEdhocHandler`] when it builds a successful response to a
+/// registration, and taken from there by the CoAP stack through
+/// [`take_observation()`][OscoreEdhocHandler::take_observation]. The stack keeps it for as long as
+/// the observation lasts, and has every notification built through
+/// [`build_notification()`][OscoreEdhocHandler::build_notification].
+///
+/// It holds the decrypted request (which is evaluated again for every notification), and the
+/// request's correlation data: every notification is protected with a new Partial IV from the
+/// server's sender sequence number, as required by [RFC 8613 Section 4.1.3.5.2].
+///
+/// [RFC 7641]: https://www.rfc-editor.org/rfc/rfc7641
+/// [RFC 8613 Section 4.1.3.5.2]: https://www.rfc-editor.org/rfc/rfc8613#section-4.1.3.5.2
+pub struct OscoreObservation {
+    kid: COwn,
+    correlation: liboscore::raw::oscore_requestid_t,
+    code: u8,
+    request: heapless::Vec<u8, MAX_OBSERVED_REQUEST_LEN>,
+}
+
+impl OscoreObservation {
+    /// Returns the decrypted request that registered the observation.
+    ///
+    /// This is what the stack uses to find which observations are affected by a change.
+    pub fn request(&self) -> impl ReadableMessage + '_ {
+        coap_message_implementations::inmemory::Message::new(self.code, &self.request)
+    }
+}
+
 struct SecContextState<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> {
     // FIXME: Updating this should also check the timeout.
 
@@ -190,6 +228,9 @@ pub struct OscoreEdhocHandler<
 
     crypto_factory: CryptoFactory,
     rng: RNG,
+
+    /// Observation registered by the response built last, until it is taken.
+    registered: Option<OscoreObservation>,
 }
 
 impl<
@@ -230,9 +271,106 @@ impl<
             authorities,
             rng,
             time,
+            registered: None,
         }
     }
 
+    /// Takes the observation that was registered by the response built last.
+    ///
+    /// A CoAP stack supporting observations calls this after it built the response to every
+    /// request that contains an Observe option (and after which that response contains an Observe
+    /// option too). Responses to OSCORE protected requests only register an observation if the
+    /// decrypted request is a registration (a GET or FETCH request with the Observe option set to
+    /// 0), and the inner handler built its response successfully.
+    ///
+    /// Only OSCORE protected observations are registered here: observations through unprotected
+    /// requests can be served by evaluating the request again through the handler.
+    pub fn take_observation(&mut self) -> Option<OscoreObservation> {
+        self.registered.take()
+    }
+
+    /// Builds a notification for an `observation` into `response`.
+    ///
+    /// The decrypted request is evaluated again by the inner handler (after checking again that
+    /// the security context's authorization allows it), and the resulting response is protected
+    /// with a new Partial IV.
+    ///
+    /// Returns `Some(true)` if the inner response was built successfully, and `Some(false)` if it
+    /// is an error (after which the observation ends, see [RFC 7641 Section 4.2]). If the security
+    /// context is gone or its authorization expired, nothing is written, and `None` is returned;
+    /// the observation ends without notification, as the client can not verify any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the writable message is not a
+    /// [`coap_message_implementations::inmemory_write::Message`]. See module level documentation
+    /// for details.
+    ///
+    /// [RFC 7641 Section 4.2]: https://www.rfc-editor.org/rfc/rfc7641#section-4.2
+    pub fn build_notification<M: MutableWritableMessage>(
+        &mut self,
+        observation: &mut OscoreObservation,
+        response: &mut M,
+    ) -> Option<bool> {
+        let request = coap_message_implementations::inmemory::Message::new(
+            observation.code,
+            &observation.request,
+        );
+        let correlation = &mut observation.correlation;
+
+        self.pool
+            .lookup(
+                |c| c.corresponding_cown() == Some(observation.kid),
+                |matched| {
+                    let SecContextState {
+                        protocol_stage: SecContextStage::Oscore(oscore_context),
+                        authorization: Some(authorization),
+                    } = matched
+                    else {
+                        debug!("Security context of an observation vanished.");
+                        return None;
+                    };
+
+                    if !authorization
+                        .time_constraint()
+                        .is_valid_with(&mut self.time)
+                    {
+                        debug!("Ending observation of expired context.");
+                        return None;
+                    }
+
+                    let extracted = if authorization.scope().request_is_allowed(&request) {
+                        AuthorizationChecked::Allowed(self.inner.extract_request_data(&request))
+                    } else {
+                        AuthorizationChecked::NotAllowed
+                    };
+
+                    let response = coap_message_implementations::inmemory_write::Message::downcast_from(response)
+                        .expect("OSCORE handler currently requires a response message implementation that is of fixed type");
+                    response.set_code(coap_numbers::code::CONTENT);
+
+                    let mut succeeded = false;
+                    // As the correlation data was used for the response to the registration
+                    // already, this takes a new Partial IV.
+                    if liboscore::protect_response(response, oscore_context, correlation, |response| {
+                        succeeded = build_inner_response(
+                            &mut self.inner,
+                            &self.authorities,
+                            response,
+                            extracted,
+                        );
+                    })
+                    .is_err()
+                    {
+                        error!("Notification could not be protected.");
+                        return None;
+                    }
+                    Some(succeeded)
+                },
+            )
+            .flatten()
+    }
+
     /// Produces a [`COwn`] (as a recipient identifier) that is both available and not equal to the
     /// peer's recipient identifier.
     fn cown_but_not(&self, c_peer: &[u8]) -> COwn {
@@ -536,11 +674,12 @@ impl<
             oscore_option,
             &mut oscore_context,
             |request| {
-                if authorization.scope().request_is_allowed(request) {
+                let extracted = if authorization.scope().request_is_allowed(request) {
                     AuthorizationChecked::Allowed(self.inner.extract_request_data(request))
                 } else {
                     AuthorizationChecked::NotAllowed
-                }
+                };
+                (extracted, observed_request(request))
             },
         );
 
@@ -564,7 +703,7 @@ impl<
             "A Default (Empty) was placed when an item was taken, which should have the lowest priority"
         );
 
-        let Ok((correlation, extracted)) = decrypted else {
+        let Ok((correlation, (extracted, observe))) = decrypted else {
             // FIXME is that the right code?
             error!("Decryption failure");
             return Err(CoAPError::unauthorized());
@@ -574,6 +713,7 @@ impl<
             kid,
             correlation,
             extracted,
+            observe,
         })
     }
 
@@ -711,6 +851,10 @@ impl<
     /// Builds an OSCORE response message after successful processing of a request in
     /// [`Self::extract_oscore_edhoc()`].
     ///
+    /// If the decrypted request registers an observation (`observe`), the response is protected
+    /// with a Partial IV of its own (as are all notifications), and on success, the observation is
+    /// stored until it is [taken][Self::take_observation].
+    ///
     /// # Errors
     ///
     /// This produces errors if requests are processed in unexpected out-of-order ways.
@@ -726,8 +870,22 @@ impl<
         kid: COwn,
         mut correlation: liboscore::raw::oscore_requestid_t,
         extracted: AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>,
+        observe: Option<ObservedRequest>,
     ) -> Result<(), Result<CoAPError, M::UnionError>> {
-        response.set_code(M::Code::new(coap_numbers::code::CHANGED).map_err(|x| Err(x.into()))?);
+        // Responses to registrations are notifications (RFC 8613 Section 4.2).
+        let outer_code = if observe.is_some() {
+            coap_numbers::code::CONTENT
+        } else {
+            coap_numbers::code::CHANGED
+        };
+        response.set_code(M::Code::new(outer_code).map_err(|x| Err(x.into()))?);
+
+        if observe.is_some() {
+            // All notifications, including the response to the registration, carry a Partial IV
+            // of their own (RFC 8613 Section 4.1.3.5.2); libOSCORE only takes one from the sender
+            // sequence number if the correlation data was used before.
+            correlation.is_first_use = false;
+        }
 
         // BIG FIXME: We have currently no way to rewind through a message once we've started
         // building it.
@@ -743,7 +901,7 @@ impl<
         // async and the handler has a method to start writing to the message (which kind'a
         // implies rewinding)
 
-        self.pool
+        let succeeded = self.pool
                     .lookup(|c| c.corresponding_cown() == Some(kid), |matched| {
                         // Not checking authorization any more: we don't even have access to the
                         // request any more, that check was done.
@@ -759,8 +917,9 @@ impl<
                         let response = coap_message_implementations::inmemory_write::Message::downcast_from(response)
                             .expect("OSCORE handler currently requires a response message implementation that is of fixed type");
 
-                        response.set_code(coap_numbers::code::CHANGED);
+                        response.set_code(outer_code);
 
+                        let mut succeeded = false;
                         if liboscore::protect_response(
                             response,
                             // SECURITY BIG FIXME: How do we make sure that our correlation is really for
@@ -769,58 +928,13 @@ impl<
                             // should be a tie; carry the OSCORE context in an owned way?).
                             oscore_context,
                             &mut correlation,
-                            |response| match extracted {
-                                AuthorizationChecked::Allowed(Ok(extracted)) => match self.inner.build_response(response, extracted) {
-                                    Ok(()) => {
-                                        // All fine, response was built
-                                    },
-                                    // One attempt to render rendering errors
-                                    // FIXME rewind message
-                                    Err(e) => {
-                                        error!("Rendering successful extraction failed with {:?}", Debug2Format(&e));
-                                        match e.render(response) {
-                                            Ok(()) => {
-                                                error!("Error rendered.");
-                                            },
-                                            Err(e2) => {
-                                                error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
-                                                // FIXME rewind message
-                                                response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
-                                            }
-                                        }
-                                    },
-                                },
-                                AuthorizationChecked::Allowed(Err(inner_request_error)) => {
-                                    error!("Extraction failed with {:?}.", Debug2Format(&inner_request_error));
-                                    match inner_request_error.render(response) {
-                                        Ok(()) => {
-                                            error!("Original error rendered successfully.");
-                                        },
-                                        Err(e) => {
-                                            error!("Original error could not be rendered due to {:?}:", Debug2Format(&e));
-                                            // Two attempts to render extraction errors
-                                            // FIXME rewind message
-                                            match e.render(response) {
-                                                Ok(()) => {
-                                                    error!("Error was rendered fine.");
-                                                },
-                                                Err(e2) => {
-                                                    error!("Rendering error caused {:?}.", Debug2Format(&e2));
-                                                    // FIXME rewind message
-                                                    response.set_code(
-                                                        coap_numbers::code::INTERNAL_SERVER_ERROR,
-                                                    );
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                AuthorizationChecked::NotAllowed => {
-                                    if self.authorities.render_not_allowed(response).is_err() {
-                                        // FIXME rewind message
-                                        response.set_code(coap_numbers::code::UNAUTHORIZED);
-                                    }
-                                }
+                            |response| {
+                                succeeded = build_inner_response(
+                                    &mut self.inner,
+                                    &self.authorities,
+                                    response,
+                                    extracted,
+                                );
                             },
                         )
                         .is_err()
@@ -828,9 +942,20 @@ impl<
                             error!("Oups, responding with weird state");
                             // todo!("Thanks to the protect API we've lost access to our response");
                         }
-                        Ok(())
+                        Ok(succeeded)
                     })
                 .transpose().map_err(Ok)?;
+
+        if succeeded == Some(true)
+            && let Some((code, request)) = observe
+        {
+            self.registered = Some(OscoreObservation {
+                kid,
+                correlation,
+                code,
+                request,
+            });
+        }
         Ok(())
     }
 
@@ -910,10 +1035,128 @@ pub enum OwnRequestData<I> {
         kid: COwn,
         correlation: liboscore::raw::oscore_requestid_t,
         extracted: AuthorizationChecked<I>,
+        /// The decrypted request, if it registers an observation.
+        observe: Option<ObservedRequest>,
     },
     ProcessedToken(crate::ace::AceCborAuthzInfoResponse),
 }
 
+/// Builds the response of an inner handler into an OSCORE protected message's plaintext, rendering
+/// any errors into it.
+///
+/// Returns true if the inner handler built its response successfully.
+fn build_inner_response<
+    H: coap_handler::Handler,
+    SSC: ServerSecurityConfig,
+    M: MutableWritableMessage,
+>(
+    inner: &mut H,
+    authorities: &SSC,
+    response: &mut M,
+    extracted: AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>,
+) -> bool {
+    let internal_server_error = |response: &mut M| {
+        if let Ok(code) = M::Code::new(coap_numbers::code::INTERNAL_SERVER_ERROR) {
+            response.set_code(code);
+        }
+    };
+
+    match extracted {
+        AuthorizationChecked::Allowed(Ok(extracted)) => {
+            match inner.build_response(response, extracted) {
+                Ok(()) => {
+                    // All fine, response was built
+                    return true;
+                }
+                // One attempt to render rendering errors
+                // FIXME rewind message
+                Err(e) => {
+                    error!(
+                        "Rendering successful extraction failed with {:?}",
+                        Debug2Format(&e)
+                    );
+                    match e.render(response) {
+                        Ok(()) => {
+                            error!("Error rendered.");
+                        }
+                        Err(e2) => {
+                            error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
+                            // FIXME rewind message
+                            internal_server_error(response);
+                        }
+                    }
+                }
+            }
+        }
+        AuthorizationChecked::Allowed(Err(inner_request_error)) => {
+            error!(
+                "Extraction failed with {:?}.",
+                Debug2Format(&inner_request_error)
+            );
+            match inner_request_error.render(response) {
+                Ok(()) => {
+                    error!("Original error rendered successfully.");
+                }
+                Err(e) => {
+                    error!(
+                        "Original error could not be rendered due to {:?}:",
+                        Debug2Format(&e)
+                    );
+                    // Two attempts to render extraction errors
+                    // FIXME rewind message
+                    match e.render(response) {
+                        Ok(()) => {
+                            error!("Error was rendered fine.");
+                        }
+                        Err(e2) => {
+                            error!("Rendering error caused {:?}.", Debug2Format(&e2));
+                            // FIXME rewind message
+                            internal_server_error(response);
+                        }
+                    }
+                }
+            }
+        }
+        AuthorizationChecked::NotAllowed => {
+            if authorities.render_not_allowed(response).is_err()
+                && let Ok(code) = M::Code::new(coap_numbers::code::UNAUTHORIZED)
+            {
+                // FIXME rewind message
+                response.set_code(code);
+            }
+        }
+    }
+    false
+}
+
+/// Copies a decrypted request if it registers an observation, i.e. if it is a GET or FETCH request
+/// with the Observe option set to 0 ([RFC 7641 Section 2]).
+///
+/// [RFC 7641 Section 2]: https://www.rfc-editor.org/rfc/rfc7641#section-2
+fn observed_request<M: ReadableMessage>(request: &M) -> Option<ObservedRequest> {
+    let code: u8 = request.code().into();
+    if code != coap_numbers::code::GET && code != coap_numbers::code::FETCH {
+        return None;
+    }
+    if !request
+        .options()
+        .any(|o| o.number() == coap_numbers::option::OBSERVE && o.value().is_empty())
+    {
+        return None;
+    }
+
+    let mut buffer = [0u8; MAX_OBSERVED_REQUEST_LEN];
+    let mut copied_code = 0;
+    let mut copy = coap_message_implementations::inmemory_write::Message::new(
+        &mut copied_code,
+        &mut buffer[..],
+    );
+    copy.set_from_message(request).ok()?;
+    let length = copy.finish();
+    let copied = heapless::Vec::from_slice(buffer.get(..length)?).ok()?;
+    Some((copied_code, copied))
+}
+
 // FIXME: It'd be tempting to implement Drop for Response to set the slot back to Empty -- but
 // that'd be easier if we could avoid the Drop during enum destructuring, which AIU is currently
 // not supported in match or let destructuring. (But our is_gc_eligible should be good enough
@@ -1224,6 +1467,9 @@ impl<
     ) -> Result<(), Self::BuildResponseError<M>> {
         use OrInner::{Inner, Own};
 
+        // Registrations are only taken right after the response that made them.
+        self.registered = None;
+
         match req {
             Own(OwnRequestData::EdhocOkSend2(c_r)) => {
                 if !SSC::HAS_EDHOC {
@@ -1241,11 +1487,12 @@ impl<
                 kid,
                 correlation,
                 extracted,
+                observe,
             }) => {
                 if !has_oscore::<SSC>() {
                     unreachable!("State is not constructed");
                 }
-                self.build_oscore_response(response, kid, correlation, extracted)
+                self.build_oscore_response(response, kid, correlation, extracted, observe)
                     .map_err(Own)?;
             }
             Inner(AuthorizationChecked::Allowed(i)) => {
@@ -0,0 +1,133 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    log::info,
+    storage,
+};
+use serde::{Deserialize, Serialize};
+
+/// Key of the number of the last run that has started updating the stored values.
+const RUN_KEY: &str = "run";
+/// Keys of the values updated together by a transaction, to the number of the run.
+const TRANSACTION_KEYS: [&str; 2] = ["transaction/a", "transaction/b"];
+/// Key of a blob spanning many chunks, whose content depends on the number of the run.
+const BLOB_KEY: &str = "blob";
+/// Maximum length of the blob.
+const BLOB_MAX_LEN: usize = 3 * 1024;
+
+/// Number of a run, stored along with its complement to detect values written partially.
+#[derive(Debug, Serialize, Deserialize)]
+struct Run {
+    number: u32,
+    complement: u32,
+}
+
+impl Run {
+    fn new(number: u32) -> Self {
+        Self {
+            number,
+            complement: !number,
+        }
+    }
+}
+
+/// Returns the length of the blob written by the given run, varying its number of chunks.
+fn blob_len(run: u32) -> usize {
+    BLOB_MAX_LEN - (run % 8) as usize * 128
+}
+
+/// Returns the byte at `index` of the blob written by the given run.
+#[expect(clippy::cast_possible_truncation)]
+fn blob_byte(run: u32, index: usize) -> u8 {
+    (index as u32).wrapping_mul(31).wrapping_add(run) as u8
+}
+
+/// Returns whether `data` is the blob written by the given run.
+fn is_blob_of(data: &[u8], run: u32) -> bool {
+    data.len() == blob_len(run)
+        && data
+            .iter()
+            .enumerate()
+            .all(|(index, byte)| *byte == blob_byte(run, index))
+}
+
+#[ariel_os::task(autostart)]
+async fn main() {
+    // A value interrupted while being written keeps its previous value.
+    let run = match storage::get::<Run>(RUN_KEY).await.unwrap() {
+        Some(run) => {
+            assert_eq!(run.complement, !run.number, "run value written partially");
+            run.number
+        }
+        None => 0,
+    };
+
+    // A transaction interrupted while being committed is completed during initialization, or
+    // not applied at all.
+    let [first, second] = TRANSACTION_KEYS;
+    let transaction_run = storage::get::<u32>(first).await.unwrap().unwrap_or(0);
+    assert_eq!(
+        storage::get::<u32>(second).await.unwrap().unwrap_or(0),
+        transaction_run,
+        "transaction applied partially"
+    );
+    assert!(
+        transaction_run == run || transaction_run + 1 == run,
+        "unexpected transaction of run {transaction_run} after run {run}"
+    );
+
+    // A blob interrupted while being written keeps its previous value.
+    let mut buffer = [0; BLOB_MAX_LEN];
+    match storage::get_blob(BLOB_KEY, &mut buffer).await.unwrap() {
+        Some(len) => {
+            let blob = buffer.get(..len).unwrap();
+            assert!(
+                is_blob_of(blob, run)
+                    || run
+                        .checked_sub(1)
+                        .is_some_and(|previous| is_blob_of(blob, previous)),
+                "unexpected blob after run {run}"
+            );
+        }
+        None => assert!(run <= 1, "blob lost after run {run}"),
+    }
+
+    info!("storage-power-loss: checked the values of run {}", run);
+
+    let run = run + 1;
+    storage::insert(RUN_KEY, Run::new(run)).await.unwrap();
+
+    let mut transaction = storage::Transaction::new();
+    for key in TRANSACTION_KEYS {
+        transaction.insert(key, run).unwrap();
+    }
+    storage::commit(&transaction).await.unwrap();
+
+    let blob = buffer.get_mut(..blob_len(run)).unwrap();
+    for (index, byte) in blob.iter_mut().enumerate() {
+        *byte = blob_byte(run, index);
+    }
+    storage::insert_blob(BLOB_KEY, blob).await.unwrap();
+
+    let len = storage::get_blob(BLOB_KEY, &mut buffer).await.unwrap();
+    assert!(
+        len.and_then(|len| buffer.get(..len))
+            .is_some_and(|blob| is_blob_of(blob, run)),
+        "blob of run {run} not read back"
+    );
+
+    // The chunks of the previous value, and those of writes interrupted by power loss, have been
+    // removed: only the current chunks remain, with less than half of their length in overhead.
+    let stats = storage::stats().await.unwrap();
+    let live = usize::try_from(stats.used - stats.erasable).unwrap();
+    assert!(
+        live < blob_len(run) + blob_len(run) / 2,
+        "stale blob chunks left: {live} bytes of items for a blob of {} bytes",
+        blob_len(run)
+    );
+
+    info!("storage-power-loss: updated the values for run {}", run);
+    exit(ExitCode::SUCCESS);
+}
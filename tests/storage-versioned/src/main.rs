@@ -0,0 +1,137 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    log::info,
+    storage::{self, Error, SchemaTag, Versioned},
+};
+use serde::{Deserialize, Serialize};
+
+/// First schema version of [`Config`].
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ConfigV1 {
+    interval: u32,
+}
+
+impl Versioned for ConfigV1 {
+    const SCHEMA_ID: u32 = 0xc0f1;
+    const SCHEMA_VERSION: u16 = 1;
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    interval: u32,
+    enabled: bool,
+}
+
+impl Versioned for Config {
+    const SCHEMA_ID: u32 = 0xc0f1;
+    const SCHEMA_VERSION: u16 = 2;
+
+    fn migrate(from_version: u16, bytes: &[u8]) -> Option<Self> {
+        match from_version {
+            // Stored as a plain interval before the type was versioned.
+            0 => Some(Self {
+                interval: postcard::from_bytes(bytes).ok()?,
+                enabled: false,
+            }),
+            1 => {
+                let old: ConfigV1 = postcard::from_bytes(bytes).ok()?;
+                Some(Self {
+                    interval: old.interval,
+                    enabled: true,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Another type, without migrations.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Other {
+    value: u32,
+}
+
+impl Versioned for Other {
+    const SCHEMA_ID: u32 = 0x07e5;
+    const SCHEMA_VERSION: u16 = 1;
+}
+
+/// Returns the tags of the [`Error::SchemaMismatch`] returned by a read, if any.
+fn mismatch<V, E>(result: Result<Option<V>, Error<E>>) -> Option<(SchemaTag, SchemaTag)> {
+    match result {
+        Err(Error::SchemaMismatch { expected, found }) => Some((expected, found)),
+        _ => None,
+    }
+}
+
+#[ariel_os::task(autostart)]
+async fn main() {
+    // An untagged value is migrated from version 0, and written back tagged.
+    storage::insert("untagged", 10_u32).await.unwrap();
+    assert_eq!(
+        storage::get_versioned::<Config>("untagged").await.unwrap(),
+        Some(Config {
+            interval: 10,
+            enabled: false,
+        })
+    );
+    assert_eq!(
+        mismatch(storage::get::<u32>("untagged").await),
+        Some((SchemaTag::UNTAGGED, SchemaTag::of::<Config>()))
+    );
+
+    // A value of an older schema version is migrated, and written back with the current one.
+    storage::insert_versioned("versioned", ConfigV1 { interval: 20 })
+        .await
+        .unwrap();
+    assert_eq!(
+        storage::get_versioned::<Config>("versioned").await.unwrap(),
+        Some(Config {
+            interval: 20,
+            enabled: true,
+        })
+    );
+    assert_eq!(
+        mismatch(storage::get_versioned::<ConfigV1>("versioned").await),
+        Some((SchemaTag::of::<ConfigV1>(), SchemaTag::of::<Config>()))
+    );
+    assert_eq!(
+        storage::get_versioned::<Config>("versioned").await.unwrap(),
+        Some(Config {
+            interval: 20,
+            enabled: true,
+        })
+    );
+
+    // A value of another type is detected, whether read as versioned or untagged.
+    storage::insert_versioned("other", Other { value: 30 })
+        .await
+        .unwrap();
+    assert_eq!(
+        mismatch(storage::get_versioned::<Config>("other").await),
+        Some((SchemaTag::of::<Config>(), SchemaTag::of::<Other>()))
+    );
+    assert_eq!(
+        mismatch(storage::get::<u32>("other").await),
+        Some((SchemaTag::UNTAGGED, SchemaTag::of::<Other>()))
+    );
+    assert_eq!(
+        storage::get_versioned::<Other>("other").await.unwrap(),
+        Some(Other { value: 30 })
+    );
+
+    // An untagged value starting like a tag is read back unchanged.
+    let tag_like = [0xff, b'T', b'A', b'G', 0x01, 0x02];
+    storage::insert("tag-like", tag_like).await.unwrap();
+    assert_eq!(
+        storage::get::<[u8; 6]>("tag-like").await.unwrap(),
+        Some(tag_like)
+    );
+
+    info!("Test passed!");
+
+    exit(ExitCode::SUCCESS);
+}
@@ -0,0 +1,74 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    thread::sync::{EventBits, EventGroup},
+    time::Duration,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+const NETWORK_UP: EventBits = 0b0001;
+const TIME_SYNCED: EventBits = 0b0010;
+const STORAGE_READY: EventBits = 0b0100;
+const SHUTDOWN: EventBits = 0b1000;
+
+static SYSTEM_STATE: EventGroup = EventGroup::new();
+static RUN_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 2);
+
+    assert!(
+        SYSTEM_STATE
+            .wait_any_timeout(STORAGE_READY, false, Duration::from_millis(10))
+            .is_err()
+    );
+
+    // Only wakes up the thread waiting for any of the bits.
+    SYSTEM_STATE.set(NETWORK_UP);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 4);
+    assert_eq!(SYSTEM_STATE.get(), NETWORK_UP);
+
+    // Wakes up the thread waiting for all of the bits, which clears them.
+    SYSTEM_STATE.set(TIME_SYNCED | STORAGE_READY);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 6);
+    assert_eq!(SYSTEM_STATE.get(), STORAGE_READY);
+
+    assert_eq!(
+        SYSTEM_STATE.clear(STORAGE_READY | NETWORK_UP),
+        STORAGE_READY
+    );
+    assert_eq!(SYSTEM_STATE.get(), 0);
+
+    // Wakes up both threads, although each of them clears the bit.
+    SYSTEM_STATE.set(SHUTDOWN);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 9);
+    assert_eq!(SYSTEM_STATE.get(), 0);
+
+    ariel_os::log::info!("Test passed!");
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 0);
+
+    let bits = SYSTEM_STATE.wait_all(NETWORK_UP | TIME_SYNCED, true);
+    assert_eq!(bits, NETWORK_UP | TIME_SYNCED);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 5);
+
+    assert_eq!(SYSTEM_STATE.wait_all(SHUTDOWN, true), SHUTDOWN);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 8);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread2() {
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 1);
+
+    let bits = SYSTEM_STATE.wait_any(NETWORK_UP | TIME_SYNCED, false);
+    assert_eq!(bits, NETWORK_UP);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 3);
+
+    assert_eq!(SYSTEM_STATE.wait_any(SHUTDOWN, true), SHUTDOWN);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 7);
+}
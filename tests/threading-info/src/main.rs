@@ -0,0 +1,55 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, RunqueueId, ThreadId, ThreadState, thread_flags},
+};
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    // Let the other thread run and block.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    // On native, the other thread runs in parallel, so it may not have blocked yet.
+    while !matches!(
+        thread::thread_info(ThreadId::new(1)).unwrap().state,
+        ThreadState::FlagBlocked(_)
+    ) {
+        core::hint::spin_loop();
+    }
+
+    for info in thread::info() {
+        ariel_os::log::info!(
+            "{:?} {:?} prio={} stack={}/{} switches={} cpu={}us",
+            info.thread_id,
+            info.state,
+            usize::from(info.priority),
+            info.stack_peak_usage,
+            info.stack_size,
+            info.context_switches,
+            info.cpu_time.as_micros(),
+        );
+        assert!(info.stack_peak_usage <= info.stack_size);
+    }
+
+    let this = thread::thread_info(ThreadId::new(0)).unwrap();
+    assert_eq!(this.state, ThreadState::Running);
+    assert_eq!(this.priority, RunqueueId::new(1));
+
+    let other = thread::thread_info(ThreadId::new(1)).unwrap();
+    assert!(matches!(other.state, ThreadState::FlagBlocked(_)));
+    assert_eq!(other.priority, RunqueueId::new(2));
+
+    // Idle threads, if any, have the lowest priority.
+    let app_threads = thread::info().filter(|info| info.priority > RunqueueId::new(0));
+    assert_eq!(app_threads.count(), 2);
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+    thread_flags::wait_one(0b10);
+}
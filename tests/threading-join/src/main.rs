@@ -0,0 +1,58 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, sync::Event, tls::LocalSlot},
+    time::Duration,
+};
+use static_cell::ConstStaticCell;
+
+static STACK0: ConstStaticCell<[u8; 2048]> = ConstStaticCell::new([0u8; 2048]);
+static STACK1: ConstStaticCell<[u8; 2048]> = ConstStaticCell::new([0u8; 2048]);
+
+static EVENT: Event = Event::new();
+static SLOT: LocalSlot<u32> = LocalSlot::new();
+
+static INPUT: u32 = 20;
+
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+fn double(input: &'static u32) -> u32 {
+    // Slots are empty in new threads.
+    assert_eq!(SLOT.get(), None);
+    assert_eq!(SLOT.set(*input), None);
+    assert_eq!(SLOT.get(), Some(*input));
+
+    *input * 2
+}
+
+fn wait_for_event() -> bool {
+    EVENT.wait();
+    true
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(SLOT.set(1), None);
+
+    let handle = thread::create_joinable(double, &INPUT, STACK0.take(), 2, None);
+    // The thread has higher priority, so it already returned.
+    assert!(handle.is_finished());
+    assert_eq!(handle.join(), 40);
+
+    // The slot of this thread has not been touched.
+    assert_eq!(SLOT.get(), Some(1));
+
+    let handle = thread::create_joinable_noarg(wait_for_event, STACK1.take(), 2, None);
+    let handle = handle.join_timeout(TIMEOUT).unwrap_err();
+    assert!(!handle.is_finished());
+    EVENT.set();
+    assert!(handle.join());
+
+    assert_eq!(SLOT.take(), Some(1));
+    assert_eq!(SLOT.get(), None);
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
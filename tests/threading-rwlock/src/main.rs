@@ -0,0 +1,104 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    thread::{self, RunqueueId, ThreadId, sync::RwLock, thread_flags},
+    time::Duration,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static RWLOCK: RwLock<usize> = RwLock::new(0);
+static RUN_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+/// Timeout of waits that are expected to succeed while the thread is blocked.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 0);
+
+    let value = RWLOCK.read();
+    // Readers can share access.
+    assert!(RWLOCK.try_read().is_some());
+
+    // The higher priority writer blocks waiting for write access.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    // New readers are blocked while a writer is waiting.
+    assert!(RWLOCK.try_read().is_none());
+    thread_flags::set(ThreadId::new(2), 0b1);
+    // Readers don't inherit priorities.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+    assert_eq!(*value, 0);
+
+    // The writer runs first, then the reader.
+    drop(value);
+    thread_flags::wait_all(0b11);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 5);
+
+    let mut value = RWLOCK.write();
+    // Let the reader block waiting for read access.
+    thread_flags::set(ThreadId::new(2), 0b10);
+    // Inherit prio of the waiting reader.
+    assert_eq!(
+        thread::get_priority(tid),
+        thread::get_priority(ThreadId::new(2)),
+    );
+    *value += 1;
+    drop(value);
+
+    // Return to old prio.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+
+    thread_flags::wait_one(0b100);
+
+    // Access handed to threads that are blocked with a timeout is not reported as a timeout.
+    // The reader blocks first, then the writer; the writer is served first.
+    let value = RWLOCK.write();
+    thread_flags::set(ThreadId::new(2), 0b100);
+    thread_flags::set(ThreadId::new(1), 0b10);
+    drop(value);
+    thread_flags::wait_all(0b1_1000);
+    // Both released their access again.
+    assert!(RWLOCK.try_write().is_some());
+
+    ariel_os::log::info!("Test passed!");
+}
+
+#[ariel_os::thread(autostart, priority = 3)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 1);
+
+    let mut value = RWLOCK.write();
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 3);
+    assert_eq!(*value, 0);
+    *value += 1;
+    drop(value);
+
+    thread_flags::set(ThreadId::new(0), 0b1);
+
+    thread_flags::wait_one(0b10);
+    let mut value = RWLOCK.write_timeout(TIMEOUT).unwrap();
+    *value += 1;
+    drop(value);
+    thread_flags::set(ThreadId::new(0), 0b1000);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread2() {
+    thread_flags::wait_one(0b1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 2);
+
+    assert_eq!(*RWLOCK.read(), 1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 4);
+    thread_flags::set(ThreadId::new(0), 0b10);
+
+    thread_flags::wait_one(0b10);
+    assert_eq!(*RWLOCK.read(), 2);
+    thread_flags::set(ThreadId::new(0), 0b100);
+
+    thread_flags::wait_one(0b100);
+    assert_eq!(*RWLOCK.read_timeout(TIMEOUT).unwrap(), 3);
+    thread_flags::set(ThreadId::new(0), 0b1_0000);
+}
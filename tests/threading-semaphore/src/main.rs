@@ -0,0 +1,76 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    thread::{ThreadId, sync::Semaphore, thread_flags},
+    time::Duration,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static SEMAPHORE: Semaphore = Semaphore::new(2);
+static RUN_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 0);
+
+    SEMAPHORE.acquire(2);
+    assert_eq!(SEMAPHORE.available(), 0);
+    assert!(!SEMAPHORE.try_acquire(1));
+    assert!(
+        SEMAPHORE
+            .acquire_timeout(1, Duration::from_millis(10))
+            .is_err()
+    );
+
+    // The higher priority threads block waiting for two permits and for one permit.
+    thread_flags::set(ThreadId::new(2), 0b1);
+    thread_flags::set(ThreadId::new(1), 0b1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 3);
+
+    // Releasing a permit lets the thread waiting for one permit run, although the highest
+    // priority thread waits for more.
+    SEMAPHORE.release(1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 5);
+    assert_eq!(SEMAPHORE.available(), 1);
+
+    SEMAPHORE.release(1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 7);
+    assert_eq!(SEMAPHORE.available(), 2);
+
+    // Permits handed to a thread that is blocked with a timeout are not reported as a timeout.
+    SEMAPHORE.acquire(2);
+    thread_flags::set(ThreadId::new(1), 0b10);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 9);
+    SEMAPHORE.release(2);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 11);
+    assert_eq!(SEMAPHORE.available(), 2);
+
+    ariel_os::log::info!("Test passed!");
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 2);
+
+    SEMAPHORE.acquire(1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 4);
+    SEMAPHORE.release(1);
+
+    thread_flags::wait_one(0b10);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 8);
+    assert_eq!(SEMAPHORE.acquire_timeout(1, Duration::from_secs(1)), Ok(()));
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 10);
+    SEMAPHORE.release(1);
+}
+
+#[ariel_os::thread(autostart, priority = 3)]
+fn thread2() {
+    thread_flags::wait_one(0b1);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 1);
+
+    SEMAPHORE.acquire(2);
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 6);
+    SEMAPHORE.release(2);
+}
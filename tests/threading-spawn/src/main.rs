@@ -0,0 +1,62 @@
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, SpawnError, THREAD_COUNT, sync::Event},
+};
+
+const STACK_SIZE: usize = 512;
+
+static EVENT: Event = Event::new();
+
+fn square(input: usize) -> usize {
+    input * input
+}
+
+fn wait_for_event() {
+    EVENT.wait();
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    // Spawn more short-lived threads than there are thread slots.
+    for i in 0..2 * THREAD_COUNT {
+        let handle = thread::spawn(square, i, STACK_SIZE, 2, None).unwrap();
+        assert_eq!(handle.join(), i * i);
+    }
+
+    // Detached threads release their slot and stack once they have returned.
+    for _ in 0..2 * THREAD_COUNT {
+        drop(thread::spawn_noarg(|| {}, STACK_SIZE, 2, None).unwrap());
+    }
+
+    // Fill all thread slots.
+    let mut handles = Vec::new();
+    loop {
+        match thread::spawn_noarg(wait_for_event, STACK_SIZE, 2, None) {
+            Ok(handle) => handles.push(handle),
+            Err(err) => {
+                assert_eq!(err, SpawnError::NoFreeSlot);
+                break;
+            }
+        }
+    }
+    assert!(!handles.is_empty());
+
+    EVENT.set();
+    for handle in handles {
+        handle.join();
+    }
+
+    // Slots are available again.
+    let handle = thread::spawn(square, 3, STACK_SIZE, 2, None).unwrap();
+    assert_eq!(handle.join(), 9);
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
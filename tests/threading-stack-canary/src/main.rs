@@ -0,0 +1,53 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, ThreadId, thread_flags},
+};
+
+const ROUNDS: usize = 100;
+
+/// Uses a significant, but not overflowing, part of the stack.
+#[inline(never)]
+fn use_stack() -> u8 {
+    let mut buffer = [0u8; 512];
+    for (byte, value) in buffer.iter_mut().zip((0..=u8::MAX).cycle()) {
+        *byte = value;
+    }
+    core::hint::black_box(&mut buffer);
+    buffer.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+#[ariel_os::thread(autostart)]
+fn thread0() {
+    for _ in 0..ROUNDS {
+        core::hint::black_box(use_stack());
+        // Switch away from this thread, which checks its canary.
+        thread::yield_same();
+        thread_flags::set(ThreadId::new(1), 0b1);
+        thread_flags::wait_one(0b1);
+    }
+
+    for info in thread::info() {
+        ariel_os::log::info!(
+            "{:?} stack={}/{}",
+            info.thread_id,
+            info.stack_peak_usage,
+            info.stack_size,
+        );
+        assert!(info.stack_peak_usage < info.stack_size);
+    }
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
+
+#[ariel_os::thread(autostart)]
+fn thread1() {
+    for _ in 0..ROUNDS {
+        thread_flags::wait_one(0b1);
+        core::hint::black_box(use_stack());
+        thread_flags::set(ThreadId::new(0), 0b1);
+    }
+}
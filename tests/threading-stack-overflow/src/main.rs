@@ -0,0 +1,136 @@
+#![no_main]
+#![no_std]
+
+#[cfg(context = "native")]
+extern crate std;
+
+use core::fmt::Write as _;
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+use static_cell::ConstStaticCell;
+
+/// Memory below the stack of the overflowing thread, so that the overflow does not corrupt
+/// anything else.
+const PADDING: usize = 1024;
+
+static STACK: ConstStaticCell<[u8; PADDING + 2048]> = ConstStaticCell::new([0u8; PADDING + 2048]);
+
+static OVERFLOWING_THREAD: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Recurses until the stack is used up to its lowest address, overwriting the canary.
+#[cfg(not(context = "native"))]
+#[inline(never)]
+fn overflow(lowest: usize) {
+    let mut buffer = [0u8; 64];
+    core::hint::black_box(&mut buffer);
+    if buffer.as_ptr() as usize > lowest {
+        overflow(lowest);
+    }
+    core::hint::black_box(&buffer);
+}
+
+fn overflowing_thread() {
+    let tid = thread::current_tid().unwrap();
+    OVERFLOWING_THREAD.store(usize::from(tid), Ordering::Release);
+
+    let (lowest, _) = thread::current_stack_limits().unwrap();
+    #[cfg(not(context = "native"))]
+    overflow(lowest);
+    // Threads run on host stacks on native, so the canary is overwritten directly instead.
+    #[cfg(context = "native")]
+    // SAFETY: the bottom of the stack memory given to this thread is not used on native.
+    unsafe {
+        core::ptr::write_bytes(lowest as *mut u8, 0, 2 * size_of::<u32>());
+    }
+
+    // Switching away from this thread checks its canary, which panics.
+    thread::park();
+
+    ariel_os::log::info!("stack overflow has not been detected");
+    exit(ExitCode::FAILURE);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    // The panic handler of std is used on native.
+    #[cfg(context = "native")]
+    std::panic::set_hook(std::boxed::Box::new(|info| {
+        check_panic(info.payload_as_str().unwrap_or_default());
+    }));
+
+    let (_, stack) = STACK.take().split_at_mut(PADDING);
+    let tid = thread::create_noarg(overflowing_thread, stack, 2, None);
+
+    // The overflowing thread may run in parallel on multi-core and native, wait until it has
+    // parked.
+    // As it panics while being parked, within the same critical section, it cannot be unparked
+    // if the overflow has been detected.
+    while !thread::unpark(tid) {
+        core::hint::spin_loop();
+    }
+
+    ariel_os::log::info!("overflowing thread parked");
+    exit(ExitCode::FAILURE);
+}
+
+/// Fixed-size buffer to format panic messages into.
+struct Buffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Buffer {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.bytes.get(..self.len).unwrap_or_default()
+    }
+}
+
+impl core::fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(not(context = "native"))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    check_panic(info.message());
+}
+
+/// Exits successfully if `message` is the one of a detected stack overflow.
+fn check_panic(message: impl core::fmt::Display) -> ! {
+    let mut actual = Buffer::new();
+    let mut expected = Buffer::new();
+    let tid = OVERFLOWING_THREAD.load(Ordering::Acquire);
+
+    if write!(actual, "{message}").is_ok()
+        && write!(expected, "stack overflow in thread {tid}").is_ok()
+        && actual.as_bytes() == expected.as_bytes()
+    {
+        ariel_os::log::info!("Test passed!");
+        exit(ExitCode::SUCCESS);
+    } else {
+        ariel_os::log::info!("unexpected panic");
+        exit(ExitCode::FAILURE);
+    }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
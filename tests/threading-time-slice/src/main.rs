@@ -0,0 +1,46 @@
+#![no_main]
+#![no_std]
+
+use portable_atomic::{AtomicUsize, Ordering};
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread,
+    time::Duration,
+};
+
+static COUNTER0: AtomicUsize = AtomicUsize::new(0);
+static COUNTER1: AtomicUsize = AtomicUsize::new(0);
+
+/// Busy-loops without ever yielding or blocking.
+fn spin(counter: &AtomicUsize) -> ! {
+    loop {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    spin(&COUNTER0);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread1() {
+    spin(&COUNTER1);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread2() {
+    // Let the spinning threads run for several time slices.
+    let time_slice = Duration::from_micros(thread::TIME_SLICE_US as u64);
+    thread::sleep(time_slice * 10);
+
+    // Without time slicing, the thread that got to run first would starve the other one.
+    let count0 = COUNTER0.load(Ordering::Relaxed);
+    let count1 = COUNTER1.load(Ordering::Relaxed);
+    ariel_os::log::info!("counts: {}, {}", count0, count1);
+    assert!(count0 > 0 && count1 > 0);
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
@@ -0,0 +1,113 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{
+        self, RunqueueId, ThreadId, TimeoutError,
+        sync::{Channel, Event, Lock, Mutex, Queue, SendTimeoutError},
+        thread_flags,
+    },
+    time::{Duration, Instant},
+};
+
+static CHANNEL: Channel<u8> = Channel::new();
+static QUEUE: Queue<u8, 1> = Queue::new();
+static EVENT: Event = Event::new();
+static LOCK: Lock = Lock::new_locked();
+static MUTEX: Mutex<()> = Mutex::new(());
+
+const TIMEOUT: Duration = Duration::from_millis(10);
+/// Timeout of waits that are expected to succeed while the thread is blocked.
+const LONG_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+
+    assert_eq!(QUEUE.recv_timeout(TIMEOUT), Err(TimeoutError));
+    assert_eq!(QUEUE.send_timeout(1, TIMEOUT), Ok(()));
+    assert_eq!(QUEUE.send_timeout(2, TIMEOUT), Err(SendTimeoutError(2)));
+    assert_eq!(QUEUE.recv_timeout(TIMEOUT), Ok(1));
+
+    assert_eq!(CHANNEL.recv_timeout(TIMEOUT), Err(TimeoutError));
+    assert_eq!(CHANNEL.send_timeout(&1, TIMEOUT), Err(TimeoutError));
+
+    assert_eq!(EVENT.wait_timeout(TIMEOUT), Err(TimeoutError));
+    EVENT.set();
+    assert_eq!(EVENT.wait_timeout(TIMEOUT), Ok(()));
+
+    assert_eq!(LOCK.acquire_timeout(TIMEOUT), Err(TimeoutError));
+    LOCK.release();
+    assert_eq!(LOCK.acquire_timeout(TIMEOUT), Ok(()));
+
+    let deadline = Instant::now() + TIMEOUT;
+    assert_eq!(
+        thread_flags::wait_any_until(0b1, deadline),
+        Err(TimeoutError)
+    );
+    assert!(Instant::now() >= deadline);
+
+    // Timing out must not set any thread flag.
+    let deadline = Instant::now() + TIMEOUT;
+    assert_eq!(
+        thread_flags::wait_any_until(0b10, deadline),
+        Err(TimeoutError)
+    );
+    assert_eq!(
+        thread_flags::wait_all_until(0b11, Instant::now() + TIMEOUT),
+        Err(TimeoutError)
+    );
+    assert_eq!(thread_flags::get(), 0);
+
+    let guard = MUTEX.lock();
+    thread_flags::set(ThreadId::new(1), 0b1);
+    // Inherit prio of the waiting thread.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(2)));
+
+    // Wait for the other thread to give up.
+    thread_flags::wait_any(0b1);
+    // Return to old prio.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+    drop(guard);
+
+    // Hand the mutex, the lock, the event and a value on the channel to the other thread while it is blocked on them,
+    // which must not be reported as a timeout. `LOCK` is still held from above.
+    let guard = MUTEX.lock();
+    EVENT.clear();
+    thread_flags::set(ThreadId::new(1), 0b10);
+    // Unless threads run in parallel, the other thread has the higher priority, so it is blocked
+    // on the mutex by now.
+    drop(guard);
+    LOCK.release();
+    EVENT.set();
+    CHANNEL.send(&3);
+    thread_flags::wait_any(0b10);
+    // The other thread released the mutex and the lock again.
+    assert!(MUTEX.try_lock().is_some());
+    assert!(LOCK.try_acquire());
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+
+    assert!(MUTEX.lock_timeout(TIMEOUT).is_err());
+
+    thread_flags::set(ThreadId::new(0), 0b1);
+
+    thread_flags::wait_one(0b10);
+
+    let guard = MUTEX.lock_timeout(LONG_TIMEOUT);
+    assert!(guard.is_ok());
+    drop(guard);
+    assert_eq!(LOCK.acquire_timeout(LONG_TIMEOUT), Ok(()));
+    LOCK.release();
+    assert_eq!(EVENT.wait_timeout(LONG_TIMEOUT), Ok(()));
+    assert_eq!(CHANNEL.recv_timeout(LONG_TIMEOUT), Ok(3));
+
+    thread_flags::set(ThreadId::new(0), 0b10);
+}
@@ -0,0 +1,64 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{
+        ThreadId,
+        sync::Event,
+        thread_flags,
+        timer::{self, Action},
+    },
+    time::{Duration, Instant},
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static EVENT: Event = Event::new();
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::AcqRel);
+}
+
+#[ariel_os::thread(autostart)]
+fn thread0() {
+    // One-shot timer setting thread flags.
+    let start = Instant::now();
+    let once = timer::start_once(
+        Duration::from_millis(10),
+        Action::Flags(ThreadId::new(0), 0b1),
+    )
+    .unwrap();
+    assert!(timer::is_active(once));
+    assert_eq!(thread_flags::wait_one(0b1), 0b1);
+    assert!(start.elapsed() >= Duration::from_millis(10));
+    assert!(!timer::is_active(once));
+    assert!(!timer::cancel(once));
+
+    // One-shot timer signaling an event.
+    timer::start_once(Duration::from_millis(10), Action::Event(&EVENT)).unwrap();
+    EVENT.wait();
+
+    // Cancelled timers do not fire.
+    let cancelled = timer::start_once(
+        Duration::from_millis(10),
+        Action::Flags(ThreadId::new(0), 0b10),
+    )
+    .unwrap();
+    assert!(timer::cancel(cancelled));
+    assert!(!timer::is_active(cancelled));
+
+    // Periodic timer running a callback in the timer-service thread.
+    let periodic =
+        timer::start_periodic(Duration::from_millis(10), Action::Callback(tick)).unwrap();
+    assert!(
+        thread_flags::wait_any_timeout(0b10, Duration::from_millis(55)).is_err(),
+        "cancelled timer fired"
+    );
+    assert!(timer::cancel(periodic));
+    let ticks = TICKS.load(Ordering::Acquire);
+    assert!((4..=6).contains(&ticks), "unexpected tick count {ticks}");
+
+    ariel_os::log::info!("Test passed!");
+    exit(ExitCode::SUCCESS);
+}